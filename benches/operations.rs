@@ -3,34 +3,132 @@ use cdk::Amount;
 use cdk::amount::SplitTarget;
 use cdk::nuts::CurrencyUnit;
 use cdk::wallet::Wallet;
+use cdk_fake_wallet::create_fake_invoice;
 use cdk_sqlite::wallet::memory;
-use futures::future;
+use clap::{Parser, ValueEnum};
+use futures::{StreamExt, future, stream};
 use hdrhistogram::Histogram;
+use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-const MINT_URL: &str = "http://127.0.0.1:8086";
-const CDK_MINT: bool = true; // true for CDK mint, false for JCMint
+#[path = "../src/latency.rs"]
+mod latency;
 
-fn print_histogram(hist: &Histogram<u64>, label: &str) {
-    println!("--- {} Benchmark Results ---", label);
-    println!("Mean:   {:.2} us", hist.mean());
-    println!("StdDev: {:.2} us", hist.stdev());
-    println!("Min:    {} us", hist.min());
-    println!("Max:    {} us", hist.max());
-    println!("50%:    {} us", hist.value_at_percentile(50.0));
-    println!("90%:    {} us", hist.value_at_percentile(90.0));
-    println!("99%:    {} us", hist.value_at_percentile(99.0));
-    println!("99.9%:  {} us", hist.value_at_percentile(99.9));
-    println!("n={}", hist.len());
-    println!()
+use latency::{Stats, print_stats};
+
+/// Measure mint, swap and melt latency of a mint backed by the signatory
+#[derive(Parser)]
+struct Args {
+    #[arg(long, default_value = "http://127.0.0.1:8086")]
+    mint_url: String,
+    /// Mint and swap operations to measure
+    #[arg(long, default_value_t = 100)]
+    iterations: usize,
+    /// Melt operations to measure, each spends 1 sat plus the fee reserve
+    #[arg(long, default_value_t = 20)]
+    melt_iterations: usize,
+    /// Sats per mint quote
+    #[arg(long, default_value_t = 1)]
+    amount: u64,
+    /// Operations in flight at once
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+    /// Repeat the swap benchmark at each of these concurrency levels, e.g. `1,2,4,8`, to see
+    /// how queueing for the device affects tail latency
+    #[arg(long, value_delimiter = ',')]
+    sweep: Vec<usize>,
+    /// The mint is JCMint, which pays quotes without waiting and no fake invoices
+    #[arg(long)]
+    jcmint: bool,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+fn print_results(results: &[Stats], format: Format) {
+    match format {
+        Format::Text => results.iter().for_each(print_stats),
+        Format::Csv => {
+            println!(
+                "operation,concurrency,n,errors,mean_us,stdev_us,min_us,max_us,p50_us,p90_us,p99_us,p999_us"
+            );
+            for s in results {
+                println!(
+                    "{},{},{},{},{:.2},{:.2},{},{},{},{},{},{}",
+                    s.operation,
+                    s.concurrency,
+                    s.n,
+                    s.errors,
+                    s.mean,
+                    s.stdev,
+                    s.min,
+                    s.max,
+                    s.p50,
+                    s.p90,
+                    s.p99,
+                    s.p999
+                );
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string_pretty(results).unwrap()),
+    }
+}
+
+/// Run `op` on every item with `concurrency` in flight and record the latency of the ones
+/// that succeed
+async fn measure<T, R, E, F, Fut>(
+    label: &'static str,
+    items: Vec<T>,
+    concurrency: usize,
+    op: F,
+) -> Stats
+where
+    E: Debug,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R, E>>,
+{
+    let mut hist: Histogram<u64> = Histogram::new(3).unwrap();
+    let mut errors = 0;
+    let mut results = stream::iter(items)
+        .map(|item| {
+            let fut = op(item);
+            async move {
+                let start = Instant::now();
+                let result = fut.await;
+                (start.elapsed(), result)
+            }
+        })
+        .buffer_unordered(concurrency.max(1));
+    while let Some((elapsed, result)) = results.next().await {
+        match result {
+            Ok(_) => hist.record(elapsed.as_micros() as u64).unwrap(),
+            Err(err) => {
+                eprintln!("{} error: {:?}", label, err);
+                errors += 1;
+            }
+        }
+    }
+    Stats::new(label, concurrency, &hist, errors)
 }
 
 #[tokio::main]
 async fn main() {
+    let args = Args::parse();
+
     // Setup wallet
     let wallet = Wallet::new(
-        MINT_URL,
+        &args.mint_url,
         CurrencyUnit::Sat,
         Arc::new(memory::empty().await.unwrap()),
         Mnemonic::generate(12).unwrap().to_seed_normalized(""),
@@ -40,12 +138,12 @@ async fn main() {
 
     // pre-generate quotes - not measured
     let mut pending_quotes = Vec::new();
-    for _ in 0..100 {
-        let amount = Amount::from(1);
+    for _ in 0..args.iterations {
+        let amount = Amount::from(args.amount);
         let quote = wallet.mint_quote(amount, None).await.unwrap();
         pending_quotes.push(quote);
     }
-    let quotes = if CDK_MINT {
+    let quotes = if !args.jcmint {
         let quote_futures = pending_quotes.into_iter().map(|quote| async {
             wallet
                 .wait_for_payment(&quote, Duration::from_secs(10))
@@ -58,39 +156,59 @@ async fn main() {
         pending_quotes
     };
 
-    // Benchmark mint operation (n=100)
-    let mut hist_mint: Histogram<u64> = Histogram::new(3).unwrap();
-    for quote in quotes {
-        let start = Instant::now();
+    let mut results = Vec::new();
 
-        // Mint
-        let _minted_proofs = wallet
-            .mint(&quote.id, SplitTarget::default(), None)
-            .await
-            .unwrap();
+    // Benchmark mint operation
+    results.push(
+        measure("Mint", quotes, args.concurrency, |quote| {
+            let wallet = &wallet;
+            async move { wallet.mint(&quote.id, SplitTarget::default(), None).await }
+        })
+        .await,
+    );
 
-        let elapsed = start.elapsed();
-        hist_mint.record(elapsed.as_micros() as u64).unwrap();
+    // Benchmark swap operation, one proof per swap. Swapped proofs come back as fresh
+    // proofs of the same amount, so every concurrency level swaps the same number of proofs.
+    let levels = if args.sweep.is_empty() {
+        vec![args.concurrency]
+    } else {
+        args.sweep.clone()
+    };
+    for concurrency in levels {
+        let proofs = wallet.get_unspent_proofs().await.unwrap();
+        let proofs = proofs.into_iter().take(args.iterations).collect();
+        results.push(
+            measure("Swap", proofs, concurrency, |proof| {
+                let wallet = &wallet;
+                async move {
+                    wallet
+                        .swap(None, SplitTarget::None, vec![proof], None, false)
+                        .await
+                }
+            })
+            .await,
+        );
     }
-    print_histogram(&hist_mint, "Mint");
 
-    // Benchmark swap operation (n=100)
-    let proofs = wallet.get_unspent_proofs().await.unwrap();
-    let mut hist_swap: Histogram<u64> = Histogram::new(3).unwrap();
-    for proof in proofs {
-        let start = Instant::now();
-
-        // Swap
-        let new_proofs = wallet
-            .swap(None, SplitTarget::None, vec![proof], None, false)
-            .await;
-
-        if new_proofs.is_err() {
-            eprintln!("Swap error: {:?}", new_proofs.err());
-        } else {
-            let elapsed = start.elapsed();
-            hist_swap.record(elapsed.as_micros() as u64).unwrap();
+    // Benchmark melt operation, needs a CDK mint with the fake wallet backend which pays the
+    // fake invoices created here
+    if args.jcmint {
+        eprintln!("Skipping melt benchmark, fake invoices are only paid by the CDK fake wallet");
+    } else {
+        // quotes not measured, only the melt itself goes through verify_proofs
+        let mut melt_quotes = Vec::new();
+        for _ in 0..args.melt_iterations {
+            let invoice = create_fake_invoice(1000, "bench".to_string());
+            melt_quotes.push(wallet.melt_quote(invoice.to_string(), None).await.unwrap());
         }
+        results.push(
+            measure("Melt", melt_quotes, args.concurrency, |quote| {
+                let wallet = &wallet;
+                async move { wallet.melt(&quote.id).await }
+            })
+            .await,
+        );
     }
-    print_histogram(&hist_swap, "Swap");
+
+    print_results(&results, args.format);
 }
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+/// Protos of the signatory service itself, shipped with the cdk-signatory crate
+const CDK_PROTO_DIR: &str = "../cdk/crates/cdk-signatory/src/proto";
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    println!(
+        "cargo:rustc-env=CDK_SIGNATORY_VERSION={}",
+        locked_version("cdk-signatory")?
+    );
+    // the admin client serves the lock and unlock subcommands
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("descriptor.bin"))
+        .compile_protos(
+            &[
+                "proto/admin.proto",
+                "proto/info.proto",
+                "proto/progress.proto",
+            ],
+            &["proto"],
+        )?;
+    // only the descriptors are used, for gRPC reflection; cdk-signatory provides the code
+    tonic_build::configure()
+        .build_client(false)
+        .build_server(false)
+        .file_descriptor_set_path(out_dir.join("cdk_signatory_descriptor.bin"))
+        .compile_protos(
+            &[format!("{}/signatory.proto", CDK_PROTO_DIR)],
+            &[CDK_PROTO_DIR],
+        )?;
+    Ok(())
+}
+
+/// Version `package` resolved to in Cargo.lock, which cargo writes before running build scripts
+fn locked_version(package: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR")?);
+    // the lock file sits next to the manifest, or at the root of an enclosing workspace
+    let lock = manifest_dir
+        .ancestors()
+        .map(|dir| dir.join("Cargo.lock"))
+        .find(|lock| lock.exists())
+        .ok_or("Cargo.lock not found")?;
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let contents = std::fs::read_to_string(&lock)?;
+    let name = format!("name = \"{}\"", package);
+    let mut lines = contents.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() != name {
+            continue;
+        }
+        if let Some(version) = lines
+            .next()
+            .and_then(|line| line.trim().strip_prefix("version = \""))
+            .and_then(|version| version.strip_suffix('"'))
+        {
+            return Ok(version.to_string());
+        }
+    }
+    Err(format!("{} not found in {}", package, lock.display()).into())
+}
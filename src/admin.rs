@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use cdk_common::nuts::CurrencyUnit;
+use cdk_signatory::signatory::Signatory;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Response, Status};
+
+use crate::config::ServerConfig;
+use crate::provision::{self, DEFAULT_MAX_ORDER};
+use crate::signatory::{TrezorSignatory, expires_in};
+
+pub mod proto {
+    tonic::include_proto!("cdk_signatory_trezor.admin");
+}
+
+use proto::admin_client::AdminClient;
+use proto::admin_server::Admin;
+pub use proto::admin_server::AdminServer;
+
+/// Operator controls for a running signatory
+pub struct AdminService {
+    signatory: Arc<TrezorSignatory>,
+}
+
+impl AdminService {
+    pub fn new(signatory: Arc<TrezorSignatory>) -> Self {
+        Self { signatory }
+    }
+
+    fn signing_state(&self) -> Response<proto::SigningState> {
+        Response::new(proto::SigningState {
+            paused: self.signatory.is_paused(),
+        })
+    }
+
+    fn lock_state(&self) -> Response<proto::LockState> {
+        Response::new(proto::LockState {
+            locked: self.signatory.is_locked(),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    async fn refresh_keysets(
+        &self,
+        _request: Request<proto::RefreshKeysetsRequest>,
+    ) -> Result<Response<proto::RefreshKeysetsResponse>, Status> {
+        let changed = self
+            .signatory
+            .refresh_keysets()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let keyset_ids = self
+            .signatory
+            .cached_keysets
+            .read()
+            .await
+            .iter()
+            .flat_map(|keysets| keysets.keysets.iter().map(|ks| ks.id.to_string()))
+            .collect();
+        tracing::info!("Keysets refreshed through the admin service");
+        Ok(Response::new(proto::RefreshKeysetsResponse {
+            changed,
+            keyset_ids,
+        }))
+    }
+
+    async fn get_device_status(
+        &self,
+        _request: Request<proto::GetDeviceStatusRequest>,
+    ) -> Result<Response<proto::DeviceStatus>, Status> {
+        let mut devices = Vec::new();
+        for device in self.signatory.devices() {
+            let busy = device.is_busy();
+            let health = device.health().await;
+            devices.push(proto::Device {
+                selector: device.selector().to_string(),
+                health: health.as_str().to_string(),
+                busy,
+                session: device.session().get().as_str().to_string(),
+            });
+        }
+        Ok(Response::new(proto::DeviceStatus {
+            devices,
+            paused: self.signatory.is_paused(),
+            locked: self.signatory.is_locked(),
+        }))
+    }
+
+    async fn pause_signing(
+        &self,
+        _request: Request<proto::PauseSigningRequest>,
+    ) -> Result<Response<proto::SigningState>, Status> {
+        self.signatory.pause();
+        Ok(self.signing_state())
+    }
+
+    async fn resume_signing(
+        &self,
+        _request: Request<proto::ResumeSigningRequest>,
+    ) -> Result<Response<proto::SigningState>, Status> {
+        self.signatory.resume();
+        Ok(self.signing_state())
+    }
+
+    async fn lock_devices(
+        &self,
+        _request: Request<proto::LockDevicesRequest>,
+    ) -> Result<Response<proto::LockState>, Status> {
+        self.signatory
+            .lock_devices()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(self.lock_state())
+    }
+
+    async fn unlock_devices(
+        &self,
+        _request: Request<proto::UnlockDevicesRequest>,
+    ) -> Result<Response<proto::LockState>, Status> {
+        self.signatory
+            .unlock_devices()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(self.lock_state())
+    }
+
+    async fn dump_metrics(
+        &self,
+        _request: Request<proto::DumpMetricsRequest>,
+    ) -> Result<Response<proto::Metrics>, Status> {
+        let mut counters: HashMap<String, u64> = self
+            .signatory
+            .metrics
+            .snapshot()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        for (reason, failed) in self.signatory.metrics.failures() {
+            counters.insert(format!("failed_calls{{{}}}", reason), failed);
+        }
+        for (id, stats) in self.signatory.keyset_stats.snapshot() {
+            counters.insert(format!("keyset_signatures{{{}}}", id), stats.signatures);
+            counters.insert(format!("keyset_amount{{{}}}", id), stats.amount);
+        }
+        if let Ok(keysets) = self.signatory.keysets().await {
+            for (id, remaining) in expires_in(&keysets) {
+                counters.insert(format!("keyset_expires_in_secs{{{}}}", id), remaining);
+            }
+        }
+        Ok(Response::new(proto::Metrics { counters }))
+    }
+
+    async fn get_keyset_stats(
+        &self,
+        _request: Request<proto::GetKeysetStatsRequest>,
+    ) -> Result<Response<proto::KeysetStatsList>, Status> {
+        let keysets = self
+            .signatory
+            .keyset_stats
+            .snapshot()
+            .into_iter()
+            .map(|(id, stats)| proto::KeysetStats {
+                keyset_id: id.to_string(),
+                signatures: stats.signatures,
+                amount: stats.amount,
+            })
+            .collect();
+        Ok(Response::new(proto::KeysetStatsList { keysets }))
+    }
+
+    async fn add_keyset(
+        &self,
+        request: Request<proto::AddKeysetRequest>,
+    ) -> Result<Response<proto::AddKeysetResponse>, Status> {
+        let request = request.into_inner();
+        let unit = CurrencyUnit::from_str(&request.unit)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let max_order = match request.max_order {
+            Some(order) => u8::try_from(order)
+                .ok()
+                .filter(|order| (1..=64).contains(order))
+                .ok_or_else(|| Status::invalid_argument("max_order must be between 1 and 64"))?,
+            None => DEFAULT_MAX_ORDER,
+        };
+        let keyset = provision::add_unit(
+            &self.signatory,
+            unit,
+            provision::power_of_two_amounts(max_order),
+            request.input_fee_ppk,
+            request.final_expiry,
+        )
+        .await
+        .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        tracing::info!("Keyset added through the admin service");
+        Ok(Response::new(proto::AddKeysetResponse {
+            keyset_id: keyset.id.to_string(),
+            unit: keyset.unit.to_string(),
+        }))
+    }
+}
+
+/// Lock (`lock`) or unlock the devices of the signatory running with `config` through its
+/// admin service, returning whether they are locked afterwards.
+///
+/// Connects to the first specific listen address, or `127.0.0.1`, over TLS with the client
+/// certificate from `tls_dir` if one is configured.
+pub async fn set_locked(config: &ServerConfig, lock: bool) -> anyhow::Result<bool> {
+    if config.listen_unix.is_some() {
+        anyhow::bail!("lock and unlock connect over TCP, unset listen_unix");
+    }
+    let Some(token_file) = &config.admin_token_file else {
+        anyhow::bail!("lock and unlock need --admin-token-file");
+    };
+    let authorization: MetadataValue<_> =
+        format!("Bearer {}", crate::server::load_token(token_file)?).parse()?;
+    let host = config
+        .listen_addr
+        .iter()
+        .map(|addr| addr.trim_start_matches('[').trim_end_matches(']'))
+        .find(|addr| !addr.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()))
+        .unwrap_or("127.0.0.1");
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, config.listen_port),
+        _ => format!("{}:{}", host, config.listen_port),
+    };
+    let endpoint = match &config.tls_dir {
+        Some(tls_dir) => {
+            let read = |name: &str| {
+                let path = tls_dir.join(name);
+                std::fs::read(&path).with_context(|| format!("reading {}", path.display()))
+            };
+            // gen-certs always includes localhost in the server certificate
+            let domain = match host {
+                "127.0.0.1" => "localhost",
+                host => host,
+            };
+            Endpoint::from_shared(format!("https://{}", authority))?.tls_config(
+                ClientTlsConfig::new()
+                    .domain_name(domain)
+                    .ca_certificate(Certificate::from_pem(read("ca.pem")?))
+                    .identity(Identity::from_pem(read("client.pem")?, read("client.key")?)),
+            )?
+        }
+        None => Endpoint::from_shared(format!("http://{}", authority))?,
+    };
+    let channel = endpoint
+        .connect()
+        .await
+        .with_context(|| format!("connecting to the admin service on {}", authority))?;
+    let mut client = AdminClient::with_interceptor(channel, move |mut req: Request<()>| {
+        req.metadata_mut()
+            .insert("authorization", authorization.clone());
+        Ok(req)
+    });
+    let state = match lock {
+        true => client.lock_devices(proto::LockDevicesRequest {}).await?,
+        false => {
+            client
+                .unlock_devices(proto::UnlockDevicesRequest {})
+                .await?
+        }
+    };
+    Ok(state.into_inner().locked)
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use cdk_common::Error;
+use cdk_common::nuts::BlindedMessage;
+use cdk_signatory::signatory::SignatoryKeysets;
+use serde::{Deserialize, Serialize};
+
+use crate::error::TrezorSignatoryError;
+use crate::policy::batch_totals;
+
+/// Sign-off by a second person, through an external service, before large batches are signed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApprovalConfig {
+    /// POST a summary of each batch above the threshold here and wait for the verdict.
+    /// Unset signs without approval.
+    pub url: Option<String>,
+    /// Batches worth more than this much of a unit need approval, by unit, e.g.
+    /// `{ sat = 100000, usd = 500 }`. Batches with a unit that is not listed always do.
+    pub thresholds: BTreeMap<String, u64>,
+    /// Seconds to wait for the verdict
+    pub timeout_secs: u64,
+    /// File holding the bearer token sent to the approval service
+    pub token_file: Option<PathBuf>,
+    /// Ask for a button press on the device instead of refusing when the approval service
+    /// cannot be reached or does not answer in time
+    pub device_fallback: bool,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            thresholds: BTreeMap::new(),
+            timeout_secs: 300,
+            token_file: None,
+            device_fallback: false,
+        }
+    }
+}
+
+/// Batch as shown to the approver
+#[derive(Debug, Serialize)]
+struct BatchSummary {
+    /// Identifies the request in the approver's and the signatory's logs
+    id: String,
+    /// Sum of the amounts per unit
+    totals: BTreeMap<String, u64>,
+    messages: usize,
+    /// Sum of the amounts per keyset id, in the keyset's unit
+    amounts: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    approved: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Client of the approval service
+pub struct Approver {
+    client: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    thresholds: BTreeMap<String, u64>,
+    device_fallback: bool,
+}
+
+impl Approver {
+    /// Approver for `config`, `None` if no approval service is configured
+    pub fn new(config: &ApprovalConfig) -> Result<Option<Self>> {
+        let Some(url) = &config.url else {
+            return Ok(None);
+        };
+        let token = match &config.token_file {
+            Some(path) => Some(crate::server::load_token(path)?),
+            None => None,
+        };
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+        tracing::info!(
+            "Batches above {} need approval from {}",
+            match config.thresholds.is_empty() {
+                true => "nothing".to_string(),
+                false => describe(&config.thresholds),
+            },
+            url
+        );
+        Ok(Some(Self {
+            client,
+            url: url.clone(),
+            token,
+            thresholds: config.thresholds.clone(),
+            device_fallback: config.device_fallback,
+        }))
+    }
+
+    /// Ask for approval of the batch if it is above the threshold of any of its units.
+    ///
+    /// Returns whether the batch must be confirmed on the device instead, which is the case
+    /// when the approval service is unavailable and the device fallback is enabled.
+    pub async fn approve(
+        &self,
+        messages: &[BlindedMessage],
+        keysets: &SignatoryKeysets,
+    ) -> Result<bool, Error> {
+        let totals = batch_totals(messages, keysets)?;
+        let below = |(unit, total): (&String, &u64)| {
+            self.thresholds
+                .get(unit)
+                .is_some_and(|threshold| total <= threshold)
+        };
+        if totals.iter().all(below) {
+            return Ok(false);
+        }
+        let value = describe(&totals);
+        let mut amounts = BTreeMap::new();
+        for message in messages {
+            let total: &mut u64 = amounts.entry(message.keyset_id.to_string()).or_default();
+            *total = total.saturating_add(u64::from(message.amount));
+        }
+        let summary = BatchSummary {
+            id: uuid::Uuid::new_v4().to_string(),
+            totals,
+            messages: messages.len(),
+            amounts,
+        };
+
+        match self.ask(&summary).await {
+            Ok(Verdict { approved: true, .. }) => {
+                tracing::info!(approval = %summary.id, "Batch of {} approved", value);
+                Ok(false)
+            }
+            Ok(Verdict { reason, .. }) => {
+                tracing::warn!(approval = %summary.id, "Batch of {} rejected", value);
+                Err(TrezorSignatoryError::Policy(format!(
+                    "batch of {} rejected by the approver{}",
+                    value,
+                    reason.map(|r| format!(": {}", r)).unwrap_or_default()
+                ))
+                .into())
+            }
+            Err(err) if self.device_fallback => {
+                tracing::warn!(
+                    approval = %summary.id,
+                    "Approval service unavailable, confirming on the device instead: {}",
+                    err
+                );
+                Ok(true)
+            }
+            Err(err) => Err(TrezorSignatoryError::Unavailable(format!(
+                "approval service unavailable: {}",
+                err
+            ))
+            .into()),
+        }
+    }
+
+    async fn ask(&self, summary: &BatchSummary) -> Result<Verdict, reqwest::Error> {
+        let mut request = self.client.post(&self.url).json(summary);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await?.error_for_status()?.json().await
+    }
+}
+
+/// Amounts per unit like `2100 sat, 5 usd`
+fn describe(amounts: &BTreeMap<String, u64>) -> String {
+    amounts
+        .iter()
+        .map(|(unit, amount)| format!("{} {}", amount, unit))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use cdk_common::PublicKey;
+use cdk_common::bitcoin::bip32::ChildNumber;
+use cdk_common::nuts::{CurrencyUnit, Id};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use trezor_client::protos;
+
+use crate::signatory::{TrezorSignatory, keyset_derivation_path, unix_now};
+
+/// Hardened purpose of the key the attestation is signed with. It is not a BIP-43 purpose
+/// wallets derive addresses under, so the key never holds funds and signing with it reveals
+/// nothing about the wallet on the seed.
+const ATTESTATION_PURPOSE: u32 = 129_372;
+
+#[derive(Serialize)]
+struct Attestation {
+    version: u32,
+    /// Unix timestamp in seconds
+    created_at: u64,
+    device: DeviceInfo,
+    pubkey: PublicKey,
+    keysets: Vec<KeysetInfo>,
+}
+
+#[derive(Serialize)]
+struct DeviceInfo {
+    vendor: String,
+    model: String,
+    firmware_version: String,
+    device_id: String,
+}
+
+#[derive(Serialize)]
+struct KeysetInfo {
+    id: Id,
+    unit: CurrencyUnit,
+    active: bool,
+    input_fee_ppk: u64,
+    final_expiry: Option<u64>,
+    /// Path of the unit, the keysets of the unit are its hardened children
+    unit_derivation_path: Option<String>,
+    keys: BTreeMap<u64, PublicKey>,
+}
+
+#[derive(Serialize)]
+struct AttestationSignature {
+    /// Message signed by the device, naming the SHA-256 of the attestation file
+    message: String,
+    /// Bitcoin address of the signing key
+    address: String,
+    path: String,
+    /// Base64 signature in the format of Bitcoin's `signmessage`
+    signature: String,
+}
+
+/// Write an attestation of the device's keysets to `output` and have the device sign it.
+///
+/// The signature is a Bitcoin signed message over the SHA-256 of the file, made with the key
+/// at `m/129372'/<account>'/0'` of the device's seed, and is written next to it with a `.sig`
+/// extension. Anyone can check it with `bitcoin-cli verifymessage` or a wallet, which ties
+/// the mint keys to the seed on the device. Keysets of other backends are not included.
+pub async fn run(signatory: &TrezorSignatory, output: &Path) -> Result<PathBuf> {
+    let account = signatory.options.account;
+    let device = signatory.pool.primary();
+    let features: protos::Features = signatory
+        .pool
+        .scope(device.call(protos::GetFeatures::new()))
+        .await?;
+    let mut keysets = signatory.fetch_keysets_from(device).await?;
+    keysets
+        .keysets
+        .retain(|ks| signatory.routes.backend(&ks.unit).is_none());
+
+    let attestation = Attestation {
+        version: 1,
+        created_at: unix_now(),
+        device: DeviceInfo {
+            vendor: features.vendor().to_string(),
+            model: features.model().to_string(),
+            firmware_version: format!(
+                "{}.{}.{}",
+                features.major_version(),
+                features.minor_version(),
+                features.patch_version()
+            ),
+            device_id: features.device_id().to_string(),
+        },
+        pubkey: keysets.pubkey,
+        keysets: keysets
+            .keysets
+            .into_iter()
+            .map(|keyset| KeysetInfo {
+                id: keyset.id,
+                unit_derivation_path: unit_derivation_path(&keyset.unit, account),
+                unit: keyset.unit,
+                active: keyset.active,
+                input_fee_ppk: keyset.input_fee_ppk,
+                final_expiry: keyset.final_expiry,
+                keys: keyset
+                    .keys
+                    .iter()
+                    .map(|(amount, key)| (u64::from(*amount), *key))
+                    .collect(),
+            })
+            .collect(),
+    };
+    let contents = serde_json::to_vec_pretty(&attestation)?;
+    std::fs::write(output, &contents).with_context(|| format!("writing {}", output.display()))?;
+
+    let message = format!(
+        "cdk-signatory-trezor attestation sha256:{}",
+        hex::encode(Sha256::digest(&contents))
+    );
+    println!("Confirm the attestation on the device");
+    let signing_path = [
+        ChildNumber::from_hardened_idx(ATTESTATION_PURPOSE)?,
+        ChildNumber::from_hardened_idx(account.unwrap_or(0))?,
+        ChildNumber::from_hardened_idx(0)?,
+    ];
+    let mut req = protos::SignMessage::new();
+    req.address_n = signing_path.iter().copied().map(u32::from).collect();
+    req.set_message(message.clone().into_bytes());
+    let signed: protos::MessageSignature = signatory.pool.scope(device.call(req)).await?;
+
+    let signature = AttestationSignature {
+        message,
+        address: signed.address().to_string(),
+        path: format_path(&signing_path),
+        signature: BASE64.encode(signed.signature()),
+    };
+    let sig_path = output.with_extension("sig");
+    std::fs::write(&sig_path, serde_json::to_vec_pretty(&signature)?)
+        .with_context(|| format!("writing {}", sig_path.display()))?;
+    Ok(sig_path)
+}
+
+/// Derivation path of `unit` in the key tree of `account` without the keyset index, which the
+/// firmware does not report
+fn unit_derivation_path(unit: &CurrencyUnit, account: Option<u32>) -> Option<String> {
+    let path = keyset_derivation_path(unit.clone(), 0, account)?;
+    let children: &[ChildNumber] = path.as_ref();
+    Some(format_path(&children[..children.len().saturating_sub(1)]))
+}
+
+fn format_path(children: &[ChildNumber]) -> String {
+    std::iter::once("m".to_string())
+        .chain(children.iter().map(ChildNumber::to_string))
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,297 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+
+use anyhow::{Context, Result, bail};
+use cdk_common::nuts::Id;
+use cdk_common::{Amount, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::oneshot;
+
+use crate::signatory::unix_now;
+
+/// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One line of the audit log.
+///
+/// `hash` is the SHA-256 of the entry serialized with an empty `hash`, and every entry
+/// stores the hash of the previous one, so editing or dropping a line breaks the chain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+    pub operation: String,
+    pub correlation_id: String,
+    pub keyset_ids: Vec<String>,
+    pub count: usize,
+    pub total_amount: u64,
+    /// `ok` or the error message
+    pub outcome: String,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let unhashed = AuditEntry {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let bytes = serde_json::to_vec(&unhashed).expect("audit entry always serializes");
+        hex::encode(Sha256::digest(&bytes))
+    }
+}
+
+/// Summary of a log that passed verification
+#[derive(Debug)]
+pub struct VerifiedLog {
+    pub entries: u64,
+    pub last_hash: String,
+}
+
+/// Entry to append, completed with its sequence number and hashes by the writer
+struct Pending {
+    entry: AuditEntry,
+    written: oneshot::Sender<Result<(), Error>>,
+}
+
+/// Append-only, hash-chained log of every signing operation.
+///
+/// Entries are written and synced by a dedicated thread, as syncing can stall for a while on a
+/// busy disk. It also assigns the sequence numbers and hashes, so the chain stays intact when
+/// a caller stops waiting for its entry.
+pub struct AuditLog {
+    pending: mpsc::Sender<Pending>,
+}
+
+impl AuditLog {
+    /// Open the log for appending, refusing to continue a chain that fails verification
+    pub fn open(path: &Path) -> Result<Self> {
+        let verified = if path.exists() {
+            verify(path).context("existing audit log failed verification")?
+        } else {
+            VerifiedLog {
+                entries: 0,
+                last_hash: GENESIS_HASH.to_string(),
+            }
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening audit log {}", path.display()))?;
+
+        let (pending, received) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("audit-log".to_string())
+            .spawn(move || write_entries(file, verified, received))
+            .context("starting the audit log writer")?;
+        Ok(Self { pending })
+    }
+
+    /// Append an entry for one signatory call, once it is synced to disk
+    pub async fn record<T>(
+        &self,
+        operation: &str,
+        correlation_id: &str,
+        items: impl IntoIterator<Item = (Id, Amount)>,
+        outcome: &Result<T, Error>,
+    ) -> Result<(), Error> {
+        let mut keyset_ids: Vec<String> = Vec::new();
+        let mut count = 0;
+        let mut total_amount: u64 = 0;
+        for (keyset_id, amount) in items {
+            let keyset_id = keyset_id.to_string();
+            if !keyset_ids.contains(&keyset_id) {
+                keyset_ids.push(keyset_id);
+            }
+            count += 1;
+            total_amount = total_amount.saturating_add(amount.into());
+        }
+
+        let entry = AuditEntry {
+            seq: 0,
+            timestamp: unix_now(),
+            operation: operation.to_string(),
+            correlation_id: correlation_id.to_string(),
+            keyset_ids,
+            count,
+            total_amount,
+            outcome: match outcome {
+                Ok(_) => "ok".to_string(),
+                Err(err) => err.to_string(),
+            },
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        let (written, done) = oneshot::channel();
+        let stopped = || Error::Custom("audit log writer stopped".to_string());
+        self.pending
+            .send(Pending { entry, written })
+            .map_err(|_| stopped())?;
+        done.await.map_err(|_| stopped())?
+    }
+}
+
+/// Chain and append the entries received on `pending` until the log is dropped
+fn write_entries(mut file: File, verified: VerifiedLog, pending: mpsc::Receiver<Pending>) {
+    let mut next_seq = verified.entries;
+    let mut last_hash = verified.last_hash;
+    for Pending { mut entry, written } in pending {
+        entry.seq = next_seq;
+        entry.prev_hash = last_hash.clone();
+        entry.hash = entry.compute_hash();
+        let result = serde_json::to_vec(&entry)
+            .map_err(|e| Error::Custom(e.to_string()))
+            .and_then(|mut line| {
+                line.push(b'\n');
+                file.write_all(&line)
+                    .and_then(|_| file.sync_data())
+                    .map_err(|e| Error::Custom(format!("writing audit log: {}", e)))
+            });
+        if result.is_ok() {
+            next_seq += 1;
+            last_hash = entry.hash;
+        }
+        // the caller may have stopped waiting, the entry is written all the same
+        let _ = written.send(result);
+    }
+}
+
+/// Check sequence numbers and the hash chain of every entry in the log
+pub fn verify(path: &Path) -> Result<VerifiedLog> {
+    let file = File::open(path).with_context(|| format!("opening audit log {}", path.display()))?;
+
+    let mut last_hash = GENESIS_HASH.to_string();
+    let mut entries = 0;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.with_context(|| format!("reading line {}", line_no))?;
+        let entry: AuditEntry = serde_json::from_str(&line)
+            .with_context(|| format!("line {} is not a valid audit entry", line_no))?;
+
+        if entry.seq != entries {
+            bail!(
+                "line {}: expected seq {}, found {}",
+                line_no,
+                entries,
+                entry.seq
+            );
+        }
+        if entry.prev_hash != last_hash {
+            bail!("line {}: chain broken, prev_hash does not match", line_no);
+        }
+        if entry.compute_hash() != entry.hash {
+            bail!("line {}: entry hash mismatch, entry was modified", line_no);
+        }
+
+        last_hash = entry.hash;
+        entries += 1;
+    }
+
+    Ok(VerifiedLog { entries, last_hash })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Path of a fresh log holding `entries` entries
+    async fn write_log(name: &str, entries: usize) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "cdk-signatory-trezor-audit-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let log = AuditLog::open(&path).unwrap();
+        for i in 0..entries {
+            let outcome = match i % 2 {
+                0 => Ok(()),
+                _ => Err(Error::AmountKey),
+            };
+            log.record("blind_sign", &i.to_string(), std::iter::empty(), &outcome)
+                .await
+                .unwrap();
+        }
+        path
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn rewrite(path: &Path, lines: &[String]) {
+        std::fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_intact_chain() {
+        let path = write_log("intact", 3).await;
+        let verified = verify(&path).unwrap();
+        assert_eq!(verified.entries, 3);
+        let last: AuditEntry = serde_json::from_str(&lines(&path)[2]).unwrap();
+        assert_eq!(verified.last_hash, last.hash);
+
+        // reopening continues the chain
+        let log = AuditLog::open(&path).unwrap();
+        log.record(
+            "verify_proofs",
+            "3",
+            std::iter::empty(),
+            &Ok::<(), Error>(()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(verify(&path).unwrap().entries, 4);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_tampered_entry() {
+        let path = write_log("tampered", 3).await;
+        let mut lines = lines(&path);
+        lines[1] = lines[1].replace("\"total_amount\":0", "\"total_amount\":1000");
+        rewrite(&path, &lines);
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2: entry hash mismatch"), "{}", err);
+        assert!(AuditLog::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_deleted_entry() {
+        let path = write_log("deleted", 3).await;
+        let mut lines = lines(&path);
+        lines.remove(1);
+        rewrite(&path, &lines);
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2: expected seq 1, found 2"), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn detects_sequence_gap() {
+        let path = write_log("gap", 2).await;
+        let mut lines = lines(&path);
+        // a consistently rehashed entry with a skipped sequence number
+        let mut entry: AuditEntry = serde_json::from_str(&lines[1]).unwrap();
+        entry.seq = 5;
+        entry.hash = entry.compute_hash();
+        lines[1] = serde_json::to_string(&entry).unwrap();
+        rewrite(&path, &lines);
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2: expected seq 1, found 5"), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
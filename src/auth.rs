@@ -0,0 +1,117 @@
+use sha2::{Digest, Sha256};
+use tonic::{Request, Status};
+
+/// Checks run on every signatory RPC before it reaches the device
+#[derive(Debug, Clone, Default)]
+pub struct ClientAuth {
+    /// SHA-256 fingerprints of the client certificates allowed to call, empty allows any
+    /// certificate signed by the client CA
+    allowed_fingerprints: Vec<String>,
+    /// SHA-256 of the bearer token every call must carry
+    token_hash: Option<[u8; 32]>,
+}
+
+impl ClientAuth {
+    /// Only accept client certificates with one of these SHA-256 fingerprints (hex, colons optional)
+    pub fn with_fingerprints(mut self, fingerprints: &[String]) -> Self {
+        self.allowed_fingerprints = fingerprints
+            .iter()
+            .map(|fp| fp.replace(':', "").to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Require `authorization: Bearer <token>` on every call
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token_hash = Some(Sha256::digest(token.as_bytes()).into());
+        self
+    }
+
+    pub fn check(&self, req: &Request<()>) -> Result<(), Status> {
+        self.check_bearer(
+            req.metadata()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok()),
+        )?;
+        let fingerprint = req
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|leaf| fingerprint(leaf.as_ref())));
+        self.check_fingerprint(fingerprint.as_deref())
+    }
+
+    /// Check the fingerprint of the caller's client certificate against the allowed ones, if
+    /// any are set
+    pub fn check_fingerprint(&self, fingerprint: Option<&str>) -> Result<(), Status> {
+        if self.allowed_fingerprints.is_empty() {
+            return Ok(());
+        }
+        let fingerprint =
+            fingerprint.ok_or_else(|| Status::unauthenticated("client certificate required"))?;
+        if !self.allowed_fingerprints.iter().any(|fp| fp == fingerprint) {
+            tracing::warn!("Rejected client certificate {}", fingerprint);
+            return Err(Status::permission_denied("client certificate not allowed"));
+        }
+        Ok(())
+    }
+
+    /// Check the value of an `authorization` header against the required token, if any
+    pub fn check_bearer(&self, authorization: Option<&str>) -> Result<(), Status> {
+        let Some(expected) = &self.token_hash else {
+            return Ok(());
+        };
+        let token = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("bearer token required"))?;
+        let actual: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+        if !constant_time_eq(&actual, expected) {
+            return Err(Status::unauthenticated("invalid bearer token"));
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 fingerprint of a DER certificate, as hex
+pub fn fingerprint(cert: &[u8]) -> String {
+    hex::encode(Sha256::digest(cert))
+}
+
+/// Compare without an early exit so the time taken does not leak the matching prefix
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    /// `fingerprint` as operators copy it from `openssl x509 -fingerprint`
+    fn with_colons(fingerprint: &str) -> String {
+        fingerprint
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| String::from_utf8_lossy(pair).to_ascii_uppercase())
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    #[test]
+    fn any_certificate_without_fingerprints() {
+        let auth = ClientAuth::default();
+        assert!(auth.check_fingerprint(None).is_ok());
+        assert!(auth.check_fingerprint(Some(&fingerprint(b"cert"))).is_ok());
+    }
+
+    #[test]
+    fn only_allowed_fingerprints() {
+        let allowed = fingerprint(b"allowed");
+        let auth = ClientAuth::default().with_fingerprints(&[with_colons(&allowed)]);
+        assert!(auth.check_fingerprint(Some(&allowed)).is_ok());
+
+        let other = auth.check_fingerprint(Some(&fingerprint(b"other")));
+        assert_eq!(other.unwrap_err().code(), Code::PermissionDenied);
+        let missing = auth.check_fingerprint(None);
+        assert_eq!(missing.unwrap_err().code(), Code::Unauthenticated);
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use cdk_common::Error;
+use trezor_client::{Trezor, TrezorMessage, protos};
+
+use crate::error::TrezorSignatoryError;
+use crate::record::{ExchangeRecorder, ReplayDevice};
+use crate::trezor::{
+    DeviceSelector, DeviceTransport, Interaction, handle_trezor_call, open_device,
+};
+
+/// Encoded protobuf message as it travels over the wire
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    pub message_type: protos::MessageType,
+    pub payload: Vec<u8>,
+}
+
+impl RawMessage {
+    pub fn encode<M: TrezorMessage>(message: &M) -> Result<Self, Error> {
+        let payload = message.write_to_bytes().map_err(|e| {
+            TrezorSignatoryError::Mapping(format!("failed to encode {:?}: {}", M::MESSAGE_TYPE, e))
+        })?;
+        Ok(Self {
+            message_type: M::MESSAGE_TYPE,
+            payload,
+        })
+    }
+
+    pub fn decode<M: TrezorMessage>(&self) -> Result<M, Error> {
+        if self.message_type != M::MESSAGE_TYPE {
+            return Err(TrezorSignatoryError::Mapping(format!(
+                "expected {:?}, got {:?}",
+                M::MESSAGE_TYPE,
+                self.message_type
+            ))
+            .into());
+        }
+        M::parse_from_bytes(&self.payload).map_err(|e| {
+            TrezorSignatoryError::Mapping(format!("failed to decode {:?}: {}", M::MESSAGE_TYPE, e))
+                .into()
+        })
+    }
+}
+
+/// Result of one blocking exchange with the device
+pub enum Exchange<R> {
+    Done(Result<R, Error>),
+    TransportFailed,
+}
+
+/// Device running the Cashu app, as seen by [`crate::device::TrezorDevice`].
+///
+/// Implemented by the real [`Trezor`], by the replay of a recorded session and, with the
+/// `mock-device` feature, by an in-process mock so the signatory can run without hardware.
+pub trait CashuDevice: Send {
+    /// Features reported on the last initialization
+    fn features(&self) -> Option<&protos::Features>;
+
+    /// Resume the session `session_id`, or start a new one if the device no longer knows it,
+    /// aborting any workflow left on the screen
+    fn init_device(&mut self, session_id: Option<&[u8]>) -> Result<(), Error>;
+
+    /// Send `req` and return the final answer, handling interaction requests on the way
+    fn call(&mut self, req: RawMessage, interaction: &Interaction) -> Exchange<RawMessage>;
+
+    fn end_session(&mut self);
+}
+
+impl CashuDevice for Trezor {
+    fn features(&self) -> Option<&protos::Features> {
+        Trezor::features(self)
+    }
+
+    fn init_device(&mut self, session_id: Option<&[u8]>) -> Result<(), Error> {
+        Trezor::init_device(self, session_id.map(<[u8]>::to_vec))
+            .map_err(|e| TrezorSignatoryError::from_client(e).into())
+    }
+
+    fn call(&mut self, req: RawMessage, interaction: &Interaction) -> Exchange<RawMessage> {
+        // trezor-client only sends typed messages, so the payload is decoded again into the
+        // request type, which also fixes the response type
+        macro_rules! dispatch {
+            ($($req:ty => $resp:ty),* $(,)?) => {
+                $(if req.message_type == <$req as TrezorMessage>::MESSAGE_TYPE {
+                    return typed_call::<$req, $resp>(self, &req, interaction);
+                })*
+            };
+        }
+        dispatch!(
+            protos::GetFeatures => protos::Features,
+            protos::LockDevice => protos::Success,
+            protos::SignMessage => protos::MessageSignature,
+            protos::CashuGetInfo => protos::CashuInfo,
+            protos::CashuGetKeysets => protos::CashuGetKeysetsResponse,
+            protos::CashuBlindSign => protos::CashuBlindSignResponse,
+            protos::CashuVerifyProofs => protos::Success,
+            protos::CashuRotateKeyset => protos::CashuRotateKeysetResponse,
+        );
+        Exchange::Done(Err(TrezorSignatoryError::Mapping(format!(
+            "no response type known for {:?}",
+            req.message_type
+        ))
+        .into()))
+    }
+
+    fn end_session(&mut self) {
+        if let Err(err) = self.call_raw(protos::EndSession::new()) {
+            tracing::debug!("Failed to end device session: {:?}", err);
+        }
+    }
+}
+
+fn typed_call<S: TrezorMessage, R: TrezorMessage>(
+    trezor: &mut Trezor,
+    req: &RawMessage,
+    interaction: &Interaction,
+) -> Exchange<RawMessage> {
+    let req: S = match req.decode() {
+        Ok(req) => req,
+        Err(err) => return Exchange::Done(Err(err)),
+    };
+    let result = match trezor.call(req, Box::new(|_, m: R| Ok(m))) {
+        Err(err) if is_transport_error(&err) => {
+            tracing::warn!("Trezor transport error, reconnecting: {:?}", err);
+            return Exchange::TransportFailed;
+        }
+        resp => handle_trezor_call(resp, interaction),
+    };
+    if interaction.cancel.take_prompt_left() {
+        // answering the pending request with Cancel clears the screen and ends the workflow
+        match trezor.call_raw(protos::Cancel::new()) {
+            Ok(_) => {
+                tracing::info!("Cancelled the device prompt of an abandoned call");
+                interaction.set_aborted();
+            }
+            Err(err) if is_transport_error(&err) => return Exchange::TransportFailed,
+            Err(err) => tracing::warn!("Failed to cancel the device prompt: {:?}", err),
+        }
+    }
+    Exchange::Done(result.and_then(|resp: R| RawMessage::encode(&resp)))
+}
+
+/// Errors caused by the USB/UDP link rather than by the device rejecting the request
+fn is_transport_error(err: &trezor_client::Error) -> bool {
+    matches!(
+        err,
+        trezor_client::Error::TransportConnect(_)
+            | trezor_client::Error::TransportBeginConnection(_)
+            | trezor_client::Error::TransportEndConnection(_)
+            | trezor_client::Error::TransportSendMessage(_)
+            | trezor_client::Error::TransportReceiveMessage(_)
+    )
+}
+
+/// Open the device matching `selector`, the replay or the mock depending on the transport,
+/// resuming `session_id` and recording its exchanges if there is a `recorder`
+pub fn open_backend(
+    selector: &DeviceSelector,
+    session_id: Option<&[u8]>,
+    recorder: Option<&Arc<ExchangeRecorder>>,
+) -> Result<Box<dyn CashuDevice>, Error> {
+    let device: Box<dyn CashuDevice> = match &selector.transport {
+        DeviceTransport::Replay(path) => Box::new(ReplayDevice::open(path)?),
+        #[cfg(feature = "mock-device")]
+        DeviceTransport::Mock => {
+            tracing::warn!("Using the MOCK device, its keys come from a public test seed");
+            Box::new(crate::mock::MockDevice::new()?)
+        }
+        _ => Box::new(open_device(selector, session_id)?),
+    };
+    let device = crate::payload_trace::wrap(selector.to_string(), device);
+    Ok(match recorder {
+        Some(recorder) => recorder.wrap(selector.to_string(), device),
+        None => device,
+    })
+}
@@ -0,0 +1,63 @@
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use cdk_common::dhke::{blind_message, construct_proofs};
+use cdk_common::nuts::{BlindedMessage, CurrencyUnit};
+use cdk_common::secret::Secret;
+use cdk_signatory::signatory::Signatory;
+use hdrhistogram::Histogram;
+
+use crate::latency::{Stats, print_stats};
+use crate::signatory::TrezorSignatory;
+
+/// Sign `iterations` batches of `batch_size` random blinded messages on the attached devices,
+/// then verify the resulting proofs, and print the latency of every call.
+///
+/// Uses the smallest amount of the first active sat keyset. The signatures are real, so they
+/// count towards volume limits and show up in the audit log.
+pub async fn run(signatory: &TrezorSignatory, iterations: usize, batch_size: usize) -> Result<()> {
+    let keysets = signatory.keysets().await?;
+    let keyset = keysets
+        .keysets
+        .iter()
+        .find(|ks| ks.active && ks.unit == CurrencyUnit::Sat)
+        .or_else(|| keysets.keysets.iter().find(|ks| ks.active))
+        .context("the device serves no active keyset")?;
+    let amount = *keyset
+        .amounts
+        .iter()
+        .min()
+        .context("the keyset has no amounts")?;
+    println!(
+        "Benchmarking {} x {} messages of {} {} on keyset {}",
+        iterations, batch_size, amount, keyset.unit, keyset.id
+    );
+
+    let mut hist_sign: Histogram<u64> = Histogram::new(3)?;
+    let mut hist_verify: Histogram<u64> = Histogram::new(3)?;
+    for _ in 0..iterations {
+        let mut messages = Vec::with_capacity(batch_size);
+        let mut secrets = Vec::with_capacity(batch_size);
+        let mut rs = Vec::with_capacity(batch_size);
+        for _ in 0..batch_size {
+            let secret = Secret::generate();
+            let (blinded, r) = blind_message(secret.as_bytes(), None)?;
+            messages.push(BlindedMessage::new(amount.into(), keyset.id, blinded));
+            secrets.push(secret);
+            rs.push(r);
+        }
+
+        let start = Instant::now();
+        let signatures = signatory.blind_sign(messages).await?;
+        hist_sign.record(start.elapsed().as_micros() as u64)?;
+
+        let proofs = construct_proofs(signatures, rs, secrets, &keyset.keys)?;
+        let start = Instant::now();
+        signatory.verify_proofs(proofs).await?;
+        hist_verify.record(start.elapsed().as_micros() as u64)?;
+    }
+
+    print_stats(&Stats::new("blind_sign", 1, &hist_sign, 0));
+    print_stats(&Stats::new("verify_proofs", 1, &hist_verify, 0));
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use anyhow::{Result, bail};
+use trezor_client::protos;
+use trezor_client::protos::features::Capability;
+
+use crate::device::TrezorDevice;
+use crate::signatory::SignatoryOptions;
+
+/// NUTs every firmware with the Cashu app implements: blind signing and proof verification
+const BASE_NUTS: [u32; 3] = [0, 1, 2];
+/// DLEQ proofs on blind signatures
+const NUT_DLEQ: u32 = 12;
+
+/// What a device's firmware can do for the signatory
+#[derive(Debug, Clone)]
+pub struct DeviceCapabilities {
+    pub firmware: String,
+    /// The Cashu app is present
+    pub cashu: bool,
+    /// NUTs reported by `CashuGetInfo`, `None` on firmware without it
+    pub nuts: Option<Vec<u32>>,
+    /// Largest batch of blinded messages or proofs the firmware accepts in one message
+    pub max_batch_size: Option<u32>,
+}
+
+impl DeviceCapabilities {
+    /// Query the device features and the Cashu app info
+    pub async fn discover(device: &TrezorDevice) -> Result<Self> {
+        let features: protos::Features = device.call(protos::GetFeatures::new()).await?;
+        let cashu = features
+            .capabilities
+            .iter()
+            .any(|c| c.enum_value() == Ok(Capability::Capability_Cashu));
+        let firmware = format!(
+            "{}.{}.{}",
+            features.major_version(),
+            features.minor_version(),
+            features.patch_version()
+        );
+        if !cashu {
+            return Ok(Self {
+                firmware,
+                cashu,
+                nuts: None,
+                max_batch_size: None,
+            });
+        }
+
+        // older Cashu firmware rejects the message, it implements the base NUTs only
+        let (nuts, max_batch_size) = match device
+            .call::<_, protos::CashuInfo>(protos::CashuGetInfo::new())
+            .await
+        {
+            Ok(info) => (
+                Some(info.supported_nuts.clone()),
+                info.max_batch_size.filter(|size| *size > 0),
+            ),
+            Err(err) => {
+                tracing::debug!("CashuGetInfo failed, assuming base NUTs: {}", err);
+                (None, None)
+            }
+        };
+        Ok(Self {
+            firmware,
+            cashu,
+            nuts,
+            max_batch_size,
+        })
+    }
+
+    pub fn supports_nut(&self, nut: u32) -> bool {
+        match &self.nuts {
+            Some(nuts) => nuts.contains(&nut),
+            None => BASE_NUTS.contains(&nut),
+        }
+    }
+
+    /// Refuse to start if the firmware cannot serve the configured options, and warn about
+    /// features the signatory has to do without
+    pub fn check(&self, selector: &str, options: &SignatoryOptions) -> Result<()> {
+        if !self.cashu {
+            bail!(
+                "Trezor ({}) runs firmware {} without the Cashu app, install firmware with Cashu support",
+                selector,
+                self.firmware
+            );
+        }
+        match &self.nuts {
+            Some(nuts) => tracing::info!(
+                "Trezor ({}) firmware {} implements NUTs {:?}",
+                selector,
+                self.firmware,
+                nuts
+            ),
+            None => tracing::warn!(
+                "Trezor ({}) firmware {} does not report its NUTs, assuming {:?}",
+                selector,
+                self.firmware,
+                BASE_NUTS
+            ),
+        }
+        if options.verify_dleq && !self.supports_nut(NUT_DLEQ) {
+            bail!(
+                "verify_dleq is set but Trezor ({}) firmware {} does not return DLEQ proofs (NUT-{})",
+                selector,
+                self.firmware,
+                NUT_DLEQ
+            );
+        }
+        Ok(())
+    }
+
+    /// Largest batch both the configuration and the firmware allow
+    pub fn limit_batch_size(&self, selector: &str, configured: usize) -> usize {
+        match self.max_batch_size {
+            Some(max) if (max as usize) < configured => {
+                tracing::warn!(
+                    "Trezor ({}) firmware {} accepts at most {} messages per batch, lowering max_batch_size from {}",
+                    selector,
+                    self.firmware,
+                    max,
+                    configured
+                );
+                max as usize
+            }
+            _ => configured,
+        }
+    }
+}
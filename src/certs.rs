@@ -0,0 +1,106 @@
+use std::fs::{OpenOptions, Permissions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    KeyUsagePurpose,
+};
+
+/// Files written by [`generate`], named like the ones from `generate_certs.sh`
+const FILES: [&str; 6] = [
+    "ca.pem",
+    "ca.key",
+    "server.pem",
+    "server.key",
+    "client.pem",
+    "client.key",
+];
+
+/// Create a CA and a server and client certificate signed by it in `tls_dir`.
+///
+/// The server certificate is valid for `names`, IP addresses become IP SANs and anything
+/// else a DNS SAN. Existing files are only replaced with `force`.
+pub fn generate(tls_dir: &Path, names: &[String], force: bool) -> Result<()> {
+    if !force {
+        if let Some(existing) = FILES.iter().find(|file| tls_dir.join(file).exists()) {
+            anyhow::bail!(
+                "{} already exists, pass --force to replace the certificates",
+                tls_dir.join(existing).display()
+            );
+        }
+    }
+    std::fs::create_dir_all(tls_dir).with_context(|| format!("creating {}", tls_dir.display()))?;
+
+    let ca_key = KeyPair::generate()?;
+    let mut ca_params = CertificateParams::new(Vec::<String>::new())?;
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "cdk-signatory-trezor CA");
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca = ca_params.self_signed(&ca_key)?;
+
+    let server_key = KeyPair::generate()?;
+    let mut server_params = CertificateParams::new(names.to_vec())?;
+    server_params
+        .distinguished_name
+        .push(DnType::CommonName, "cdk-signatory-trezor");
+    server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    let server = server_params.signed_by(&server_key, &ca, &ca_key)?;
+
+    let client_key = KeyPair::generate()?;
+    let mut client_params = CertificateParams::new(Vec::<String>::new())?;
+    client_params
+        .distinguished_name
+        .push(DnType::CommonName, "cdk-mint");
+    client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client = client_params.signed_by(&client_key, &ca, &ca_key)?;
+
+    write(tls_dir, "ca.pem", &ca.pem(), 0o644)?;
+    write(tls_dir, "ca.key", &ca_key.serialize_pem(), 0o600)?;
+    write(tls_dir, "server.pem", &server.pem(), 0o644)?;
+    write(tls_dir, "server.key", &server_key.serialize_pem(), 0o600)?;
+    write(tls_dir, "client.pem", &client.pem(), 0o644)?;
+    write(tls_dir, "client.key", &client_key.serialize_pem(), 0o600)?;
+    Ok(())
+}
+
+/// Names the server certificate must be valid for when listening on `listen_addrs`.
+///
+/// Unspecified addresses (`0.0.0.0`, `::`) are skipped, clients connect to one of the host's
+/// addresses instead, which can be added with `extra`.
+pub fn server_names(listen_addrs: &[String], extra: &[String]) -> Vec<String> {
+    let mut names = vec!["localhost".to_string()];
+    for addr in listen_addrs.iter().chain(extra) {
+        let name = addr
+            .strip_prefix('[')
+            .and_then(|rest| rest.strip_suffix(']'))
+            .unwrap_or(addr);
+        if name.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+            continue;
+        }
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+fn write(dir: &Path, name: &str, contents: &str, mode: u32) -> Result<()> {
+    let path = dir.join(name);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(&path)
+        .with_context(|| format!("writing {}", path.display()))?;
+    // mode only applies to new files, tighten replaced ones as well
+    file.set_permissions(Permissions::from_mode(mode))
+        .and_then(|_| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("writing {}", path.display()))
+}
@@ -0,0 +1,326 @@
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+
+use crate::approval::ApprovalConfig;
+use crate::http::HttpConfig;
+use crate::lanes::LaneConfig;
+use crate::notify::NotifyConfig;
+use crate::payload_trace::Redaction;
+use crate::policy::SigningPolicy;
+use crate::quota::ClientQuotaConfig;
+use crate::response_cache::ResponseCacheConfig;
+use crate::rotation::RotationConfig;
+use crate::routing::RoutingConfig;
+use crate::signatory::{SignatoryOptions, VolumeLimits};
+use crate::software::AuthSignerConfig;
+use crate::standby::StandbyConfig;
+use crate::trezor::{DeviceSelector, DeviceTransport, RetryPolicy};
+
+/// Signatory configuration, loaded from a TOML file and overridden by CLI flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub device: DeviceConfig,
+    pub logging: LoggingConfig,
+    pub signing: SignatoryOptions,
+    pub policy: SigningPolicy,
+    pub limits: VolumeLimits,
+    pub client_quotas: ClientQuotaConfig,
+    pub audit: AuditConfig,
+    pub metrics: MetricsConfig,
+    pub response_cache: ResponseCacheConfig,
+    pub notify: NotifyConfig,
+    pub rotation: RotationConfig,
+    pub auth_signer: AuthSignerConfig,
+    pub routing: RoutingConfig,
+    pub http: HttpConfig,
+    pub approval: ApprovalConfig,
+    pub standby: StandbyConfig,
+    /// Signatories served by this process, each from its own config file. When set, only
+    /// `[logging]` is read from this file.
+    pub tenants: Vec<TenantConfig>,
+}
+
+/// One of several signatories served by one process, e.g. for several mints on one machine
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    /// Name used in logs
+    pub name: String,
+    /// Config file of the tenant's signatory, with its own listeners, devices and policy
+    pub config: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to listen on, all with `listen_port`
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen_addr: Vec<String>,
+    pub listen_port: u32,
+    pub tls_dir: Option<PathBuf>,
+    /// CA that client certificates must be signed by, defaults to `ca.pem` in `tls_dir`
+    pub client_ca: Option<PathBuf>,
+    /// SHA-256 fingerprints of the only client certificates allowed to call the signatory
+    pub allowed_client_fingerprints: Vec<String>,
+    /// File holding a bearer token every signatory call must present
+    pub auth_token_file: Option<PathBuf>,
+    /// File holding the bearer token of the admin service, which is only served when set
+    pub admin_token_file: Option<PathBuf>,
+    /// Serve on this unix socket instead of TCP
+    pub listen_unix: Option<PathBuf>,
+    /// Permissions of the unix socket file, e.g. `0o660`
+    pub unix_socket_mode: Option<u32>,
+    /// Seconds to wait for in-flight requests on shutdown
+    pub shutdown_timeout: u64,
+    /// Maximum signatory calls queued for the devices, further calls are rejected, 0 is unlimited
+    pub max_queue_depth: usize,
+    /// Maximum calls queued from one client, by certificate or else address, 0 is unlimited
+    pub max_calls_per_client: usize,
+    /// Seconds rejected clients are told to wait before retrying
+    pub retry_after: u64,
+    /// Serve gRPC server reflection, so tools like grpcurl work without the protos
+    pub reflection: bool,
+    /// File holding the hex secp256k1 secret key signatory responses are signed with
+    pub response_signing_key_file: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: vec!["127.0.0.1".to_string()],
+            listen_port: 15060,
+            tls_dir: None,
+            client_ca: None,
+            allowed_client_fingerprints: Vec::new(),
+            auth_token_file: None,
+            admin_token_file: None,
+            listen_unix: None,
+            unix_socket_mode: None,
+            shutdown_timeout: 30,
+            max_queue_depth: 64,
+            max_calls_per_client: 0,
+            retry_after: 1,
+            reflection: false,
+            response_signing_key_file: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Socket addresses the TCP listeners are bound to.
+    ///
+    /// Each address is an IPv4 or IPv6 literal, with or without brackets (`::1`, `[::1]`), or
+    /// a hostname, which is bound on every address it resolves to.
+    pub fn listen_addrs(&self) -> Result<Vec<SocketAddr>> {
+        if self.listen_addr.is_empty() {
+            anyhow::bail!("at least one listen address is required");
+        }
+        let port = u16::try_from(self.listen_port)
+            .with_context(|| format!("invalid listen port {}", self.listen_port))?;
+        let mut addrs = Vec::new();
+        for addr in &self.listen_addr {
+            for resolved in resolve_listen_addr(addr, port)? {
+                if !addrs.contains(&resolved) {
+                    addrs.push(resolved);
+                }
+            }
+        }
+        Ok(addrs)
+    }
+}
+
+fn resolve_listen_addr(addr: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    let host = addr
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(addr);
+    if let Ok(ip) = IpAddr::from_str(host) {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    if host != addr || host.contains(':') {
+        anyhow::bail!(
+            "invalid listen address {:?}, give the port with --listen-port instead",
+            addr
+        );
+    }
+    let resolved: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .with_context(|| format!("resolving listen address {:?}", addr))?
+        .collect();
+    if resolved.is_empty() {
+        anyhow::bail!("listen address {:?} did not resolve to any address", addr);
+    }
+    Ok(resolved)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// `auto`, `usb`, `udp` or `udp:<host>:<port>` for the emulator, or `replay:<file>`
+    pub transport: DeviceTransport,
+    /// Serials of the devices to use, empty for the single connected device
+    pub serial: Vec<String>,
+    pub label: Option<String>,
+    /// Interval in seconds between keyset refreshes from the device, 0 disables
+    pub keyset_refresh_interval: u64,
+    /// Seconds after which a device call is aborted, 0 disables
+    pub call_timeout: u64,
+    /// Seconds of inactivity after which the device is pinged, 0 disables
+    pub keepalive_interval: u64,
+    /// Attempts per device call when the transport fails, including the first one
+    pub retry_attempts: u32,
+    /// Milliseconds before the first retry, doubled for each further retry
+    pub retry_initial_backoff_ms: u64,
+    /// Upper bound of the pause between retries in milliseconds
+    pub retry_max_backoff_ms: u64,
+    /// Persist keysets here so they can be served before the device answers after a restart
+    pub keyset_cache_file: Option<PathBuf>,
+    /// BIP-39 mnemonic of the device, used to sign in software while no device is reachable
+    pub fallback_seed_file: Option<PathBuf>,
+    /// Mint whose advertised keysets must be served by the device, checked on start
+    pub mint_url: Option<String>,
+    /// Append every message exchanged with the devices to this file, for replay
+    pub record_file: Option<PathBuf>,
+    /// Act on USB hotplug events instead of noticing unplugged devices on the next call
+    pub usb_hotplug: bool,
+    /// verify_proofs calls of at most this many proofs go ahead of other calls waiting for
+    /// the device, 0 serves calls in arrival order
+    pub priority_verify_max_proofs: usize,
+    /// Priority calls served in a row while other calls wait, before one of those goes first
+    pub priority_max_streak: u32,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            transport: DeviceTransport::Auto,
+            serial: Vec::new(),
+            label: None,
+            keyset_refresh_interval: 300,
+            call_timeout: 60,
+            keepalive_interval: 0,
+            retry_attempts: 3,
+            retry_initial_backoff_ms: 100,
+            retry_max_backoff_ms: 2000,
+            keyset_cache_file: None,
+            fallback_seed_file: None,
+            mint_url: None,
+            record_file: None,
+            usb_hotplug: true,
+            priority_verify_max_proofs: 0,
+            priority_max_streak: 4,
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn call_timeout(&self) -> Option<Duration> {
+        (self.call_timeout > 0).then(|| Duration::from_secs(self.call_timeout))
+    }
+
+    pub fn priority_lanes(&self) -> Option<LaneConfig> {
+        (self.priority_verify_max_proofs > 0).then_some(LaneConfig {
+            max_priority_proofs: self.priority_verify_max_proofs,
+            max_streak: self.priority_max_streak.max(1),
+        })
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        (self.keepalive_interval > 0).then(|| Duration::from_secs(self.keepalive_interval))
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.retry_attempts,
+            initial_backoff: Duration::from_millis(self.retry_initial_backoff_ms),
+            max_backoff: Duration::from_millis(self.retry_max_backoff_ms),
+        }
+    }
+
+    /// One selector per configured device
+    pub fn selectors(&self) -> Vec<DeviceSelector> {
+        if self.serial.is_empty() {
+            return vec![DeviceSelector {
+                transport: self.transport.clone(),
+                serial: None,
+                label: self.label.clone(),
+            }];
+        }
+        self.serial
+            .iter()
+            .map(|serial| DeviceSelector {
+                transport: self.transport.clone(),
+                serial: Some(serial.clone()),
+                label: self.label.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `tracing` filter directives, e.g. `info,cdk_signatory_trezor=debug`
+    pub filter: Option<String>,
+    pub format: LogFormat,
+    /// OTLP/gRPC collector spans are exported to, e.g. `http://127.0.0.1:4317`
+    pub otlp_endpoint: Option<String>,
+    /// Log the hex payload of every message exchanged with the devices
+    pub trace_payloads: bool,
+    /// Fields blanked out of traced payloads
+    pub payload_redaction: Redaction,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Append a hash-chained record of every signing operation to this file
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Keep the per-keyset signing statistics in this file across restarts
+    pub keyset_stats_file: Option<PathBuf>,
+}
+
+/// Output format of log lines
+#[derive(Debug, Clone, Copy, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with span fields such as `correlation_id` and `batch_size`
+    Json,
+}
+
+/// Accept a single string as well as a list, so `listen_addr = "127.0.0.1"` keeps working
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    })
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("parsing config {}", path.display()))
+    }
+}
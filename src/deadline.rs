@@ -0,0 +1,102 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use tonic::codegen::http::Request;
+
+tokio::task_local! {
+    /// When the caller of the gRPC call being served stops waiting for it
+    static DEADLINE: Instant;
+}
+
+/// Run `fut` under `deadline`, if there is one
+pub async fn scope<F: Future>(deadline: Option<Instant>, fut: F) -> F::Output {
+    match deadline {
+        Some(deadline) => DEADLINE.scope(deadline, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Time left until the deadline of the call being served, `None` without a deadline
+pub fn remaining() -> Option<Duration> {
+    DEADLINE
+        .try_with(|deadline| deadline.saturating_duration_since(Instant::now()))
+        .ok()
+}
+
+/// Deadline the client set with the `grpc-timeout` header, counted from now
+pub fn from_request<B>(req: &Request<B>) -> Option<Instant> {
+    let value = req.headers().get("grpc-timeout")?.to_str().ok()?;
+    match parse_grpc_timeout(value) {
+        Some(timeout) => Some(Instant::now() + timeout),
+        None => {
+            tracing::debug!("Ignoring invalid grpc-timeout {:?}", value);
+            None
+        }
+    }
+}
+
+/// Parse a `grpc-timeout` value, up to 8 digits followed by the unit: `H`ours, `M`inutes,
+/// `S`econds, `m`illiseconds, `u`microseconds or `n`anoseconds
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let split = value.len().checked_sub(1)?;
+    let (digits, unit) = value.split_at_checked(split)?;
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount * 3600),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_grpc_timeout_units() {
+        let cases = [
+            ("2H", Duration::from_secs(7200)),
+            ("3M", Duration::from_secs(180)),
+            ("10S", Duration::from_secs(10)),
+            ("250m", Duration::from_millis(250)),
+            ("99999999u", Duration::from_micros(99_999_999)),
+            ("0n", Duration::ZERO),
+        ];
+        for (value, timeout) in cases {
+            assert_eq!(parse_grpc_timeout(value), Some(timeout), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn parse_grpc_timeout_rejects_invalid_values() {
+        for value in [
+            "",
+            "S",
+            "10",
+            "10s",
+            "123456789S",
+            "-1S",
+            "1.5S",
+            "10 S",
+            "1€",
+        ] {
+            assert_eq!(parse_grpc_timeout(value), None, "{:?}", value);
+        }
+    }
+
+    #[tokio::test]
+    async fn remaining_time_of_the_call() {
+        assert_eq!(remaining(), None);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let left = scope(Some(deadline), async { remaining() }).await.unwrap();
+        assert!(left > Duration::from_secs(9) && left <= Duration::from_secs(10));
+        assert_eq!(scope(None, async { remaining() }).await, None);
+    }
+}
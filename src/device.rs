@@ -0,0 +1,675 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use cdk_common::Error;
+use tokio::sync::{Mutex, OwnedMutexGuard, watch};
+use trezor_client::{TrezorMessage, protos};
+
+use crate::backend::{CashuDevice, Exchange, RawMessage, open_backend};
+use crate::error::TrezorSignatoryError;
+use crate::lanes::{DeviceLanes, LaneConfig};
+use crate::record::ExchangeRecorder;
+use crate::trezor::{CallCancel, DeviceSelector, DeviceTransport, Interaction, RetryPolicy};
+
+/// How many times to try re-opening the device after a transport failure
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// Pause between reconnect attempts, gives the OS time to re-enumerate the USB device
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+/// Where the session with a device stands.
+///
+/// A prompt moves the session out of `Ready` when the device raises it, and the device's
+/// final answer to the call moves it back, so a call in progress shows what the device waits
+/// for.
+///
+/// ```text
+/// Uninitialized -> Ready <-> AwaitingConfirmation
+///                  Ready <-> Locked <- AwaitingConfirmation
+///    any state  -> Disconnected -> Ready (reconnect)
+///    any state  -> Uninitialized (timed out call or closed session) -> Ready
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// No session yet, or the last one was abandoned and is re-initialized on the next call
+    Uninitialized,
+    /// Connected and unlocked
+    Ready,
+    /// A confirmation screen is waiting for the user
+    AwaitingConfirmation,
+    /// Waiting for PIN or passphrase entry
+    Locked,
+    /// Not reachable over the transport
+    Disconnected,
+}
+
+impl SessionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uninitialized => "uninitialized",
+            Self::Ready => "ready",
+            Self::AwaitingConfirmation => "awaiting_confirmation",
+            Self::Locked => "locked",
+            Self::Disconnected => "disconnected",
+        }
+    }
+
+    /// Health implied by the session state the last call or probe left behind
+    pub fn of_session(state: SessionState) -> Self {
+        match state {
+            SessionState::Locked => Self::Locked,
+            SessionState::Disconnected => Self::Disconnected,
+            SessionState::Uninitialized
+            | SessionState::Ready
+            | SessionState::AwaitingConfirmation => Self::Ready,
+        }
+    }
+
+    /// Best of `healths`, as a pool can sign as long as one of its devices is ready
+    pub fn best(healths: impl IntoIterator<Item = Self>) -> Self {
+        let mut best = Self::Disconnected;
+        for health in healths {
+            match health {
+                Self::Ready => return Self::Ready,
+                Self::Locked => best = Self::Locked,
+                Self::Disconnected => {}
+            }
+        }
+        best
+    }
+
+    /// Worst of `healths`, as units served by a pool cannot be signed without it
+    pub fn worst(healths: impl IntoIterator<Item = Self>) -> Self {
+        let mut worst = Self::Ready;
+        for health in healths {
+            match health {
+                Self::Disconnected => return Self::Disconnected,
+                Self::Locked => worst = Self::Locked,
+                Self::Ready => {}
+            }
+        }
+        worst
+    }
+}
+
+/// Current [`SessionState`] of one device, updated as calls progress
+#[derive(Debug)]
+pub struct DeviceSession {
+    state: watch::Sender<SessionState>,
+}
+
+impl DeviceSession {
+    fn new(state: SessionState) -> Self {
+        Self {
+            state: watch::channel(state).0,
+        }
+    }
+
+    pub fn get(&self) -> SessionState {
+        *self.state.borrow()
+    }
+
+    pub fn set(&self, state: SessionState) {
+        let previous = self.state.send_replace(state);
+        if previous != state {
+            tracing::debug!("Device session {} -> {}", previous.as_str(), state.as_str());
+        }
+    }
+
+    /// Notified on every state change
+    pub fn subscribe(&self) -> watch::Receiver<SessionState> {
+        self.state.subscribe()
+    }
+}
+
+/// Coarse device state as seen from the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceHealth {
+    /// Connected and unlocked
+    Ready,
+    /// Connected but waiting for PIN or passphrase entry
+    Locked,
+    /// Not reachable over the transport
+    Disconnected,
+}
+
+impl DeviceHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Locked => "locked",
+            Self::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Connection to a Trezor that reconnects transparently after transport failures.
+///
+/// The device is looked up again with the same [`DeviceSelector`] so a replugged device
+/// (or a device on a different USB port) is picked up without restarting the process.
+pub struct TrezorDevice {
+    selector: DeviceSelector,
+    interaction: Interaction,
+    trezor: Arc<Mutex<Option<Box<dyn CashuDevice>>>>,
+    session: Arc<DeviceSession>,
+    /// Abort calls that take longer than this, e.g. a confirmation nobody answers
+    call_timeout: Option<Duration>,
+    retry: RetryPolicy,
+    /// Records the exchanges of this device, including after reconnects
+    recorder: Option<Arc<ExchangeRecorder>>,
+    /// Set when a call timed out and the device may still be in the middle of its workflow
+    needs_reset: Arc<AtomicBool>,
+    /// Session to resume after a reset or reconnect, so an entered passphrase stays unlocked
+    session_id: Arc<std::sync::Mutex<Option<Vec<u8>>>>,
+    /// Firmware version seen on the last (re)connect
+    firmware: std::sync::Mutex<String>,
+    /// When the last call finished or started, for keepalive pings
+    last_used: std::sync::Mutex<Instant>,
+    /// Number of reconnects so far, watched to refresh state that may have changed meanwhile
+    reconnects: watch::Sender<u64>,
+    /// Order of waiting calls
+    lanes: Arc<DeviceLanes>,
+}
+
+impl TrezorDevice {
+    /// Open the device matching `selector`
+    pub fn connect(
+        mut selector: DeviceSelector,
+        interaction: Interaction,
+        call_timeout: Option<Duration>,
+        retry: RetryPolicy,
+        recorder: Option<Arc<ExchangeRecorder>>,
+    ) -> Result<Self, Error> {
+        let trezor = open_backend(&selector, None, recorder.as_ref())?;
+        // the address behind a device node changes on replug, so reconnects find the device
+        // by the serial it answered with
+        if let (DeviceTransport::UsbPath(_), None) = (&selector.transport, &selector.serial) {
+            selector.serial = trezor.features().map(|f| f.device_id().to_string());
+        }
+        let session = Arc::new(DeviceSession::new(initial_state(trezor.as_ref())));
+        let session_id = Arc::new(std::sync::Mutex::new(session_id(trezor.as_ref())));
+        let device = selector.to_string();
+        Ok(Self {
+            selector,
+            interaction: Interaction {
+                session: Some(session.clone()),
+                device,
+                ..interaction
+            },
+            session,
+            firmware: std::sync::Mutex::new(firmware_version(trezor.as_ref())),
+            trezor: Arc::new(Mutex::new(Some(trezor))),
+            call_timeout,
+            retry,
+            recorder,
+            needs_reset: Arc::new(AtomicBool::new(false)),
+            session_id,
+            last_used: std::sync::Mutex::new(Instant::now()),
+            reconnects: watch::channel(0).0,
+            lanes: DeviceLanes::new(None),
+        })
+    }
+
+    /// Let small verify_proofs calls go ahead of other calls waiting for the device
+    pub fn with_priority_lanes(mut self, config: Option<LaneConfig>) -> Self {
+        self.lanes = DeviceLanes::new(config);
+        self
+    }
+
+    /// Send `req` to the device and wait for the `R` response, handling interaction requests.
+    ///
+    /// If the message cannot be delivered because the transport failed, the device is
+    /// re-opened and the request is sent again as allowed by the [`RetryPolicy`].
+    ///
+    /// The call timeout counts from when the device is free for this call, so waiting behind
+    /// other calls never aborts anything. Within a gRPC call with a deadline, the wait is bounded
+    /// by the deadline, the call timeout is shortened to the time left and no device work is
+    /// started once the deadline has passed.
+    ///
+    /// USB reads cannot be interrupted, so when the call timeout expires during an exchange the
+    /// caller gets an error right away while the exchange finishes in the background. The next
+    /// call then re-initializes the device, which aborts any workflow left on its screen.
+    ///
+    /// Dropping the returned future, as tonic does when the mint cancels the call or its
+    /// deadline passes, cancels the call too: the next confirmation, PIN or passphrase request
+    /// of the device is answered with `Cancel`, which clears its screen and frees the device.
+    /// A confirmation already on the screen is not cancelled: trezor-client blocks reading the
+    /// answer to its ButtonAck and cannot send `Cancel` meanwhile, so it holds the device until
+    /// it is answered or the call timeout resets the device.
+    #[tracing::instrument(name = "device_call", skip_all, fields(message = ?S::MESSAGE_TYPE))]
+    pub async fn call<S, R>(&self, req: S) -> Result<R, Error>
+    where
+        S: TrezorMessage + Clone + Send + 'static,
+        R: TrezorMessage + Send + 'static,
+    {
+        self.touch();
+        let result = self.call_with_timeout(req).await;
+        self.touch();
+        result
+    }
+
+    async fn call_with_timeout<S, R>(&self, req: S) -> Result<R, Error>
+    where
+        S: TrezorMessage + Clone + Send + 'static,
+        R: TrezorMessage + Send + 'static,
+    {
+        // dropping this future, e.g. because the gRPC call was cancelled or timed out, cancels
+        // the interaction requests still to come
+        let cancel = CallCancel::default();
+        let _cancel_guard = cancel.guard();
+
+        // waiting for the device is bounded by the caller's deadline only, a call that gives
+        // up before its exchange started leaves the device as it is
+        let wait = async {
+            let turn = self.lanes.enter().await;
+            (turn, self.trezor.clone().lock_owned().await)
+        };
+        let (_turn, guard) = match crate::deadline::remaining() {
+            Some(remaining) => tokio::time::timeout(remaining, wait)
+                .await
+                .map_err(|_| TrezorSignatoryError::DeadlineExceeded)?,
+            None => wait.await,
+        };
+
+        // the budget left by the caller's gRPC deadline, if shorter than the call timeout
+        let deadline = crate::deadline::remaining()
+            .filter(|remaining| self.call_timeout.is_none_or(|timeout| *remaining < timeout));
+        if deadline == Some(Duration::ZERO) {
+            return Err(TrezorSignatoryError::DeadlineExceeded.into());
+        }
+        let exchanging = Arc::new(AtomicBool::new(false));
+        let call = self.call_with_reconnect(guard, req, &cancel, &exchanging);
+        let Some(timeout) = deadline.or(self.call_timeout) else {
+            return call.await;
+        };
+        match tokio::time::timeout(timeout, call).await {
+            Ok(res) => res,
+            Err(_) => {
+                // a timeout while reconnecting or backing off left nothing on the device
+                if exchanging.load(Ordering::SeqCst) {
+                    self.needs_reset.store(true, Ordering::SeqCst);
+                    self.session.set(SessionState::Uninitialized);
+                }
+                Err(match deadline {
+                    Some(_) => TrezorSignatoryError::DeadlineExceeded,
+                    None => TrezorSignatoryError::Timeout(timeout),
+                }
+                .into())
+            }
+        }
+    }
+
+    /// Run the exchange on the device held by `guard`, reconnecting on transport failures.
+    ///
+    /// `exchanging` is set while a message is on its way to or from the device.
+    async fn call_with_reconnect<S, R>(
+        &self,
+        mut guard: OwnedMutexGuard<Option<Box<dyn CashuDevice>>>,
+        req: S,
+        cancel: &CallCancel,
+        exchanging: &Arc<AtomicBool>,
+    ) -> Result<R, Error>
+    where
+        S: TrezorMessage + Clone + Send + 'static,
+        R: TrezorMessage + Send + 'static,
+    {
+        for attempt in 0..self.retry.max_attempts.max(1) {
+            if attempt > 0 {
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+            }
+            if guard.is_none() {
+                let trezor = self.reconnect().await?;
+                self.session.set(initial_state(trezor.as_ref()));
+                *guard = Some(trezor);
+            }
+
+            let req = req.clone();
+            // a device shared by several signatories answers for the one calling
+            let interaction = match crate::trezor::caller_interaction() {
+                Some(caller) => Interaction {
+                    session: Some(self.session.clone()),
+                    cancel: cancel.clone(),
+                    device: self.interaction.device.clone(),
+                    call: crate::progress::current_call(),
+                    ..caller
+                },
+                None => Interaction {
+                    cancel: cancel.clone(),
+                    call: crate::progress::current_call(),
+                    ..self.interaction.clone()
+                },
+            };
+            let needs_reset = self.needs_reset.clone();
+            let session_id = self.session_id.clone();
+            let in_exchange = exchanging.clone();
+            in_exchange.store(true, Ordering::SeqCst);
+            // the guard travels with the blocking task so the lock is held until the
+            // exchange really ends, even if this future is dropped on timeout
+            let (returned, outcome) = tokio::task::spawn_blocking(move || {
+                let mut guard = guard;
+                let outcome = match guard.as_mut() {
+                    Some(trezor) => exchange(
+                        trezor.as_mut(),
+                        req,
+                        &interaction,
+                        &needs_reset,
+                        &session_id,
+                    ),
+                    None => Exchange::TransportFailed,
+                };
+                in_exchange.store(false, Ordering::SeqCst);
+                (guard, outcome)
+            })
+            .await
+            .map_err(|e| TrezorSignatoryError::Transport(format!("call task failed: {}", e)))?;
+            guard = returned;
+
+            match outcome {
+                Exchange::Done(res) => {
+                    if self.session.get() == SessionState::Uninitialized {
+                        self.session.set(SessionState::Ready);
+                    }
+                    return res;
+                }
+                // drop the broken connection so a failed reconnect is retried on the next call
+                Exchange::TransportFailed => {
+                    self.session.set(SessionState::Disconnected);
+                    *guard = None;
+                }
+            }
+        }
+
+        Err(TrezorSignatoryError::Transport(format!(
+            "link still failing after {} attempts",
+            self.retry.max_attempts
+        ))
+        .into())
+    }
+
+    /// Session state machine of this device
+    pub fn session(&self) -> &DeviceSession {
+        &self.session
+    }
+
+    /// Criteria the device was selected by
+    pub fn selector(&self) -> &DeviceSelector {
+        &self.selector
+    }
+
+    /// Notified after every reconnect, the device may have rebooted into new firmware or
+    /// been re-initialized in the meantime
+    pub fn subscribe_reconnects(&self) -> watch::Receiver<u64> {
+        self.reconnects.subscribe()
+    }
+
+    /// Whether a call is currently in progress on this device
+    pub fn is_busy(&self) -> bool {
+        self.trezor.try_lock().is_err()
+    }
+
+    /// Health as of the last call or probe, without touching the device
+    pub fn cached_health(&self) -> DeviceHealth {
+        DeviceHealth::of_session(self.session.get())
+    }
+
+    /// Probe the device with a `GetFeatures` call, which never prompts for PIN or passphrase.
+    ///
+    /// A device busy with another call is not probed, its health follows the session state the
+    /// call maintains, so a device stuck on the PIN or passphrase prompt reports as locked.
+    pub async fn health(&self) -> DeviceHealth {
+        if self.is_busy() {
+            return self.cached_health();
+        }
+        match self
+            .call::<_, protos::Features>(protos::GetFeatures::new())
+            .await
+        {
+            Ok(features) if features.unlocked() => {
+                self.session.set(SessionState::Ready);
+                DeviceHealth::Ready
+            }
+            Ok(_) => {
+                self.session.set(SessionState::Locked);
+                DeviceHealth::Locked
+            }
+            Err(err) => {
+                tracing::debug!("Device health probe failed: {}", err);
+                DeviceHealth::Disconnected
+            }
+        }
+    }
+
+    /// Ping the device with [`Self::health`] whenever it has been idle for `interval`.
+    ///
+    /// Keeps transports that drop idle sessions open and notices a disconnected device before
+    /// the next real request has to wait for the reconnect.
+    pub fn spawn_keepalive(self: &Arc<Self>, interval: Duration) {
+        let device = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval / 2);
+            loop {
+                ticker.tick().await;
+                let Some(device) = device.upgrade() else {
+                    return;
+                };
+                if device.idle_for() < interval || device.is_busy() {
+                    continue;
+                }
+                if device.health().await == DeviceHealth::Disconnected {
+                    tracing::warn!("Keepalive ping to Trezor ({}) failed", device.selector);
+                }
+            }
+        });
+    }
+
+    fn touch(&self) {
+        *self.last_used.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_used
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed()
+    }
+
+    /// Ping the open connection once without reconnecting and drop it if the link is gone.
+    ///
+    /// Returns whether the device is still attached. A busy device is reported attached, the
+    /// call in progress notices a broken link by itself.
+    pub async fn check_link(&self) -> bool {
+        let Ok(mut guard) = self.trezor.clone().try_lock_owned() else {
+            return true;
+        };
+        if guard.is_none() {
+            return false;
+        }
+        let interaction = self.interaction.clone();
+        let needs_reset = self.needs_reset.clone();
+        let session_id = self.session_id.clone();
+        let attached = tokio::task::spawn_blocking(move || {
+            let outcome = match guard.as_mut() {
+                Some(trezor) => exchange::<_, protos::Features>(
+                    trezor.as_mut(),
+                    protos::GetFeatures::new(),
+                    &interaction,
+                    &needs_reset,
+                    &session_id,
+                ),
+                None => Exchange::TransportFailed,
+            };
+            match outcome {
+                Exchange::Done(_) => true,
+                Exchange::TransportFailed => {
+                    *guard = None;
+                    false
+                }
+            }
+        })
+        .await
+        .unwrap_or(false);
+        if !attached {
+            self.session.set(SessionState::Disconnected);
+        }
+        attached
+    }
+
+    /// Reconnect now if the connection was dropped, instead of on the next call
+    pub async fn reattach(&self) -> Result<(), Error> {
+        let mut guard = self.trezor.lock().await;
+        if guard.is_some() {
+            return Ok(());
+        }
+        let trezor = self.reconnect().await?;
+        self.session.set(initial_state(trezor.as_ref()));
+        *guard = Some(trezor);
+        Ok(())
+    }
+
+    /// Lock the device and end its session, so the next call asks for PIN and passphrase again
+    pub async fn lock(&self) -> Result<(), Error> {
+        self.call::<_, protos::Success>(protos::LockDevice::new())
+            .await?;
+        let mut guard = self.trezor.lock().await;
+        if let Some(trezor) = guard.as_mut() {
+            trezor.end_session();
+        }
+        *self.session_id.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        // start a fresh session before the next message
+        self.needs_reset.store(true, Ordering::SeqCst);
+        self.session.set(SessionState::Locked);
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for the call in progress, then end the device session and disconnect
+    pub async fn close(&self, timeout: Duration) {
+        let Ok(mut guard) = tokio::time::timeout(timeout, self.trezor.lock()).await else {
+            tracing::warn!(
+                "Device still busy after {:?}, closing without ending session",
+                timeout
+            );
+            return;
+        };
+        if let Some(mut trezor) = guard.take() {
+            trezor.end_session();
+        }
+        self.session.set(SessionState::Uninitialized);
+    }
+
+    async fn reconnect(&self) -> Result<Box<dyn CashuDevice>, Error> {
+        let resume = self
+            .session_id
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let mut last_err = None;
+        for attempt in 1..=RECONNECT_ATTEMPTS {
+            match open_backend(&self.selector, resume.as_deref(), self.recorder.as_ref()) {
+                Ok(trezor) => {
+                    tracing::info!("Reconnected to Trezor after {} attempt(s)", attempt);
+                    self.note_reconnect(trezor.as_ref());
+                    return Ok(trezor);
+                }
+                Err(err) => {
+                    tracing::debug!("Reconnect attempt {} failed: {}", attempt, err);
+                    last_err = Some(err);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            TrezorSignatoryError::Transport("failed to reconnect".to_string()).into()
+        }))
+    }
+
+    fn note_reconnect(&self, trezor: &dyn CashuDevice) {
+        let current = session_id(trezor);
+        let mut previous = self.session_id.lock().unwrap_or_else(|e| e.into_inner());
+        match (previous.as_ref(), current.as_ref()) {
+            (Some(previous), Some(current)) if previous == current => {
+                tracing::info!("Resumed the previous Trezor session");
+            }
+            (Some(_), _) => {
+                tracing::warn!("Trezor started a new session, any passphrase is asked again");
+            }
+            (None, _) => {}
+        }
+        *previous = current;
+        drop(previous);
+
+        let version = firmware_version(trezor);
+        let mut firmware = self.firmware.lock().unwrap_or_else(|e| e.into_inner());
+        if *firmware != version {
+            tracing::warn!(
+                "Trezor firmware changed from {} to {} while disconnected",
+                firmware,
+                version
+            );
+            *firmware = version;
+        }
+        self.reconnects.send_modify(|count| *count += 1);
+    }
+}
+
+/// State of a freshly initialized device
+fn initial_state(trezor: &dyn CashuDevice) -> SessionState {
+    match trezor.features() {
+        Some(features) if !features.unlocked() => SessionState::Locked,
+        Some(_) => SessionState::Ready,
+        None => SessionState::Uninitialized,
+    }
+}
+
+/// Session id reported in the features of an initialized device, firmware before 2.3 has none
+fn session_id(trezor: &dyn CashuDevice) -> Option<Vec<u8>> {
+    trezor
+        .features()
+        .filter(|features| features.has_session_id())
+        .map(|features| features.session_id().to_vec())
+}
+
+/// Firmware version reported in the features of an initialized device
+fn firmware_version(trezor: &dyn CashuDevice) -> String {
+    match trezor.features() {
+        Some(features) => format!(
+            "{}.{}.{} ({})",
+            features.major_version(),
+            features.minor_version(),
+            features.patch_version(),
+            hex::encode(features.revision())
+        ),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Blocking request/response exchange, including interaction requests
+fn exchange<S, R>(
+    trezor: &mut dyn CashuDevice,
+    req: S,
+    interaction: &Interaction,
+    needs_reset: &AtomicBool,
+    session_id: &std::sync::Mutex<Option<Vec<u8>>>,
+) -> Exchange<R>
+where
+    S: TrezorMessage,
+    R: TrezorMessage,
+{
+    if needs_reset.swap(false, Ordering::SeqCst) {
+        // Initialize aborts whatever workflow a timed out call left on the device, resuming
+        // the session keeps the passphrase
+        let session_id = session_id.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Err(err) = trezor.init_device(session_id.as_deref()) {
+            tracing::warn!("Failed to reset device after timeout: {}", err);
+            return Exchange::TransportFailed;
+        }
+    }
+
+    let req = match RawMessage::encode(&req) {
+        Ok(req) => req,
+        Err(err) => return Exchange::Done(Err(err)),
+    };
+    match trezor.call(req, interaction) {
+        Exchange::Done(res) => Exchange::Done(res.and_then(|resp| resp.decode())),
+        Exchange::TransportFailed => Exchange::TransportFailed,
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::Duration;
+
+use cdk_common::Error;
+use trezor_client::protos;
+use trezor_client::protos::failure::FailureType;
+
+/// Errors raised while talking to the device
+#[derive(Debug, thiserror::Error)]
+pub enum TrezorSignatoryError {
+    /// The USB/UDP link failed or no device could be opened
+    #[error("Trezor transport error: {0}")]
+    Transport(String),
+    /// The device rejected the request
+    #[error("Trezor failure {code:?}: {message}")]
+    Firmware { code: FailureType, message: String },
+    /// The device failed to process a valid request, or is not set up to
+    #[error("Trezor device fault {code:?}: {message}")]
+    DeviceFault { code: FailureType, message: String },
+    /// The user cancelled on the device or at a PIN/passphrase prompt
+    #[error("Trezor action cancelled by the user")]
+    Cancelled,
+    /// The device refused the PIN that was entered
+    #[error("Trezor PIN invalid")]
+    PinInvalid,
+    /// The request is not supported by the signatory or the firmware
+    #[error("Operation not supported: {0}")]
+    Unsupported(String),
+    /// A message from the device could not be converted
+    #[error("Invalid Trezor response: {0}")]
+    Mapping(String),
+    /// The device answered, but not to the request that was sent
+    #[error("Trezor response does not match the request: {0}")]
+    ResponseMismatch(String),
+    /// PIN or passphrase could not be obtained on the host
+    #[error("Trezor interaction failed: {0}")]
+    Interaction(String),
+    /// The request was refused by a host-side signing policy
+    #[error("Signing refused by policy: {0}")]
+    Policy(String),
+    /// blind_sign was called outside the signing window of the policy
+    #[error("Signing window closed: {0}")]
+    WindowClosed(String),
+    /// The request is malformed, e.g. an amount the keyset has no key for
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    /// The device did not answer in time
+    #[error("Trezor call timed out after {} s", .0.as_secs())]
+    Timeout(Duration),
+    /// The deadline the client set for the gRPC call passed
+    #[error("Deadline of the call exceeded")]
+    DeadlineExceeded,
+    /// The device serves a different key tree than the one cached, e.g. after a restore
+    #[error("Device key tree changed to {0}, signing is paused until an operator resumes it")]
+    KeyTreeChanged(String),
+    /// Signing is switched off for now, e.g. during a firmware update
+    #[error("Signatory temporarily unavailable: {0}")]
+    Unavailable(String),
+}
+
+impl TrezorSignatoryError {
+    /// Classify an error returned by `trezor_client`
+    pub fn from_client(err: trezor_client::Error) -> Self {
+        match err {
+            trezor_client::Error::FailureResponse(failure) => Self::from_failure(failure),
+            trezor_client::Error::Protobuf(err) => Self::Mapping(err.to_string()),
+            trezor_client::Error::UnexpectedMessageType(message_type) => {
+                Self::Unsupported(format!("unexpected message type {:?}", message_type))
+            }
+            err => Self::Transport(format!("{:?}", err)),
+        }
+    }
+
+    /// Classify a `Failure` message sent by the device
+    pub fn from_failure(failure: protos::Failure) -> Self {
+        match failure.code() {
+            FailureType::Failure_ActionCancelled | FailureType::Failure_PinCancelled => {
+                Self::Cancelled
+            }
+            FailureType::Failure_PinInvalid => Self::PinInvalid,
+            FailureType::Failure_UnexpectedMessage => {
+                Self::Unsupported(failure.message().to_string())
+            }
+            code @ (FailureType::Failure_ProcessError
+            | FailureType::Failure_FirmwareError
+            | FailureType::Failure_NotInitialized) => Self::DeviceFault {
+                code,
+                message: failure.message().to_string(),
+            },
+            code => Self::Firmware {
+                code,
+                message: failure.message().to_string(),
+            },
+        }
+    }
+
+    /// Status of the [`Error::HttpError`] the error is converted to, `None` when the device
+    /// could not be reached at all
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::Transport(_) => None,
+            Self::InvalidRequest(_) => Some(400),
+            Self::PinInvalid => Some(401),
+            Self::Policy(_) | Self::WindowClosed(_) => Some(403),
+            Self::Cancelled => Some(409),
+            Self::Firmware { .. } => Some(422),
+            Self::Interaction(_) => Some(424),
+            Self::DeviceFault { .. } => Some(500),
+            Self::Unsupported(_) => Some(501),
+            Self::Mapping(_) | Self::ResponseMismatch(_) => Some(502),
+            Self::Unavailable(_) | Self::KeyTreeChanged(_) => Some(503),
+            Self::Timeout(_) | Self::DeadlineExceeded => Some(504),
+        }
+    }
+}
+
+/// The CDK error type has no hardware specific variants. Errors are converted to
+/// [`Error::HttpError`], the variant of a failed call to a remote party, with a status per
+/// category, so the mint can tell a cancellation (409) or a wrong PIN (401) from a policy
+/// refusal (403) or an unreachable device (no status) without parsing the message.
+impl From<TrezorSignatoryError> for Error {
+    fn from(err: TrezorSignatoryError) -> Self {
+        Error::HttpError(err.status(), err.to_string())
+    }
+}
+
+/// Category of an error returned by the signatory, used as the metrics label of failed calls.
+///
+/// Device errors reach the signatory as [`Error::HttpError`], whose status is set from the
+/// [`TrezorSignatoryError`] by [`TrezorSignatoryError::status`].
+pub fn failure_reason(err: &Error) -> &'static str {
+    match err {
+        Error::HttpError(status, _) => match status {
+            None => "transport",
+            Some(400) => "invalid_request",
+            Some(401) => "pin_invalid",
+            Some(403) => "policy",
+            Some(409) => "cancelled",
+            Some(422) => "rejected",
+            Some(424) => "interaction",
+            Some(500) => "device_fault",
+            Some(501) => "unsupported",
+            Some(502) => "mapping",
+            Some(503) => "unavailable",
+            Some(504) => "timeout",
+            Some(_) => "other",
+        },
+        Error::UnknownKeySet
+        | Error::AmountKey
+        | Error::InactiveKeyset
+        | Error::UnsupportedUnit => "invalid_request",
+        _ => "other",
+    }
+}
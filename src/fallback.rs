@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use bip39::Mnemonic;
+use cdk_common::Error;
+use cdk_common::nuts::{BlindSignature, BlindedMessage, Proof};
+use cdk_signatory::signatory::SignatoryKeysets;
+
+use crate::host_signer::{HostKeyset, HostSigner};
+use crate::signatory::derivation_indexes;
+
+/// Software signer holding the same keys as the device, used only while no device is reachable.
+///
+/// Keeping the seed on the host gives up the protection the hardware wallet provides, so this
+/// is a last resort for hardware maintenance windows. Keys are derived with the CDK scheme
+/// and only keysets whose derived id matches the device's are served.
+pub struct SoftwareFallback {
+    signer: HostSigner,
+}
+
+impl SoftwareFallback {
+    /// Derive the keys for `device_keysets` under `account` from the BIP-39 mnemonic in `path`
+    pub fn from_seed_file(
+        path: &Path,
+        passphrase: &str,
+        account: Option<u32>,
+        device_keysets: &SignatoryKeysets,
+    ) -> Result<Self> {
+        let words =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mnemonic = Mnemonic::parse(words.trim())
+            .with_context(|| format!("{} does not hold a valid mnemonic", path.display()))?;
+        let mut signer = HostSigner::new(&mnemonic.to_seed_normalized(passphrase), account)?;
+
+        let indexes = derivation_indexes(device_keysets);
+        for device_keyset in &device_keysets.keysets {
+            let index = indexes[&device_keyset.id];
+            let derived = signer
+                .derive(
+                    device_keyset.unit.clone(),
+                    index,
+                    &device_keyset.amounts,
+                    device_keyset.final_expiry,
+                    device_keyset.id.get_version(),
+                )
+                .ok()
+                .filter(|keyset| keyset.id == device_keyset.id);
+            match derived {
+                Some(keyset) => signer.insert(HostKeyset {
+                    keyset,
+                    index,
+                    amounts: device_keyset.amounts.clone(),
+                    input_fee_ppk: device_keyset.input_fee_ppk,
+                    active: device_keyset.active,
+                }),
+                None => tracing::warn!(
+                    "Fallback seed does not derive keyset {}, it will not be served without the device",
+                    device_keyset.id
+                ),
+            }
+        }
+        if signer.keysets().is_empty() {
+            bail!("fallback seed does not derive any of the device keysets");
+        }
+
+        tracing::warn!(
+            "Software fallback signer loaded with {} keysets; the seed is held on this host",
+            signer.keysets().len()
+        );
+        Ok(Self { signer })
+    }
+
+    pub fn blind_sign(&self, messages: &[BlindedMessage]) -> Result<Vec<BlindSignature>, Error> {
+        self.signer.blind_sign(messages)
+    }
+
+    pub fn verify_proofs(&self, proofs: &[Proof]) -> Result<(), Error> {
+        self.signer.verify_proofs(proofs)
+    }
+}
@@ -0,0 +1,197 @@
+use cdk_common::dhke::{sign_message, verify_message};
+use cdk_common::nuts::{
+    BlindSignature, BlindedMessage, CurrencyUnit, Id, KeySetVersion, MintKeyPair, MintKeySet, Proof,
+};
+use cdk_common::{Amount, Error, Keys, PublicKey, bitcoin};
+use cdk_signatory::signatory::SignatoryKeySet;
+
+use crate::signatory::keyset_derivation_path;
+
+/// Keyset whose keys are held on the host
+pub struct HostKeyset {
+    pub keyset: MintKeySet,
+    /// Derivation index within the unit
+    pub index: u32,
+    pub amounts: Vec<u64>,
+    pub input_fee_ppk: u64,
+    pub active: bool,
+}
+
+impl HostKeyset {
+    pub fn info(&self) -> SignatoryKeySet {
+        SignatoryKeySet {
+            id: self.keyset.id,
+            unit: self.keyset.unit.clone(),
+            active: self.active,
+            keys: Keys::from(self.keyset.keys.clone()),
+            amounts: self.amounts.clone(),
+            input_fee_ppk: self.input_fee_ppk,
+            final_expiry: self.keyset.final_expiry,
+        }
+    }
+}
+
+/// Keysets derived on the host by the CDK scheme, signing and verifying with BDHKE like the
+/// Cashu app on the device.
+///
+/// Shared by everything that signs without the device: the software signers, the fallback
+/// and the mock device. Only inactive keysets are refused, amounts and policy are checked by
+/// the signatory before.
+pub struct HostSigner {
+    xpriv: bitcoin::bip32::Xpriv,
+    /// BIP32 account the keysets are derived under
+    account: Option<u32>,
+    keysets: Vec<HostKeyset>,
+}
+
+impl HostSigner {
+    /// Signer without keysets, deriving them from `seed` under `account`
+    pub fn new(seed: &[u8], account: Option<u32>) -> Result<Self, Error> {
+        let xpriv = bitcoin::bip32::Xpriv::new_master(bitcoin::Network::Bitcoin, seed)
+            .map_err(|e| Error::Custom(format!("deriving master key: {}", e)))?;
+        Ok(Self {
+            xpriv,
+            account,
+            keysets: Vec::new(),
+        })
+    }
+
+    /// Public key of the master key, reported by devices as the signatory key
+    #[cfg(any(test, feature = "mock-device"))]
+    pub fn pubkey(&self) -> PublicKey {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        PublicKey::from(bitcoin::bip32::Xpub::from_priv(&secp, &self.xpriv).public_key)
+    }
+
+    pub fn keysets(&self) -> &[HostKeyset] {
+        &self.keysets
+    }
+
+    /// Derive keyset `index` of `unit` without adding it
+    pub fn derive(
+        &self,
+        unit: CurrencyUnit,
+        index: u32,
+        amounts: &[u64],
+        final_expiry: Option<u64>,
+        version: KeySetVersion,
+    ) -> Result<MintKeySet, Error> {
+        let path = keyset_derivation_path(unit.clone(), index, self.account)
+            .ok_or(Error::UnsupportedUnit)?;
+        Ok(MintKeySet::generate_from_xpriv(
+            &bitcoin::secp256k1::Secp256k1::new(),
+            self.xpriv,
+            amounts,
+            unit,
+            path,
+            final_expiry,
+            version,
+        ))
+    }
+
+    pub fn insert(&mut self, keyset: HostKeyset) {
+        self.keysets.push(keyset);
+    }
+
+    /// Derive the next keyset of `unit` and make it the only active one
+    pub fn rotate(
+        &mut self,
+        unit: CurrencyUnit,
+        amounts: &[u64],
+        input_fee_ppk: u64,
+        final_expiry: Option<u64>,
+    ) -> Result<&HostKeyset, Error> {
+        let index = self
+            .keysets
+            .iter()
+            .filter(|k| k.keyset.unit == unit)
+            .map(|k| k.index + 1)
+            .max()
+            .unwrap_or(0);
+        let keyset = self.derive(
+            unit.clone(),
+            index,
+            amounts,
+            final_expiry,
+            KeySetVersion::Version00,
+        )?;
+        for previous in self.keysets.iter_mut().filter(|k| k.keyset.unit == unit) {
+            previous.active = false;
+        }
+        self.keysets.push(HostKeyset {
+            keyset,
+            index,
+            amounts: amounts.to_vec(),
+            input_fee_ppk,
+            active: true,
+        });
+        Ok(self.keysets.last().expect("just pushed"))
+    }
+
+    pub fn blind_sign(&self, messages: &[BlindedMessage]) -> Result<Vec<BlindSignature>, Error> {
+        messages
+            .iter()
+            .map(|message| self.sign(message.keyset_id, message.amount, &message.blinded_secret))
+            .collect()
+    }
+
+    pub fn verify_proofs(&self, proofs: &[Proof]) -> Result<(), Error> {
+        for proof in proofs {
+            self.verify(
+                proof.keyset_id,
+                proof.amount,
+                proof.c,
+                proof.secret.as_bytes(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Sign one blinded secret with the key of `amount` in an active keyset
+    pub fn sign(
+        &self,
+        keyset_id: Id,
+        amount: Amount,
+        blinded_secret: &PublicKey,
+    ) -> Result<BlindSignature, Error> {
+        let key_pair = self.key_pair(keyset_id, amount, true)?;
+        let c = sign_message(&key_pair.secret_key, blinded_secret)?;
+        Ok(BlindSignature::new(
+            amount,
+            c,
+            keyset_id,
+            blinded_secret,
+            key_pair.secret_key.clone(),
+        )?)
+    }
+
+    /// Check the signature `c` on `secret` against the key of `amount`
+    pub fn verify(
+        &self,
+        keyset_id: Id,
+        amount: Amount,
+        c: PublicKey,
+        secret: &[u8],
+    ) -> Result<(), Error> {
+        let key_pair = self.key_pair(keyset_id, amount, false)?;
+        verify_message(&key_pair.secret_key, c, secret)?;
+        Ok(())
+    }
+
+    fn key_pair(
+        &self,
+        keyset_id: Id,
+        amount: Amount,
+        signing: bool,
+    ) -> Result<&MintKeyPair, Error> {
+        let keyset = self
+            .keysets
+            .iter()
+            .find(|k| k.keyset.id == keyset_id)
+            .ok_or(Error::UnknownKeySet)?;
+        if signing && !keyset.active {
+            return Err(Error::InactiveKeyset);
+        }
+        keyset.keyset.keys.get(&amount).ok_or(Error::AmountKey)
+    }
+}
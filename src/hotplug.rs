@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rusb::{Context, Device, Hotplug, HotplugBuilder, UsbContext};
+use tokio::sync::mpsc;
+
+use crate::device::{SessionState, TrezorDevice};
+use crate::metrics::SignatoryMetrics;
+use crate::progress::ProgressEvents;
+
+/// pid.codes vendor id the Trezor Model T and later enumerate with
+const TREZOR_VENDOR_ID: u16 = 0x1209;
+/// Product id of the firmware, the bootloader (0x53c0) cannot sign
+const TREZOR_PRODUCT_ID: u16 = 0x53c1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UsbEvent {
+    Attached,
+    Detached,
+}
+
+/// Forwards libusb callbacks, which run on the event thread, to the async side
+struct Forwarder {
+    events: mpsc::UnboundedSender<UsbEvent>,
+}
+
+impl<T: UsbContext> Hotplug<T> for Forwarder {
+    fn device_arrived(&mut self, device: Device<T>) {
+        tracing::debug!(
+            "Trezor attached at USB {:03}/{:03}",
+            device.bus_number(),
+            device.address()
+        );
+        let _ = self.events.send(UsbEvent::Attached);
+    }
+
+    fn device_left(&mut self, device: Device<T>) {
+        tracing::debug!(
+            "Trezor detached from USB {:03}/{:03}",
+            device.bus_number(),
+            device.address()
+        );
+        let _ = self.events.send(UsbEvent::Detached);
+    }
+}
+
+/// Act on Trezors being plugged in and out instead of waiting for the next call to fail.
+///
+/// Hotplug events do not tell which of our devices came or went, so on a detach every idle
+/// USB device is pinged once and dropped if its link is gone, and on an attach every
+/// disconnected one is reopened. Reopening notifies the reconnect watchers, which re-check
+/// the keysets. Does nothing if libusb has no hotplug support on this platform, and fails if
+/// libusb cannot be started.
+pub fn spawn(
+    devices: Vec<Arc<TrezorDevice>>,
+    metrics: Arc<SignatoryMetrics>,
+    progress: ProgressEvents,
+) -> Result<()> {
+    let devices: Vec<Arc<TrezorDevice>> = devices
+        .into_iter()
+        .filter(|device| device.selector().transport.is_usb())
+        .collect();
+    if devices.is_empty() {
+        return Ok(());
+    }
+    if !rusb::has_hotplug() {
+        tracing::warn!("No USB hotplug support, unplugged devices are noticed on the next call");
+        return Ok(());
+    }
+
+    let context = Context::new()?;
+    let (events, mut received) = mpsc::unbounded_channel();
+    let registration = HotplugBuilder::new()
+        .vendor_id(TREZOR_VENDOR_ID)
+        .product_id(TREZOR_PRODUCT_ID)
+        .enumerate(false)
+        .register(&context, Box::new(Forwarder { events }))?;
+    // libusb runs the callbacks from handle_events, which blocks
+    std::thread::Builder::new()
+        .name("usb-hotplug".to_string())
+        .spawn(move || {
+            let _registration = registration;
+            loop {
+                if let Err(err) = context.handle_events(None) {
+                    tracing::error!("USB hotplug event loop failed: {}", err);
+                    return;
+                }
+            }
+        })?;
+
+    tracing::info!(
+        "Watching USB hotplug events for {} device(s)",
+        devices.len()
+    );
+    tokio::spawn(async move {
+        while let Some(event) = received.recv().await {
+            metrics.record_usb_event(event == UsbEvent::Attached);
+            for device in &devices {
+                let selector = device.selector().to_string();
+                let was_disconnected = device.session().get() == SessionState::Disconnected;
+                match event {
+                    UsbEvent::Detached => {
+                        if !device.check_link().await && !was_disconnected {
+                            tracing::warn!("Trezor ({}) was unplugged", selector);
+                            progress.device_detached(&selector);
+                        }
+                    }
+                    UsbEvent::Attached if was_disconnected => match device.reattach().await {
+                        Ok(()) => {
+                            tracing::info!("Trezor ({}) was plugged in again", selector);
+                            progress.device_attached(&selector);
+                        }
+                        // another Trezor, or this one is still starting up
+                        Err(err) => tracing::debug!("Reattaching {} failed: {}", selector, err),
+                    },
+                    UsbEvent::Attached => {}
+                }
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,416 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::extract::connect_info::Connected;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::serve::IncomingStream;
+use axum::{Json, Router};
+use cdk_common::nuts::{BlindSignature, BlindedMessage, CurrencyUnit, Id, Keys, Proof};
+use cdk_common::{Error, PublicKey};
+use cdk_signatory::signatory::Signatory;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+
+use crate::auth::ClientAuth;
+use crate::config::ServerConfig;
+use crate::progress::CALL_ID_HEADER;
+use crate::queue::RequestQueue;
+use crate::signatory::TrezorSignatory;
+use crate::tls::ReloadingTls;
+
+/// Optional HTTP server exposing the signatory as JSON
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to serve on, e.g. `127.0.0.1:15061`. Unset disables the HTTP server. Addresses
+    /// other than loopback need TLS, see [`spawn`].
+    pub listen_addr: Option<String>,
+    /// File holding the bearer token of `blind_sign` and `verify_proofs`, which are only
+    /// served when set
+    pub token_file: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+struct HttpState {
+    signatory: Arc<TrezorSignatory>,
+    auth: ClientAuth,
+    queue: RequestQueue,
+}
+
+/// Serve the JSON endpoints in the background if `config` has a listen address.
+///
+/// Keysets and status are served to anyone who can connect, `blind_sign` and `verify_proofs`
+/// need the bearer token. With `tls_dir` in `server` the bridge uses the same mutual TLS and
+/// allowed client fingerprints as the gRPC listeners, without it only a loopback address is
+/// served. Calls that reach the signatory share `queue` with the gRPC services.
+pub async fn spawn(
+    signatory: Arc<TrezorSignatory>,
+    config: &HttpConfig,
+    server: &ServerConfig,
+    queue: RequestQueue,
+) -> Result<Option<JoinHandle<()>>> {
+    let Some(addr) = &config.listen_addr else {
+        return Ok(None);
+    };
+
+    let mut router = Router::new()
+        .route("/v1/keysets", get(keysets))
+        .route("/v1/status", get(status));
+    let mut auth = match &config.token_file {
+        Some(path) => {
+            router = router
+                .route("/v1/blind_sign", post(blind_sign))
+                .route("/v1/verify_proofs", post(verify_proofs));
+            ClientAuth::default().with_token(&crate::server::load_token(path)?)
+        }
+        None => {
+            tracing::info!("No HTTP token file, blind_sign and verify_proofs are not served");
+            ClientAuth::default()
+        }
+    };
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding {}", addr))?;
+    let local_addr = listener.local_addr()?;
+    let tls = match &server.tls_dir {
+        Some(tls_dir) => {
+            auth = auth.with_fingerprints(&server.allowed_client_fingerprints);
+            let tls = ReloadingTls::load(tls_dir, server.client_ca.as_deref(), &[b"http/1.1"])?;
+            tls.spawn_reload();
+            Some(tls)
+        }
+        // tokens, blinded messages and proofs would cross the network in the clear
+        None if !local_addr.ip().is_loopback() => anyhow::bail!(
+            "the HTTP bridge has no TLS without tls_dir, listen on a loopback address instead \
+             of {}",
+            local_addr
+        ),
+        None => None,
+    };
+    let router = router
+        .with_state(HttpState {
+            signatory,
+            auth,
+            queue,
+        })
+        .into_make_service_with_connect_info::<Peer>();
+
+    tracing::info!(
+        "HTTP bridge listening on {}{}",
+        local_addr,
+        if tls.is_some() { " (TLS)" } else { "" }
+    );
+    Ok(Some(tokio::spawn(async move {
+        let served = match tls {
+            Some(tls) => {
+                let incoming = tls.incoming(vec![listener]);
+                axum::serve(
+                    TlsListener {
+                        incoming,
+                        local_addr,
+                    },
+                    router,
+                )
+                .await
+            }
+            None => axum::serve(listener, router).await,
+        };
+        if let Err(err) = served {
+            tracing::error!("HTTP bridge failed: {}", err);
+        }
+    })))
+}
+
+/// Connections of the bridge once their TLS handshake completed
+struct TlsListener {
+    incoming: ReceiverStream<io::Result<TlsStream<TcpStream>>>,
+    local_addr: SocketAddr,
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = Peer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let Some(accepted) = self.incoming.next().await else {
+                // the accept tasks only stop once the listener is dropped
+                return std::future::pending().await;
+            };
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::warn!("Failed to accept HTTP connection: {}", err);
+                    continue;
+                }
+            };
+            let (tcp, session) = stream.get_ref();
+            let Ok(addr) = tcp.peer_addr() else {
+                continue;
+            };
+            let cert = session
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|leaf| crate::auth::fingerprint(leaf.as_ref()));
+            return (stream, Peer { addr, cert });
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(Peer {
+            addr: self.local_addr,
+            cert: None,
+        })
+    }
+}
+
+/// Caller of the bridge: its address and, over TLS, the fingerprint of its client certificate
+#[derive(Debug, Clone)]
+struct Peer {
+    addr: SocketAddr,
+    cert: Option<String>,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Peer {
+            addr: *stream.remote_addr(),
+            cert: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+/// Error answered as `{"error": "..."}`, with a `Retry-After` header in seconds if set
+struct HttpError(StatusCode, String, Option<HeaderValue>);
+
+impl From<Error> for HttpError {
+    fn from(err: Error) -> Self {
+        let status = match &err {
+            Error::UnknownKeySet
+            | Error::AmountKey
+            | Error::InactiveKeyset
+            | Error::UnsupportedUnit => StatusCode::BAD_REQUEST,
+            // device errors carry the status of their category, see `error.rs`
+            Error::HttpError(Some(status), _) => {
+                StatusCode::from_u16(*status).unwrap_or(StatusCode::UNPROCESSABLE_ENTITY)
+            }
+            Error::HttpError(None, _) => StatusCode::SERVICE_UNAVAILABLE,
+            // e.g. a proof that does not verify
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        Self(status, err.to_string(), None)
+    }
+}
+
+/// Calls refused by the request queue or the client checks
+impl From<Status> for HttpError {
+    fn from(status: Status) -> Self {
+        let code = match status.code() {
+            tonic::Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+            tonic::Code::PermissionDenied => StatusCode::FORBIDDEN,
+            tonic::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        let retry_after = status
+            .metadata()
+            .get("retry-after")
+            .and_then(|value| HeaderValue::from_bytes(value.as_bytes()).ok());
+        Self(code, status.message().to_string(), retry_after)
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        let mut response = (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response();
+        if let Some(retry_after) = self.2 {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after);
+        }
+        response
+    }
+}
+
+#[derive(Deserialize)]
+struct KeysetsQuery {
+    #[serde(default)]
+    include_keys: bool,
+}
+
+#[derive(Serialize)]
+struct KeysetsResponse {
+    pubkey: PublicKey,
+    keysets: Vec<KeysetResponse>,
+}
+
+#[derive(Serialize)]
+struct KeysetResponse {
+    id: Id,
+    unit: CurrencyUnit,
+    active: bool,
+    input_fee_ppk: u64,
+    final_expiry: Option<u64>,
+    amounts: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keys: Option<Keys>,
+}
+
+/// `GET /v1/keysets?include_keys=true`
+async fn keysets(
+    State(state): State<HttpState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    Query(query): Query<KeysetsQuery>,
+    headers: HeaderMap,
+) -> Result<Json<KeysetsResponse>, HttpError> {
+    state.auth.check_fingerprint(peer.cert.as_deref())?;
+    let keysets = queued(&state, &peer, &headers, state.signatory.keysets()).await??;
+    Ok(Json(KeysetsResponse {
+        pubkey: keysets.pubkey,
+        keysets: keysets
+            .keysets
+            .into_iter()
+            .map(|keyset| KeysetResponse {
+                id: keyset.id,
+                unit: keyset.unit,
+                active: keyset.active,
+                input_fee_ppk: keyset.input_fee_ppk,
+                final_expiry: keyset.final_expiry,
+                amounts: keyset.amounts,
+                keys: query.include_keys.then_some(keyset.keys),
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    health: &'static str,
+    paused: bool,
+    locked: bool,
+    standby: bool,
+    devices: Vec<DeviceStatus>,
+}
+
+#[derive(Serialize)]
+struct DeviceStatus {
+    selector: String,
+    health: &'static str,
+    busy: bool,
+    session: &'static str,
+}
+
+/// `GET /v1/status`, from the state the last calls and health probes left on the devices
+async fn status(
+    State(state): State<HttpState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    headers: HeaderMap,
+) -> Result<Json<StatusResponse>, HttpError> {
+    state.auth.check_fingerprint(peer.cert.as_deref())?;
+    let report = async {
+        let devices = state
+            .signatory
+            .devices()
+            .map(|device| DeviceStatus {
+                selector: device.selector().to_string(),
+                health: device.cached_health().as_str(),
+                busy: device.is_busy(),
+                session: device.session().get().as_str(),
+            })
+            .collect();
+        StatusResponse {
+            health: state.signatory.cached_health().as_str(),
+            paused: state.signatory.is_paused(),
+            locked: state.signatory.is_locked(),
+            standby: state.signatory.is_standby(),
+            devices,
+        }
+    };
+    Ok(Json(queued(&state, &peer, &headers, report).await?))
+}
+
+fn check_client(state: &HttpState, peer: &Peer, headers: &HeaderMap) -> Result<(), HttpError> {
+    state.auth.check_fingerprint(peer.cert.as_deref())?;
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    Ok(state.auth.check_bearer(authorization)?)
+}
+
+/// Run `fut` behind the request queue, as a call of `peer` tagged with its request id and the
+/// operation it names
+async fn queued<F: Future>(
+    state: &HttpState,
+    peer: &Peer,
+    headers: &HeaderMap,
+    fut: F,
+) -> Result<F::Output, HttpError> {
+    let request_id = headers
+        .get(CALL_ID_HEADER)
+        .and_then(|value| value.to_str().ok());
+    let operation = crate::operation::from_headers(headers)?;
+    Ok(state
+        .queue
+        .run(
+            peer.addr.ip().to_string(),
+            peer.cert.clone(),
+            request_id,
+            crate::operation::scope(operation, fut),
+        )
+        .await?)
+}
+
+/// `POST /v1/blind_sign` with a JSON array of NUT-00 blinded messages
+async fn blind_sign(
+    State(state): State<HttpState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    headers: HeaderMap,
+    Json(messages): Json<Vec<BlindedMessage>>,
+) -> Result<Json<Vec<BlindSignature>>, HttpError> {
+    check_client(&state, &peer, &headers)?;
+    Ok(Json(
+        queued(
+            &state,
+            &peer,
+            &headers,
+            state.signatory.blind_sign(messages),
+        )
+        .await??,
+    ))
+}
+
+/// `POST /v1/verify_proofs` with a JSON array of NUT-00 proofs
+async fn verify_proofs(
+    State(state): State<HttpState>,
+    ConnectInfo(peer): ConnectInfo<Peer>,
+    headers: HeaderMap,
+    Json(proofs): Json<Vec<Proof>>,
+) -> Result<StatusCode, HttpError> {
+    check_client(&state, &peer, &headers)?;
+    queued(
+        &state,
+        &peer,
+        &headers,
+        state.signatory.verify_proofs(proofs),
+    )
+    .await??;
+    Ok(StatusCode::NO_CONTENT)
+}
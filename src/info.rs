@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use cdk_common::PublicKey;
+use cdk_signatory::signatory::Signatory;
+use tonic::{Request, Response, Status};
+
+use crate::signatory::TrezorSignatory;
+use crate::version::PROTOCOL_VERSION;
+
+pub mod proto {
+    tonic::include_proto!("cdk_signatory_trezor.info");
+}
+
+use proto::info_server::Info;
+pub use proto::info_server::InfoServer;
+
+/// Limits mints can read to shape their requests, and the served keysets
+pub struct InfoService {
+    signatory: Arc<TrezorSignatory>,
+    /// Key the signatory's responses are signed with, if they are
+    response_key: Option<PublicKey>,
+}
+
+impl InfoService {
+    pub fn new(signatory: Arc<TrezorSignatory>) -> Self {
+        Self {
+            signatory,
+            response_key: None,
+        }
+    }
+
+    /// Report `key` as the key responses are signed with
+    pub fn with_response_key(mut self, key: Option<PublicKey>) -> Self {
+        self.response_key = key;
+        self
+    }
+}
+
+#[tonic::async_trait]
+impl Info for InfoService {
+    async fn get_info(
+        &self,
+        _request: Request<proto::GetInfoRequest>,
+    ) -> Result<Response<proto::SignatoryInfo>, Status> {
+        let options = &self.signatory.options;
+        Ok(Response::new(proto::SignatoryInfo {
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            max_batch_size: u32::try_from(options.max_batch_size).unwrap_or(u32::MAX),
+            watch_only: options.watch_only,
+            response_pubkey: self
+                .response_key
+                .map(|key| key.to_hex())
+                .unwrap_or_default(),
+        }))
+    }
+
+    async fn get_keysets(
+        &self,
+        request: Request<proto::GetKeysetsRequest>,
+    ) -> Result<Response<proto::Keysets>, Status> {
+        let include_keys = request.into_inner().include_keys;
+        let keysets = self
+            .signatory
+            .keysets()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(proto::Keysets {
+            pubkey: keysets.pubkey.to_hex(),
+            keysets: keysets
+                .keysets
+                .into_iter()
+                .map(|keyset| proto::Keyset {
+                    id: keyset.id.to_string(),
+                    unit: keyset.unit.to_string(),
+                    active: keyset.active,
+                    input_fee_ppk: keyset.input_fee_ppk,
+                    final_expiry: keyset.final_expiry,
+                    amounts: keyset.amounts,
+                    keys: if include_keys {
+                        keyset
+                            .keys
+                            .iter()
+                            .map(|(amount, key)| (amount.to_u64(), key.to_hex()))
+                            .collect()
+                    } else {
+                        Default::default()
+                    },
+                })
+                .collect(),
+        }))
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use cdk_common::nuts::{CurrencyUnit, Id, PublicKey};
+use cdk_common::{Error, Keys};
+use cdk_signatory::signatory::{SignatoryKeySet, SignatoryKeysets};
+use serde::{Deserialize, Serialize};
+
+use crate::state_file;
+
+/// On-disk copy of [`SignatoryKeysets`]
+#[derive(Serialize, Deserialize)]
+struct CachedKeysets {
+    pubkey: PublicKey,
+    keysets: Vec<CachedKeySet>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedKeySet {
+    id: Id,
+    unit: CurrencyUnit,
+    active: bool,
+    keys: Keys,
+    amounts: Vec<u64>,
+    input_fee_ppk: u64,
+    final_expiry: Option<u64>,
+}
+
+impl From<&SignatoryKeysets> for CachedKeysets {
+    fn from(keysets: &SignatoryKeysets) -> Self {
+        Self {
+            pubkey: keysets.pubkey,
+            keysets: keysets
+                .keysets
+                .iter()
+                .map(|ks| CachedKeySet {
+                    id: ks.id,
+                    unit: ks.unit.clone(),
+                    active: ks.active,
+                    keys: ks.keys.clone(),
+                    amounts: ks.amounts.clone(),
+                    input_fee_ppk: ks.input_fee_ppk,
+                    final_expiry: ks.final_expiry,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<CachedKeysets> for SignatoryKeysets {
+    fn from(cached: CachedKeysets) -> Self {
+        Self {
+            pubkey: cached.pubkey,
+            keysets: cached
+                .keysets
+                .into_iter()
+                .map(|ks| SignatoryKeySet {
+                    id: ks.id,
+                    unit: ks.unit,
+                    active: ks.active,
+                    keys: ks.keys,
+                    amounts: ks.amounts,
+                    input_fee_ppk: ks.input_fee_ppk,
+                    final_expiry: ks.final_expiry,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Read keysets saved by [`store`], `None` if the file does not exist yet
+pub fn load(path: &Path) -> Result<Option<SignatoryKeysets>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = std::fs::read(path)
+        .map_err(|e| Error::Custom(format!("reading {}: {}", path.display(), e)))?;
+    let cached: CachedKeysets = serde_json::from_slice(&contents)
+        .map_err(|e| Error::Custom(format!("parsing {}: {}", path.display(), e)))?;
+    Ok(Some(cached.into()))
+}
+
+/// Save `keysets` to `path`
+pub async fn store(path: &Path, keysets: &SignatoryKeysets) -> Result<(), Error> {
+    let contents = serde_json::to_vec_pretty(&CachedKeysets::from(keysets))
+        .map_err(|e| Error::Custom(e.to_string()))?;
+    state_file::write(path, contents)
+        .await
+        .map_err(|e| Error::Custom(format!("writing {}: {}", path.display(), e)))
+}
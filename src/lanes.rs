@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+tokio::task_local! {
+    /// Number of proofs of the verify_proofs call being served
+    static VERIFY_PROOFS: usize;
+}
+
+/// Run `fut` as part of a verify_proofs call of `proofs` proofs
+pub async fn verify_scope<F: Future>(proofs: usize, fut: F) -> F::Output {
+    VERIFY_PROOFS.scope(proofs, fut).await
+}
+
+/// Which device calls jump the queue
+#[derive(Debug, Clone, Copy)]
+pub struct LaneConfig {
+    /// verify_proofs calls of at most this many proofs go ahead of other calls
+    pub max_priority_proofs: usize,
+    /// Priority calls served in a row while other calls wait, before one of those goes first
+    pub max_streak: u32,
+}
+
+/// Calls waiting in one lane, served round-robin across callers and in arrival order per caller
+#[derive(Default)]
+struct Lane {
+    callers: VecDeque<(String, VecDeque<oneshot::Sender<Turn>>)>,
+}
+
+impl Lane {
+    fn push(&mut self, caller: String, waiting: oneshot::Sender<Turn>) {
+        match self.callers.iter_mut().find(|(c, _)| *c == caller) {
+            Some((_, calls)) => calls.push_back(waiting),
+            None => self.callers.push_back((caller, VecDeque::from([waiting]))),
+        }
+    }
+
+    /// Take the oldest call of the caller whose turn it is, who then goes to the back
+    fn pop(&mut self) -> Option<oneshot::Sender<Turn>> {
+        let (caller, mut calls) = self.callers.pop_front()?;
+        let next = calls.pop_front();
+        if !calls.is_empty() {
+            self.callers.push_back((caller, calls));
+        }
+        next
+    }
+
+    fn is_empty(&self) -> bool {
+        self.callers.is_empty()
+    }
+}
+
+#[derive(Default)]
+struct LaneState {
+    /// Whether a call holds the turn
+    busy: bool,
+    priority: Lane,
+    normal: Lane,
+    /// Priority calls served in a row while normal calls were waiting
+    streak: u32,
+}
+
+/// Orders the calls waiting for one device.
+///
+/// Callers, told apart by their client certificate or else their address, take turns, so one
+/// mint sending a burst of calls does not hold up the others. A caller's own calls are served
+/// in arrival order. With a [`LaneConfig`], small verify_proofs calls, typically from
+/// latency-critical melts, also go ahead of large blind_sign batches; after `max_streak`
+/// priority calls in a row a waiting normal call goes next, so a steady stream of melts cannot
+/// starve minting.
+pub struct DeviceLanes {
+    config: Option<LaneConfig>,
+    state: Mutex<LaneState>,
+}
+
+impl DeviceLanes {
+    pub fn new(config: Option<LaneConfig>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            state: Mutex::new(LaneState::default()),
+        })
+    }
+
+    /// Wait for the turn of the current call, held until the returned [`Turn`] is dropped
+    pub async fn enter(self: &Arc<Self>) -> Turn {
+        let priority = self.config.is_some_and(|config| {
+            VERIFY_PROOFS
+                .try_with(|proofs| *proofs <= config.max_priority_proofs)
+                .unwrap_or(false)
+        });
+        // calls of the signatory itself, e.g. keepalive pings, queue as one caller
+        let caller = crate::progress::current_call()
+            .map(|call| call.caller)
+            .unwrap_or_default();
+        let waiting = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if !state.busy {
+                state.busy = true;
+                return Turn {
+                    lanes: self.clone(),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            match priority {
+                true => state.priority.push(caller, tx),
+                false => state.normal.push(caller, tx),
+            }
+            rx
+        };
+        waiting
+            .await
+            .expect("waiting calls are only dropped after handing them the turn")
+    }
+
+    /// Take the call to hand the turn to next, or mark the device idle if none is waiting
+    fn next(&self) -> Option<oneshot::Sender<Turn>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let max_streak = self.config.map_or(0, |config| config.max_streak);
+        let starved = state.streak >= max_streak && !state.normal.is_empty();
+        let next = match state.priority.is_empty() || starved {
+            false => {
+                if !state.normal.is_empty() {
+                    state.streak += 1;
+                }
+                state.priority.pop()
+            }
+            true => {
+                state.streak = 0;
+                state.normal.pop()
+            }
+        };
+        if next.is_none() {
+            state.busy = false;
+        }
+        next
+    }
+}
+
+/// Turn of one call on the device, handed to the next waiting call when dropped
+pub struct Turn {
+    lanes: Arc<DeviceLanes>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        if let Some(next) = self.lanes.next() {
+            // a call that stopped waiting returns the turn, dropping it hands it on again
+            let _ = next.send(Turn {
+                lanes: self.lanes.clone(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::progress::CallOrigin;
+
+    fn waiting(lanes: &DeviceLanes) -> usize {
+        let state = lanes.state.lock().unwrap();
+        [&state.priority, &state.normal]
+            .iter()
+            .flat_map(|lane| lane.callers.iter())
+            .map(|(_, calls)| calls.len())
+            .sum()
+    }
+
+    /// Queue call `name` from `caller`, a verify_proofs call of `proofs` proofs if set, which
+    /// records its name when it gets the turn
+    async fn queue(
+        lanes: &Arc<DeviceLanes>,
+        caller: &str,
+        proofs: Option<usize>,
+        name: &'static str,
+        served: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> JoinHandle<()> {
+        let before = waiting(lanes);
+        let (device, served) = (lanes.clone(), served.clone());
+        let call = async move {
+            let _turn = device.enter().await;
+            served.lock().unwrap().push(name);
+        };
+        let origin = CallOrigin::new(caller.to_string(), None);
+        let handle = tokio::spawn(crate::progress::call_scope(origin, async move {
+            match proofs {
+                Some(proofs) => verify_scope(proofs, call).await,
+                None => call.await,
+            }
+        }));
+        while waiting(lanes) == before {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn callers_take_turns() {
+        let lanes = DeviceLanes::new(None);
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let turn = lanes.enter().await;
+        let handles = [
+            queue(&lanes, "a", None, "a1", &served).await,
+            queue(&lanes, "a", None, "a2", &served).await,
+            queue(&lanes, "a", None, "a3", &served).await,
+            queue(&lanes, "b", None, "b1", &served).await,
+            queue(&lanes, "b", None, "b2", &served).await,
+        ];
+        drop(turn);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), ["a1", "b1", "a2", "b2", "a3"]);
+        assert!(!lanes.state.lock().unwrap().busy);
+    }
+
+    #[tokio::test]
+    async fn small_verify_proofs_go_first_until_the_streak_ends() {
+        let lanes = DeviceLanes::new(Some(LaneConfig {
+            max_priority_proofs: 10,
+            max_streak: 2,
+        }));
+        let served = Arc::new(Mutex::new(Vec::new()));
+        let turn = lanes.enter().await;
+        let handles = [
+            queue(&lanes, "mint", None, "sign", &served).await,
+            queue(&lanes, "mint", Some(100), "large", &served).await,
+            queue(&lanes, "mint", Some(1), "small1", &served).await,
+            queue(&lanes, "mint", Some(10), "small2", &served).await,
+            queue(&lanes, "mint", Some(2), "small3", &served).await,
+        ];
+        drop(turn);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(
+            *served.lock().unwrap(),
+            ["small1", "small2", "sign", "small3", "large"]
+        );
+    }
+}
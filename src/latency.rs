@@ -0,0 +1,63 @@
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+/// Latency summary of one operation in microseconds.
+///
+/// Shared by the `bench` subcommand and `benches/operations.rs`, which includes this file with
+/// `#[path]`, so it may only use dependencies both have.
+#[derive(Serialize)]
+pub struct Stats {
+    pub operation: &'static str,
+    pub concurrency: usize,
+    pub n: u64,
+    pub errors: usize,
+    pub mean: f64,
+    pub stdev: f64,
+    pub min: u64,
+    pub max: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+}
+
+impl Stats {
+    pub fn new(
+        operation: &'static str,
+        concurrency: usize,
+        hist: &Histogram<u64>,
+        errors: usize,
+    ) -> Self {
+        Self {
+            operation,
+            concurrency,
+            n: hist.len(),
+            errors,
+            mean: hist.mean(),
+            stdev: hist.stdev(),
+            min: hist.min(),
+            max: hist.max(),
+            p50: hist.value_at_percentile(50.0),
+            p90: hist.value_at_percentile(90.0),
+            p99: hist.value_at_percentile(99.0),
+            p999: hist.value_at_percentile(99.9),
+        }
+    }
+}
+
+pub fn print_stats(stats: &Stats) {
+    println!("--- {} Benchmark Results ---", stats.operation);
+    println!("Mean:   {:.2} us", stats.mean);
+    println!("StdDev: {:.2} us", stats.stdev);
+    println!("Min:    {} us", stats.min);
+    println!("Max:    {} us", stats.max);
+    println!("50%:    {} us", stats.p50);
+    println!("90%:    {} us", stats.p90);
+    println!("99%:    {} us", stats.p99);
+    println!("99.9%:  {} us", stats.p999);
+    println!(
+        "n={} errors={} concurrency={}",
+        stats.n, stats.errors, stats.concurrency
+    );
+    println!()
+}
@@ -1,60 +1,1240 @@
-use std::net::SocketAddr;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use cdk_common::nuts::{CurrencyUnit, Id};
 use cdk_signatory::signatory::Signatory;
-use cdk_signatory::start_grpc_server;
-use clap::Parser;
-use tokio::sync::Mutex;
+use clap::{CommandFactory, Parser, Subcommand};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::prelude::*;
 
-use crate::signatory::TrezorSignatory;
+use crate::approval::Approver;
+use crate::audit::AuditLog;
+use crate::capabilities::DeviceCapabilities;
+use crate::config::{Config, DeviceConfig, LogFormat, LoggingConfig};
+use crate::device::TrezorDevice;
+use crate::fallback::SoftwareFallback;
+use crate::metrics::KeysetStats;
+use crate::passphrase::PassphraseSource;
+use crate::payload_trace::Redaction;
+use crate::pin::TerminalPinProvider;
+use crate::policy::SigningWindow;
+use crate::progress::ProgressEvents;
+use crate::queue::RequestQueue;
+use crate::quota::ClientQuotas;
+use crate::record::ExchangeRecorder;
+use crate::response_cache::ResponseCache;
+use crate::routing::{Backend, BackendConfig, Routes};
+use crate::server::Listener;
+use crate::signatory::{TrezorPool, TrezorSignatory};
+use crate::software::SoftwareSigner;
+use crate::standby::StandbyRole;
+use crate::telemetry::OtlpExport;
+use crate::trezor::{CallCancel, DeviceSelector, DeviceTransport, EMULATOR_ADDR, Interaction};
 
+mod admin;
+mod approval;
+mod attest;
+mod audit;
+mod auth;
+mod backend;
+mod bench;
+mod capabilities;
+mod certs;
+mod config;
+mod deadline;
+mod device;
+mod error;
+mod fallback;
+mod host_signer;
+mod hotplug;
+mod http;
+mod info;
+mod keyset_cache;
+mod lanes;
+mod latency;
 mod mapping;
+mod metrics;
+mod mint_check;
+mod mint_config;
+#[cfg(any(test, feature = "mock-device"))]
+mod mock;
+mod notify;
+mod operation;
+mod passphrase;
+mod payload_trace;
+mod pin;
+mod policy;
+mod progress;
+mod provision;
+mod queue;
+mod quota;
+mod record;
+mod response_cache;
+mod response_signing;
+mod rotation;
+mod routing;
+mod server;
 mod signatory;
+mod software;
+mod standby;
+mod state_file;
+mod systemd;
+mod telemetry;
+mod tls;
 mod trezor;
+mod version;
 
 #[derive(Parser)]
 #[command(name = "cdk-signatory-trezor")]
 #[command(version = "0.1.0")]
 #[command(about = "Trezor Signatory CLI for Cashu CDK")]
 struct Cli {
-    #[arg(long, default_value = "127.0.0.1")]
-    listen_addr: String,
-    #[arg(long, default_value = "15060")]
-    listen_port: u32,
-    #[arg(long)]
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// TOML config file, flags given on the command line take precedence
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, repeat to listen on several [default: 127.0.0.1]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_LISTEN_ADDR", value_delimiter = ',')]
+    listen_addr: Vec<String>,
+    /// Port to listen on [default: 15060]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_LISTEN_PORT")]
+    listen_port: Option<u32>,
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_TLS_DIR")]
     tls_dir: Option<PathBuf>,
+    /// CA that client certificates must be signed by [default: <tls_dir>/ca.pem]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_CLIENT_CA")]
+    client_ca: Option<PathBuf>,
+    /// SHA-256 fingerprint of a client certificate allowed to call the signatory, repeatable
+    #[arg(
+        long,
+        env = "CDK_SIGNATORY_TREZOR_ALLOWED_CLIENT_FINGERPRINT",
+        value_delimiter = ','
+    )]
+    allowed_client_fingerprint: Vec<String>,
+    /// Require the bearer token stored in this file on every signatory call
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_AUTH_TOKEN_FILE")]
+    auth_token_file: Option<PathBuf>,
+    /// Serve the admin service, requiring the bearer token stored in this file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ADMIN_TOKEN_FILE")]
+    admin_token_file: Option<PathBuf>,
+    /// Serve on a unix domain socket at this path instead of TCP
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_LISTEN_UNIX")]
+    listen_unix: Option<PathBuf>,
+    /// Octal permissions of the unix socket file, e.g. 660
+    #[arg(long, value_parser = parse_octal_mode, env = "CDK_SIGNATORY_TREZOR_UNIX_SOCKET_MODE")]
+    unix_socket_mode: Option<u32>,
+    /// Seconds to wait for in-flight requests on shutdown [default: 30]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+    /// Maximum signatory calls queued for the devices, 0 is unlimited [default: 64]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_MAX_QUEUE_DEPTH")]
+    max_queue_depth: Option<usize>,
+    /// Maximum calls queued per client certificate or address, 0 is unlimited [default: 0]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_MAX_CALLS_PER_CLIENT")]
+    max_calls_per_client: Option<usize>,
+    /// Serve gRPC server reflection for grpcurl and other debugging tools
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_GRPC_REFLECTION")]
+    grpc_reflection: bool,
+    /// Sign every signatory response with the hex secp256k1 secret key in this file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_RESPONSE_SIGNING_KEY_FILE")]
+    response_signing_key_file: Option<PathBuf>,
+    /// Serve keysets, status and, with --http-token-file, signing as JSON on this address
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_HTTP_LISTEN_ADDR")]
+    http_listen_addr: Option<String>,
+    /// Serve blind_sign and verify_proofs over HTTP, requiring the bearer token in this file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_HTTP_TOKEN_FILE")]
+    http_token_file: Option<PathBuf>,
+    /// Run as one of a hot standby pair, exchanging heartbeats with --standby-peer-addr
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_STANDBY_ROLE")]
+    standby_role: Option<StandbyRole>,
+    /// UDP address to receive the peer's heartbeats on
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_STANDBY_LISTEN_ADDR")]
+    standby_listen_addr: Option<String>,
+    /// UDP address to send heartbeats to
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_STANDBY_PEER_ADDR")]
+    standby_peer_addr: Option<String>,
+    /// File holding the key heartbeats are authenticated with, the same on both instances
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_STANDBY_KEY_FILE")]
+    standby_key_file: Option<PathBuf>,
+    /// Maximum number of blinded messages or proofs sent to the device per message [default: 32]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_MAX_BATCH_SIZE")]
+    max_batch_size: Option<NonZeroUsize>,
+    /// Verify the DLEQ proof of every signature returned by the device
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_VERIFY_DLEQ")]
+    verify_dleq: bool,
+    /// BIP32 account of the Cashu key tree on the device [default: firmware default]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ACCOUNT")]
+    account: Option<u32>,
+    /// Serve keysets and verify proofs only, refusing blind_sign and keyset rotation
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_WATCH_ONLY")]
+    watch_only: bool,
+    /// Check every request against the policy and log it, but never sign or use the device to verify
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_DRY_RUN")]
+    dry_run: bool,
+    /// Check proofs against the cached keysets before sending them to the device
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_PREVERIFY_PROOFS")]
+    preverify_proofs: bool,
+    /// Verify proofs carrying a DLEQ proof on the host instead of the device
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_HOST_VERIFY_PROOFS")]
+    host_verify_proofs: bool,
+    /// Check the P2PK and HTLC witnesses of locked proofs on the host before verifying them
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_VERIFY_SPENDING_CONDITIONS")]
+    verify_spending_conditions: bool,
+    /// Require a button press on the device for batches worth more than this many sats
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_CONFIRM_THRESHOLD_SATS")]
+    confirm_threshold_sats: Option<u64>,
+    /// POST batches above the approval thresholds here and sign only once approved
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_APPROVAL_URL")]
+    approval_url: Option<String>,
+    /// Batches worth more than this many sats need approval, other units are set in the
+    /// config file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_APPROVAL_THRESHOLD_SATS")]
+    approval_threshold_sats: Option<u64>,
+    /// Send the bearer token in this file to the approval service
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_APPROVAL_TOKEN_FILE")]
+    approval_token_file: Option<PathBuf>,
+    /// Confirm on the device instead of refusing while the approval service is unavailable
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_APPROVAL_DEVICE_FALLBACK")]
+    approval_device_fallback: bool,
+    /// Only blind_sign within these days and hours, e.g. "mon-fri 08:00-18:00 +01:00"
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_SIGNING_WINDOW")]
+    signing_window: Option<SigningWindow>,
+    /// Refuse rotations setting a higher input fee in parts per thousand
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ROTATION_MAX_INPUT_FEE_PPK")]
+    rotation_max_input_fee_ppk: Option<u64>,
+    /// Refuse rotations to keysets with a larger denomination
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ROTATION_MAX_AMOUNT")]
+    rotation_max_amount: Option<u64>,
+    /// Refuse rotations whose final expiry lies further than this many seconds ahead
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ROTATION_MAX_EXPIRY_SECS")]
+    rotation_max_expiry_secs: Option<u64>,
+    /// Only sign for this keyset id, repeat to allow several
+    #[arg(
+        long,
+        env = "CDK_SIGNATORY_TREZOR_ALLOWED_KEYSET",
+        value_delimiter = ','
+    )]
+    allowed_keyset: Vec<Id>,
+    /// Only sign and verify for keysets of this unit (e.g. sat), repeat to allow several
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_ALLOWED_UNIT", value_delimiter = ',')]
+    allowed_unit: Vec<CurrencyUnit>,
+    /// Interval in seconds between keyset refreshes from the device, 0 disables [default: 300]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_KEYSET_REFRESH_INTERVAL")]
+    keyset_refresh_interval: Option<u64>,
+    /// Seconds after which a device call is aborted, 0 disables [default: 60]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_CALL_TIMEOUT")]
+    call_timeout: Option<u64>,
+    /// Ping the device after this many idle seconds, 0 disables [default: 0]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_KEEPALIVE_INTERVAL")]
+    keepalive_interval: Option<u64>,
+    /// Attempts per device call when the USB/UDP link fails, including the first [default: 3]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_RETRY_ATTEMPTS")]
+    retry_attempts: Option<u32>,
+    /// verify_proofs calls of at most this many proofs go ahead of other calls waiting for
+    /// the device, 0 disables [default: 0]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_PRIORITY_VERIFY_MAX_PROOFS")]
+    priority_verify_max_proofs: Option<usize>,
+    /// Transport to find devices on: auto, usb, udp, udp:<host>:<port> or replay:<file>, or
+    /// mock when built with the mock-device feature [default: auto]
+    #[arg(
+        long,
+        conflicts_with = "emulator",
+        env = "CDK_SIGNATORY_TREZOR_TRANSPORT"
+    )]
+    transport: Option<DeviceTransport>,
+    /// Connect to the trezor-emulator on its default UDP port
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_EMULATOR")]
+    emulator: bool,
+    /// Refuse to start unless the keysets advertised by this mint are served by the device
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_MINT_URL")]
+    mint_url: Option<String>,
+    /// Only use the device with this serial number, repeat to sign with a pool of devices
+    #[arg(
+        long,
+        env = "CDK_SIGNATORY_TREZOR_DEVICE_SERIAL",
+        value_delimiter = ','
+    )]
+    device_serial: Vec<String>,
+    /// Only use the device with this label
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_DEVICE_LABEL")]
+    device_label: Option<String>,
+    /// BIP-39 mnemonic of the device, used to sign in software while no device is reachable
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_FALLBACK_SEED_FILE")]
+    fallback_seed_file: Option<PathBuf>,
+    /// Append every message exchanged with the devices to this file, replay it with
+    /// `--transport replay:<file>`
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_RECORD_FILE")]
+    record_file: Option<PathBuf>,
+    /// Seconds a confirmation may stay pending on the device before notifying [default: 30]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_NOTIFY_AFTER")]
+    notify_after: Option<u64>,
+    /// POST a JSON notification to this URL when a confirmation stays pending
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_NOTIFY_WEBHOOK")]
+    notify_webhook: Option<String>,
+    /// Show a desktop notification when a confirmation stays pending
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_NOTIFY_DESKTOP")]
+    notify_desktop: bool,
+    /// Mail this address through the local sendmail when a confirmation stays pending
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_NOTIFY_EMAIL")]
+    notify_email: Option<String>,
+    /// Rotate the keysets on this schedule, e.g. 30d or 12h
+    #[arg(long, value_parser = rotation::parse_interval, env = "CDK_SIGNATORY_TREZOR_ROTATE_EVERY")]
+    rotate_every: Option<Duration>,
+    /// Sign blind auth tokens in software with keys from the mnemonic in this file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_AUTH_SEED_FILE")]
+    auth_seed_file: Option<PathBuf>,
+    /// Passphrase of the hidden wallet to use
+    #[arg(long, conflicts_with_all = ["passphrase_prompt", "passphrase_on_device"], env = "CDK_SIGNATORY_TREZOR_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// Prompt for the passphrase on the terminal when the device asks for it
+    #[arg(
+        long,
+        conflicts_with = "passphrase_on_device",
+        env = "CDK_SIGNATORY_TREZOR_PASSPHRASE_PROMPT"
+    )]
+    passphrase_prompt: bool,
+    /// Enter the passphrase on the device itself
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_PASSPHRASE_ON_DEVICE")]
+    passphrase_on_device: bool,
+    /// `tracing` filter directives, overrides RUST_LOG
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_LOG_FILTER")]
+    log_filter: Option<String>,
+    /// Log output format [default: text]
+    #[arg(long, value_enum, env = "CDK_SIGNATORY_TREZOR_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+    /// Export tracing spans to this OTLP/gRPC collector, e.g. http://127.0.0.1:4317
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+    /// Log the hex payload of every message exchanged with the devices, for diagnosing
+    /// firmware mismatches
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_TRACE_PAYLOADS")]
+    trace_payloads: bool,
+    /// Fields blanked out of traced payloads [default: secrets]
+    #[arg(long, value_enum, env = "CDK_SIGNATORY_TREZOR_PAYLOAD_REDACTION")]
+    payload_redaction: Option<Redaction>,
+    /// Append a hash-chained record of every signing operation to this file
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+    /// Persist keysets to this file so they are served right away after a restart
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_KEYSET_CACHE_FILE")]
+    keyset_cache_file: Option<PathBuf>,
+    /// Keep the per-keyset signing statistics in this file across restarts
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_KEYSET_STATS_FILE")]
+    keyset_stats_file: Option<PathBuf>,
+    /// Number of recent blind_sign responses kept to answer retries, 0 disables [default: 0]
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_RESPONSE_CACHE_CAPACITY")]
+    response_cache_capacity: Option<usize>,
+    /// Keep the blind_sign response cache in this file across restarts
+    #[arg(long, env = "CDK_SIGNATORY_TREZOR_RESPONSE_CACHE_FILE")]
+    response_cache_file: Option<PathBuf>,
 }
 
-fn init_logging() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
+#[derive(Subcommand)]
+enum Command {
+    /// Check the hash chain of an audit log and exit
+    VerifyAuditLog {
+        /// Audit log to verify
+        path: PathBuf,
+    },
+    /// Check the response signature of a captured call against a pinned public key and exit
+    VerifyResponse {
+        /// Hex public key of the response signing key, as printed by print-mint-config
+        #[arg(long)]
+        pubkey: cdk_common::PublicKey,
+        /// Method path of the call, e.g. /cdk_signatory.Signatory/BlindSign
+        #[arg(long)]
+        method: String,
+        /// File holding the request body as sent on the wire
+        #[arg(long)]
+        request: PathBuf,
+        /// File holding the response body as sent on the wire
+        #[arg(long)]
+        response: PathBuf,
+        /// grpc-status trailer of the response
+        #[arg(long, default_value = "0")]
+        status: String,
+        /// Hex signature from the x-signatory-signature trailer
+        #[arg(long)]
+        signature: String,
+    },
+    /// Measure blind_sign and verify_proofs latency on the attached device and exit
+    Bench {
+        /// Number of blind_sign and verify_proofs calls
+        #[arg(long, default_value_t = 100)]
+        iterations: usize,
+        /// Blinded messages per call
+        #[arg(long, default_value_t = 1)]
+        batch_size: usize,
+    },
+    /// Create a CA, server and client certificate in --tls-dir and exit
+    GenCerts {
+        /// Additional name or address the server certificate is valid for, repeatable
+        #[arg(long)]
+        san: Vec<String>,
+        /// Replace existing certificates
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the cdk-mintd settings that connect a mint to this signatory and exit
+    PrintMintConfig {
+        /// Name or address the mint connects to, defaults to the first specific --listen-addr
+        #[arg(long)]
+        host: Option<String>,
+    },
+    /// Provision a keyset for a unit the device does not serve yet, e.g. usd, and exit
+    AddKeyset {
+        /// Currency unit of the new keyset
+        unit: CurrencyUnit,
+        /// Number of power of two denominations, starting at 1
+        #[arg(long, default_value_t = provision::DEFAULT_MAX_ORDER, value_parser = clap::value_parser!(u8).range(1..=64))]
+        max_order: u8,
+        /// Fee per input in parts per thousand
+        #[arg(long, default_value_t = 0)]
+        input_fee_ppk: u64,
+        /// Unix timestamp in seconds after which the keyset is no longer used
+        #[arg(long)]
+        final_expiry: Option<u64>,
+    },
+    /// Lock the devices of the running signatory through its admin service and exit
+    Lock,
+    /// Unlock the devices of the running signatory through its admin service and exit. The
+    /// PIN and passphrase are asked for where the signatory runs.
+    Unlock,
+    /// Write a device-signed attestation of the keysets to a file, to publish, and exit
+    Attest {
+        /// Attestation file, the signature is written next to it with a .sig extension
+        output: PathBuf,
+    },
+}
+
+impl Cli {
+    /// Load the config file if given and apply the flags set on the command line on top
+    fn load_config(&self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        if !self.listen_addr.is_empty() {
+            config.server.listen_addr = self.listen_addr.clone();
+        }
+        if let Some(listen_port) = self.listen_port {
+            config.server.listen_port = listen_port;
+        }
+        if let Some(tls_dir) = &self.tls_dir {
+            config.server.tls_dir = Some(tls_dir.clone());
+        }
+        if let Some(client_ca) = &self.client_ca {
+            config.server.client_ca = Some(client_ca.clone());
+        }
+        if !self.allowed_client_fingerprint.is_empty() {
+            config.server.allowed_client_fingerprints = self.allowed_client_fingerprint.clone();
+        }
+        if let Some(path) = &self.auth_token_file {
+            config.server.auth_token_file = Some(path.clone());
+        }
+        if let Some(path) = &self.admin_token_file {
+            config.server.admin_token_file = Some(path.clone());
+        }
+        if let Some(listen_unix) = &self.listen_unix {
+            config.server.listen_unix = Some(listen_unix.clone());
+        }
+        if let Some(mode) = self.unix_socket_mode {
+            config.server.unix_socket_mode = Some(mode);
+        }
+        if let Some(timeout) = self.shutdown_timeout {
+            config.server.shutdown_timeout = timeout;
+        }
+        if let Some(depth) = self.max_queue_depth {
+            config.server.max_queue_depth = depth;
+        }
+        if let Some(calls) = self.max_calls_per_client {
+            config.server.max_calls_per_client = calls;
+        }
+        if self.grpc_reflection {
+            config.server.reflection = true;
+        }
+        if let Some(path) = &self.response_signing_key_file {
+            config.server.response_signing_key_file = Some(path.clone());
+        }
+        if let Some(addr) = &self.http_listen_addr {
+            config.http.listen_addr = Some(addr.clone());
+        }
+        if let Some(path) = &self.http_token_file {
+            config.http.token_file = Some(path.clone());
+        }
+        if let Some(role) = self.standby_role {
+            config.standby.role = Some(role);
+        }
+        if let Some(addr) = &self.standby_listen_addr {
+            config.standby.listen_addr = Some(addr.clone());
+        }
+        if let Some(addr) = &self.standby_peer_addr {
+            config.standby.peer_addr = Some(addr.clone());
+        }
+        if let Some(path) = &self.standby_key_file {
+            config.standby.key_file = Some(path.clone());
+        }
+        if let Some(max_batch_size) = self.max_batch_size {
+            config.signing.max_batch_size = max_batch_size.get();
+        }
+        if self.verify_dleq {
+            config.signing.verify_dleq = true;
+        }
+        if let Some(account) = self.account {
+            config.signing.account = Some(account);
+        }
+        if self.watch_only {
+            config.signing.watch_only = true;
+        }
+        if self.dry_run {
+            config.signing.dry_run = true;
+        }
+        if self.preverify_proofs {
+            config.signing.preverify_proofs = true;
+        }
+        if self.host_verify_proofs {
+            config.signing.host_verify_proofs = true;
+        }
+        if self.verify_spending_conditions {
+            config.signing.verify_spending_conditions = true;
+        }
+        if let Some(threshold) = self.confirm_threshold_sats {
+            config.policy.confirm_threshold_sats = Some(threshold);
+        }
+        if let Some(window) = &self.signing_window {
+            config.policy.signing_window = Some(window.clone());
+        }
+        if let Some(url) = &self.approval_url {
+            config.approval.url = Some(url.clone());
+        }
+        if let Some(threshold) = self.approval_threshold_sats {
+            config
+                .approval
+                .thresholds
+                .insert("sat".to_string(), threshold);
+        }
+        if let Some(path) = &self.approval_token_file {
+            config.approval.token_file = Some(path.clone());
+        }
+        if self.approval_device_fallback {
+            config.approval.device_fallback = true;
+        }
+        if let Some(max) = self.rotation_max_input_fee_ppk {
+            config.policy.rotation_max_input_fee_ppk = Some(max);
+        }
+        if let Some(max) = self.rotation_max_amount {
+            config.policy.rotation_max_amount = Some(max);
+        }
+        if let Some(max) = self.rotation_max_expiry_secs {
+            config.policy.rotation_max_expiry_secs = Some(max);
+        }
+        if !self.allowed_keyset.is_empty() {
+            config.policy.allowed_keysets = self.allowed_keyset.clone();
+        }
+        if !self.allowed_unit.is_empty() {
+            config.policy.allowed_units = self.allowed_unit.clone();
+        }
+        if let Some(interval) = self.keyset_refresh_interval {
+            config.device.keyset_refresh_interval = interval;
+        }
+        if let Some(timeout) = self.call_timeout {
+            config.device.call_timeout = timeout;
+        }
+        if let Some(interval) = self.keepalive_interval {
+            config.device.keepalive_interval = interval;
+        }
+        if let Some(attempts) = self.retry_attempts {
+            config.device.retry_attempts = attempts;
+        }
+        if let Some(max_proofs) = self.priority_verify_max_proofs {
+            config.device.priority_verify_max_proofs = max_proofs;
+        }
+        if let Some(transport) = &self.transport {
+            config.device.transport = transport.clone();
+        }
+        if self.emulator {
+            config.device.transport = DeviceTransport::Udp(EMULATOR_ADDR.to_string());
+        }
+        if !self.device_serial.is_empty() {
+            config.device.serial = self.device_serial.clone();
+        }
+        if let Some(label) = &self.device_label {
+            config.device.label = Some(label.clone());
+        }
+        if let Some(url) = &self.mint_url {
+            config.device.mint_url = Some(url.clone());
+        }
+        if let Some(path) = &self.fallback_seed_file {
+            config.device.fallback_seed_file = Some(path.clone());
+        }
+        if let Some(path) = &self.record_file {
+            config.device.record_file = Some(path.clone());
+        }
+        if let Some(after) = self.notify_after {
+            config.notify.after_secs = after;
+        }
+        if let Some(url) = &self.notify_webhook {
+            config.notify.webhook_url = Some(url.clone());
+        }
+        if self.notify_desktop {
+            config.notify.desktop = true;
+        }
+        if let Some(email) = &self.notify_email {
+            config.notify.email = Some(email.clone());
+        }
+        if let Some(every) = self.rotate_every {
+            config.rotation.rotate_every = Some(every);
+        }
+        if let Some(path) = &self.auth_seed_file {
+            config.auth_signer.seed_file = Some(path.clone());
+        }
+        if let Some(path) = &self.keyset_stats_file {
+            config.metrics.keyset_stats_file = Some(path.clone());
+        }
+        if let Some(filter) = &self.log_filter {
+            config.logging.filter = Some(filter.clone());
+        }
+        if let Some(format) = self.log_format {
+            config.logging.format = format;
+        }
+        if let Some(endpoint) = &self.otlp_endpoint {
+            config.logging.otlp_endpoint = Some(endpoint.clone());
+        }
+        if self.trace_payloads {
+            config.logging.trace_payloads = true;
+        }
+        if let Some(redaction) = self.payload_redaction {
+            config.logging.payload_redaction = redaction;
+        }
+        if let Some(path) = &self.audit_log {
+            config.audit.path = Some(path.clone());
+        }
+        if let Some(path) = &self.keyset_cache_file {
+            config.device.keyset_cache_file = Some(path.clone());
+        }
+        if let Some(capacity) = self.response_cache_capacity {
+            config.response_cache.capacity = capacity;
+        }
+        if let Some(path) = &self.response_cache_file {
+            config.response_cache.state_file = Some(path.clone());
+        }
+
+        Ok(config)
+    }
+
+    fn passphrase_source(&self) -> PassphraseSource {
+        if let Some(passphrase) = &self.passphrase {
+            PassphraseSource::Static(passphrase.clone())
+        } else if self.passphrase_prompt {
+            PassphraseSource::prompt()
+        } else if self.passphrase_on_device {
+            PassphraseSource::OnDevice
+        } else {
+            PassphraseSource::Empty
+        }
+    }
+}
+
+/// Prefix of the environment variables setting the options, e.g. `CDK_SIGNATORY_TREZOR_TRANSPORT`
+const ENV_PREFIX: &str = "CDK_SIGNATORY_TREZOR_";
+
+/// Refuse variables with [`ENV_PREFIX`] that set no option, so a misspelled variable in a
+/// container definition does not leave the option at its default unnoticed
+fn check_env_vars() -> Result<()> {
+    let command = Cli::command();
+    let known: Vec<&OsStr> = command
+        .get_arguments()
+        .filter_map(|arg| arg.get_env())
+        .collect();
+    let mut unknown: Vec<String> = std::env::vars_os()
+        .filter_map(|(name, _)| name.into_string().ok())
+        .filter(|name| name.starts_with(ENV_PREFIX) && !known.contains(&OsStr::new(name)))
+        .collect();
+    if !unknown.is_empty() {
+        unknown.sort();
+        anyhow::bail!(
+            "unknown environment variables {}, see --help for the supported ones",
+            unknown.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn parse_octal_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .map_err(|e| format!("invalid octal mode {:?}: {}", value, e))
+}
+
+/// Set up the log output, returns the span exporter if one is configured
+fn init_logging(config: &LoggingConfig) -> Result<Option<OtlpExport>> {
+    let filter = match &config.filter {
+        Some(directives) => EnvFilter::try_new(directives)?,
+        None => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    };
+    let otlp = config
+        .otlp_endpoint
+        .as_deref()
+        .map(OtlpExport::new)
+        .transpose()?;
+    let output = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(output)
+        .with(otlp.as_ref().map(|otlp| otlp.layer()))
         .init();
+    if config.trace_payloads {
+        payload_trace::enable(config.payload_redaction);
+    }
+    Ok(otlp)
+}
+
+/// Refresh the keyset cache whenever the process receives SIGHUP
+fn spawn_refresh_on_sighup(signatory: TrezorSignatory) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, refreshing keysets");
+            if let Err(err) = signatory.refresh_keysets().await {
+                tracing::warn!("Failed to refresh keysets: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Toggle the paused state whenever the process receives SIGUSR1
+fn spawn_pause_on_sigusr1(signatory: TrezorSignatory) -> Result<()> {
+    let mut user1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while user1.recv().await.is_some() {
+            if signatory.is_paused() {
+                signatory.resume();
+            } else {
+                signatory.pause();
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Devices opened so far by selector, with the settings they were opened with
+type OpenedDevices = HashMap<String, (Arc<TrezorDevice>, String)>;
+
+/// Connect to the devices of `selectors`, which must share a seed, and ping them if configured.
+///
+/// Devices already in `opened` are reused, so tenants selecting the same device share it.
+/// They must configure it alike, as one connection cannot have two timeouts or retry policies.
+/// Calls of the pool answer interaction requests through `interaction` even on a device
+/// opened by another tenant.
+fn connect_pool(
+    selectors: Vec<DeviceSelector>,
+    config: &DeviceConfig,
+    interaction: &Interaction,
+    recorder: Option<&Arc<ExchangeRecorder>>,
+    opened: &mut OpenedDevices,
+) -> Result<TrezorPool> {
+    let settings = format!(
+        "call timeout {:?}, {:?}, priority lanes {:?}, keepalive {:?}, record file {:?}",
+        config.call_timeout(),
+        config.retry_policy(),
+        config.priority_lanes(),
+        config.keepalive_interval(),
+        config.record_file,
+    );
+    let mut devices = Vec::with_capacity(selectors.len());
+    for selector in selectors {
+        // the display form leaves out the transport
+        let key = format!("{:?}", selector);
+        if let Some((device, opened_with)) = opened.get(&key) {
+            if *opened_with != settings {
+                anyhow::bail!(
+                    "device {} is shared with different [device] settings: {} here, {} where \
+                     it was first opened",
+                    selector,
+                    settings,
+                    opened_with
+                );
+            }
+            devices.push(device.clone());
+            continue;
+        }
+        let device = Arc::new(
+            TrezorDevice::connect(
+                selector,
+                interaction.clone(),
+                config.call_timeout(),
+                config.retry_policy(),
+                recorder.cloned(),
+            )?
+            .with_priority_lanes(config.priority_lanes()),
+        );
+        if let Some(interval) = config.keepalive_interval() {
+            device.spawn_keepalive(interval);
+        }
+        opened.insert(key, (device.clone(), settings.clone()));
+        devices.push(device);
+    }
+    Ok(TrezorPool::new(devices)?.with_interaction(interaction.clone()))
+}
+
+/// Resolves on the first SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(err) => {
+            tracing::warn!("Failed to install SIGTERM handler: {}", err);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
 
 #[tokio::main]
 pub async fn main() -> Result<()> {
-    init_logging();
-
     let args: Cli = Cli::parse();
+    check_env_vars()?;
+
+    if let Some(Command::VerifyAuditLog { path }) = &args.command {
+        let verified = audit::verify(path)?;
+        println!(
+            "{}: {} entries, chain intact, last hash {}",
+            path.display(),
+            verified.entries,
+            verified.last_hash
+        );
+        return Ok(());
+    }
+
+    if let Some(Command::VerifyResponse {
+        pubkey,
+        method,
+        request,
+        response,
+        status,
+        signature,
+    }) = &args.command
+    {
+        let read = |path: &PathBuf| {
+            std::fs::read(path).with_context(|| format!("reading {}", path.display()))
+        };
+        response_signing::verify(
+            pubkey,
+            method,
+            &read(request)?,
+            &read(response)?,
+            status.as_bytes(),
+            signature,
+        )?;
+        println!("Signature valid for {} by {}", method, pubkey.to_hex());
+        return Ok(());
+    }
+
+    let config = args.load_config()?;
+
+    if let Some(Command::GenCerts { san, force }) = &args.command {
+        let Some(tls_dir) = &config.server.tls_dir else {
+            anyhow::bail!("gen-certs needs --tls-dir or tls_dir in the config file");
+        };
+        let names = certs::server_names(&config.server.listen_addr, san);
+        certs::generate(tls_dir, &names, *force)?;
+        println!(
+            "Certificates for {} written to {}, give ca.pem, client.pem and client.key to the mint",
+            names.join(", "),
+            tls_dir.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(Command::PrintMintConfig { host }) = &args.command {
+        print!("{}", mint_config::render(&config.server, host.as_deref())?);
+        return Ok(());
+    }
+
+    if let Some(command @ (Command::Lock | Command::Unlock)) = &args.command {
+        let locked = admin::set_locked(&config.server, matches!(command, Command::Lock)).await?;
+        println!(
+            "Devices {}",
+            match locked {
+                true => "locked",
+                false => "unlocked",
+            }
+        );
+        return Ok(());
+    }
+
+    let otlp = init_logging(&config.logging)?;
+
+    let tenants = if config.tenants.is_empty() {
+        vec![(None, config)]
+    } else {
+        if args.command.is_some() {
+            anyhow::bail!(
+                "subcommands act on one signatory, pass the tenant's config file instead"
+            );
+        }
+        let mut tenants = Vec::new();
+        for tenant in &config.tenants {
+            tenants.push((Some(tenant.name.clone()), Config::load(&tenant.config)?));
+        }
+        tenants
+    };
+    let mut devices = HashMap::new();
+    let mut instances = Vec::new();
+    for (name, config) in tenants {
+        if let Some(name) = &name {
+            tracing::info!("Starting tenant {}", name);
+        }
+        // each tenant is asked for its own passphrase, a prompted one is not reused for others
+        let passphrase = Arc::new(args.passphrase_source());
+        let interaction = Interaction {
+            pin: Arc::new(TerminalPinProvider),
+            passphrase: passphrase.clone(),
+            progress: ProgressEvents::default(),
+            session: None,
+            cancel: CallCancel::default(),
+            device: String::new(),
+            call: None,
+        };
+        notify::spawn_notifier(config.notify.clone(), &interaction.progress);
+        let signatory = build_signatory(&config, &interaction, &mut devices)
+            .await
+            .with_context(|| match &name {
+                Some(name) => format!("starting tenant {}", name),
+                None => "starting the signatory".to_string(),
+            })?;
+        instances.push((name, config, interaction, passphrase, signatory));
+    }
+
+    if let [(None, _, _, _, signatory)] = instances.as_slice() {
+        if let Some(Command::Bench {
+            iterations,
+            batch_size,
+        }) = &args.command
+        {
+            return bench::run(signatory, *iterations, *batch_size).await;
+        }
+
+        if let Some(Command::AddKeyset {
+            unit,
+            max_order,
+            input_fee_ppk,
+            final_expiry,
+        }) = &args.command
+        {
+            let keyset = provision::add_unit(
+                signatory,
+                unit.clone(),
+                provision::power_of_two_amounts(*max_order),
+                *input_fee_ppk,
+                *final_expiry,
+            )
+            .await?;
+            println!(
+                "Added {} keyset {} with {} amounts, restart or refresh the mint to serve it",
+                keyset.unit,
+                keyset.id,
+                keyset.amounts.len()
+            );
+            return Ok(());
+        }
 
-    let mut trezor = trezor_client::unique(false)?;
-    trezor.init_device(None)?;
+        if let Some(Command::Attest { output }) = &args.command {
+            let sig_path = attest::run(signatory, output).await?;
+            println!(
+                "Attestation written to {}, its signature to {}",
+                output.display(),
+                sig_path.display()
+            );
+            return Ok(());
+        }
+    }
 
-    let mut signatory = TrezorSignatory::new(Arc::new(Mutex::new(trezor))).await?;
-    signatory.update_cached_keysets().await?;
+    // socket activation hands over one listener, which goes to a single signatory only
+    let mut activated = if instances.len() == 1 {
+        systemd::activated_listener()?
+    } else {
+        None
+    };
+    let drain_timeout = instances
+        .iter()
+        .map(|(_, config, _, _, _)| Duration::from_secs(config.server.shutdown_timeout))
+        .max()
+        .unwrap_or_default();
+    let mut pools: Vec<Arc<TrezorPool>> = Vec::new();
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut servers = Vec::new();
+    for (name, config, interaction, passphrase, signatory) in instances {
+        pools.extend(signatory.pools().cloned());
+        // shared by the gRPC services and the HTTP bridge, so neither can bypass its limits
+        let queue = RequestQueue::new(
+            config.server.max_queue_depth,
+            config.server.max_calls_per_client,
+            Duration::from_secs(config.server.retry_after),
+        );
+        let signatory =
+            start_serving(signatory, &config, &interaction, &passphrase, queue.clone()).await?;
+        let listener = match (activated.take(), &config.server.listen_unix) {
+            (Some(activated), _) => activated,
+            (None, Some(path)) => Listener::Unix {
+                path: path.clone(),
+                mode: config.server.unix_socket_mode,
+            },
+            (None, None) => Listener::Tcp(config.server.listen_addrs()?),
+        };
+        let mut shutdown = shutdown_rx.clone();
+        servers.push(async move {
+            server::serve(
+                signatory,
+                interaction.progress.clone(),
+                listener,
+                &config.server,
+                queue,
+                async move {
+                    let _ = shutdown.changed().await;
+                },
+            )
+            .await
+            .with_context(|| match &name {
+                Some(name) => format!("serving tenant {}", name),
+                None => "serving the signatory".to_string(),
+            })
+        });
+    }
+    let server = futures::future::try_join_all(servers);
+    tokio::pin!(server);
 
-    let socket_addr = SocketAddr::from_str(&format!("{}:{}", args.listen_addr, args.listen_port))?;
+    tokio::select! {
+        res = &mut server => return res.map(|_| ()),
+        _ = shutdown_signal() => {}
+    }
 
-    start_grpc_server(Arc::new(signatory), socket_addr, args.tls_dir).await?;
+    systemd::notify_stopping();
+    tracing::info!(
+        "Shutting down, waiting up to {:?} for in-flight requests",
+        drain_timeout
+    );
+    let _ = shutdown_tx.send(());
+    let started = Instant::now();
+    match tokio::time::timeout(drain_timeout, &mut server).await {
+        Ok(res) => {
+            res?;
+        }
+        Err(_) => tracing::warn!("In-flight requests did not finish in time"),
+    }
+
+    let remaining = drain_timeout.saturating_sub(started.elapsed());
+    futures::future::join_all(pools.iter().map(|pool| pool.close(remaining))).await;
+    tracing::info!("Shutdown complete");
+    if let Some(otlp) = otlp {
+        otlp.shutdown();
+    }
 
     Ok(())
 }
+
+/// Connect the devices and backends of `config` and load the keysets
+async fn build_signatory(
+    config: &Config,
+    interaction: &Interaction,
+    devices: &mut OpenedDevices,
+) -> Result<TrezorSignatory> {
+    let recorder = match &config.device.record_file {
+        Some(path) => {
+            tracing::warn!("Recording device exchanges to {}", path.display());
+            Some(Arc::new(ExchangeRecorder::open(path)?))
+        }
+        None => None,
+    };
+
+    let pool = connect_pool(
+        config.device.selectors(),
+        &config.device,
+        interaction,
+        recorder.as_ref(),
+        devices,
+    )?;
+    let mut routes = Routes::default();
+    for (name, backend, units) in config.routing.routed_backends()? {
+        let routed = match backend {
+            BackendConfig::Device { .. } => Backend::Pool(Arc::new(connect_pool(
+                backend.selectors(&config.device.transport),
+                &config.device,
+                interaction,
+                recorder.as_ref(),
+                devices,
+            )?)),
+            BackendConfig::Software {
+                seed_file,
+                state_file,
+            } => Backend::Software(Arc::new(SoftwareSigner::load(
+                seed_file,
+                state_file.clone(),
+                units.clone(),
+                config.signing.account,
+            )?)),
+        };
+        tracing::info!("Serving {:?} from backend {:?}", units, name);
+        routes.add(units, routed);
+    }
+    if let Some(software) = SoftwareSigner::for_auth(&config.auth_signer, config.signing.account)? {
+        routes.add(
+            vec![CurrencyUnit::Auth],
+            Backend::Software(Arc::new(software)),
+        );
+    }
+    // fail with a clear diagnostic now rather than on the first signing request
+    let mut options = config.signing.clone();
+    let routed_devices = routes.pools().flat_map(|(pool, _)| pool.devices());
+    for device in pool.devices().iter().chain(routed_devices) {
+        let selector = device.selector().to_string();
+        let capabilities = DeviceCapabilities::discover(device).await?;
+        capabilities.check(&selector, &options)?;
+        options.max_batch_size = capabilities.limit_batch_size(&selector, options.max_batch_size);
+    }
+
+    let mut signatory = TrezorSignatory::new(
+        Arc::new(pool),
+        options,
+        config.policy.clone(),
+        config.limits.clone(),
+    )
+    .await?
+    .with_routes(routes);
+    if let Some(path) = &config.audit.path {
+        signatory = signatory.with_audit_log(Arc::new(AuditLog::open(path)?));
+    }
+    if let Some(approver) = Approver::new(&config.approval)? {
+        signatory = signatory.with_approver(Arc::new(approver));
+    }
+    if let Some(quotas) = ClientQuotas::new(&config.client_quotas) {
+        if config.server.tls_dir.is_none() {
+            anyhow::bail!(
+                "client quotas count calls by client certificate, which needs tls_dir, \
+                 calls without one are refused"
+            );
+        }
+        if config.server.listen_unix.is_some() {
+            tracing::warn!(
+                "Client quotas are enforced, calls over the unix socket have no client \
+                 certificate and are refused"
+            );
+        }
+        signatory = signatory.with_client_quotas(Arc::new(quotas));
+    }
+    if let Some(path) = &config.metrics.keyset_stats_file {
+        signatory = signatory.with_keyset_stats(KeysetStats::load(path.clone())?);
+    }
+    if config.response_cache.capacity > 0 {
+        signatory =
+            signatory.with_response_cache(ResponseCache::load(config.response_cache.clone())?);
+    }
+    let mut seeded = false;
+    if let Some(path) = &config.device.keyset_cache_file {
+        (signatory, seeded) = signatory.with_keyset_cache_file(path.clone()).await?;
+    }
+
+    if seeded {
+        // serve the persisted keysets right away and reconcile with the devices in the background
+        let signatory = signatory.clone();
+        tokio::spawn(async move {
+            let res = match signatory.check_pool_consistency().await {
+                Ok(()) => signatory.update_cached_keysets().await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = res {
+                tracing::error!("Failed to verify cached keysets with the device: {}", err);
+            }
+        });
+    } else {
+        signatory.check_pool_consistency().await?;
+        signatory.update_cached_keysets().await?;
+    }
+    Ok(signatory)
+}
+
+/// Check the keysets against the mint, add the software fallback and start the background
+/// tasks and side servers of a signatory about to be served
+async fn start_serving(
+    mut signatory: TrezorSignatory,
+    config: &Config,
+    interaction: &Interaction,
+    passphrase: &PassphraseSource,
+    queue: RequestQueue,
+) -> Result<Arc<TrezorSignatory>> {
+    if let Some(mint_url) = &config.device.mint_url {
+        // check against the device itself, not keysets restored from the cache file
+        let keysets = signatory.fetch_keysets().await?;
+        mint_check::check_against_mint(mint_url, &keysets).await?;
+    }
+
+    if let Some(path) = &config.device.fallback_seed_file {
+        // a passphrase only entered on the device or the terminal would have to be guessed,
+        // and a wrong one derives keys of a different wallet
+        let Some(passphrase) = passphrase.known() else {
+            anyhow::bail!(
+                "fallback_seed_file needs the passphrase given with --passphrase, not prompted \
+                 for or entered on the device"
+            );
+        };
+        let keysets = signatory.keysets().await?;
+        let fallback =
+            SoftwareFallback::from_seed_file(path, passphrase, config.signing.account, &keysets)?;
+        signatory = signatory.with_fallback(Arc::new(fallback));
+    }
+
+    if config.device.keyset_refresh_interval > 0 {
+        signatory.spawn_keyset_refresh(Duration::from_secs(config.device.keyset_refresh_interval));
+    }
+    signatory.spawn_refresh_on_reconnect();
+    if config.device.usb_hotplug {
+        // hotplug only speeds up noticing a replug, so a host where libusb cannot start, e.g.
+        // a container without /dev/bus/usb, still serves
+        if let Err(err) = hotplug::spawn(
+            signatory.devices().cloned().collect(),
+            signatory.metrics.clone(),
+            interaction.progress.clone(),
+        ) {
+            tracing::warn!(
+                "Not watching USB hotplug events, unplugged devices are noticed on the next call: {:#}",
+                err
+            );
+        }
+    }
+    if config.signing.watch_only || config.signing.dry_run {
+        if config.rotation.rotate_every.is_some() {
+            tracing::warn!("Scheduled keyset rotation is disabled in watch-only and dry-run mode");
+        }
+    } else {
+        rotation::spawn_scheduled_rotation(signatory.clone(), config.rotation.clone())?;
+    }
+    spawn_refresh_on_sighup(signatory.clone())?;
+    spawn_pause_on_sigusr1(signatory.clone())?;
+
+    let signatory = Arc::new(signatory);
+    http::spawn(signatory.clone(), &config.http, &config.server, queue).await?;
+    standby::spawn(signatory.clone(), &config.standby).await?;
+    Ok(signatory)
+}
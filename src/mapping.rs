@@ -1,7 +1,10 @@
+use std::str::FromStr;
+
+use crate::error::TrezorSignatoryError;
 use anyhow::Result;
-use cdk_common::nuts::{BlindSignature, BlindedMessage, CurrencyUnit, Id, Keys, Proof};
-use cdk_common::{Amount, BlindSignatureDleq, Error, PublicKey, SecretKey};
-use cdk_signatory::signatory::{SignatoryKeySet, SignatoryKeysets};
+use cdk_common::nuts::{BlindSignature, BlindedMessage, CurrencyUnit, Id, Keys, Proof, Witness};
+use cdk_common::{Amount, BlindSignatureDleq, Error, ProofDleq, PublicKey, SecretKey};
+use cdk_signatory::signatory::{RotateKeyArguments, SignatoryKeySet, SignatoryKeysets};
 use protobuf::MessageField;
 use trezor_client::{TrezorResponse, protos};
 
@@ -16,7 +19,9 @@ pub trait TryIntoCdk<T> {
 /// Helper to extract a required field from Option with a descriptive error
 #[inline]
 fn required<T>(opt: Option<T>, field: &str) -> Result<T, Error> {
-    opt.ok_or_else(|| Error::Custom(format!("missing required field: {}", field)))
+    opt.ok_or_else(|| {
+        TrezorSignatoryError::Mapping(format!("missing required field: {}", field)).into()
+    })
 }
 
 impl TryIntoCdk<BlindSignatureDleq> for protos::BlindSignatureDLEQ {
@@ -33,7 +38,9 @@ impl TryIntoCdk<BlindSignature> for protos::BlindSignature {
         Ok(BlindSignature {
             amount: required(self.amount, "amount")?.into(),
             keyset_id: Id::from_bytes(&required(self.keyset_id, "keyset_id")?)?,
-            c: PublicKey::from_slice(&required(self.blinded_secret, "blinded_secret")?)?,
+            c: PublicKey::from_slice(&required(self.blinded_secret, "blinded_secret")?).map_err(
+                |e| TrezorSignatoryError::Mapping(format!("signature is not a valid point: {}", e)),
+            )?,
             dleq: self
                 .dleq
                 .into_option()
@@ -52,12 +59,60 @@ impl TryIntoCdk<Vec<BlindSignature>> for protos::CashuBlindSignResponse {
     }
 }
 
+// Every field CDK hands the signatory is forwarded. NUT-20 quote signatures are not among them:
+// they sign the whole mint request, which the mint verifies before calling `blind_sign` with
+// the bare blinded messages, so there is nothing for the firmware to check.
 impl TryIntoCdk<protos::BlindedMessage> for BlindedMessage {
     fn try_into_cdk(self) -> Result<protos::BlindedMessage, Error> {
         Ok(protos::BlindedMessage {
             amount: Some(self.amount.into()),
             keyset_id: Some(self.keyset_id.to_bytes()),
             blinded_secret: Some(self.blinded_secret.to_bytes().to_vec()),
+            witness: MessageField::from_option(self.witness.map(|w| w.try_into_cdk()).transpose()?),
+            special_fields: Default::default(),
+        })
+    }
+}
+
+impl TryIntoCdk<protos::Witness> for Witness {
+    fn try_into_cdk(self) -> Result<protos::Witness, Error> {
+        let mut witness = protos::Witness::new();
+        match self {
+            Witness::P2PKWitness(p2pk) => {
+                witness.signatures = p2pk.signatures;
+            }
+            Witness::HTLCWitness(htlc) => {
+                witness.set_preimage(htlc.preimage);
+                witness.signatures = htlc.signatures.unwrap_or_default();
+            }
+        }
+        Ok(witness)
+    }
+}
+
+impl TryIntoCdk<protos::CurrencyUnit> for CurrencyUnit {
+    fn try_into_cdk(self) -> Result<protos::CurrencyUnit, Error> {
+        Ok(protos::CurrencyUnit {
+            currency_unit: Some(match self {
+                CurrencyUnit::Sat => protos::currency_unit::Currency_unit::Unit(
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_SAT.into(),
+                ),
+                CurrencyUnit::Msat => protos::currency_unit::Currency_unit::Unit(
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_MSAT.into(),
+                ),
+                CurrencyUnit::Usd => protos::currency_unit::Currency_unit::Unit(
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_USD.into(),
+                ),
+                CurrencyUnit::Eur => protos::currency_unit::Currency_unit::Unit(
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_EUR.into(),
+                ),
+                CurrencyUnit::Auth => protos::currency_unit::Currency_unit::Unit(
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_AUTH.into(),
+                ),
+                CurrencyUnit::Custom(s) => protos::currency_unit::Currency_unit::CustomUnit(s),
+                // units added to CDK after this mapping travel by name, like custom units
+                other => protos::currency_unit::Currency_unit::CustomUnit(other.to_string()),
+            }),
             special_fields: Default::default(),
         })
     }
@@ -68,30 +123,7 @@ impl TryIntoCdk<protos::KeySet> for SignatoryKeySet {
     fn try_into_cdk(self) -> Result<protos::KeySet, Error> {
         Ok(protos::KeySet {
             id: Some(self.id.to_bytes()),
-            unit: MessageField::some(protos::CurrencyUnit {
-                currency_unit: Some(match self.unit {
-                    CurrencyUnit::Sat => protos::currency_unit::Currency_unit::Unit(
-                        protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_SAT.into(),
-                    ),
-                    CurrencyUnit::Msat => protos::currency_unit::Currency_unit::Unit(
-                        protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_MSAT.into(),
-                    ),
-                    CurrencyUnit::Usd => protos::currency_unit::Currency_unit::Unit(
-                        protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_USD.into(),
-                    ),
-                    CurrencyUnit::Eur => protos::currency_unit::Currency_unit::Unit(
-                        protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_EUR.into(),
-                    ),
-                    CurrencyUnit::Auth => protos::currency_unit::Currency_unit::Unit(
-                        protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_AUTH.into(),
-                    ),
-                    CurrencyUnit::Custom(s) => protos::currency_unit::Currency_unit::CustomUnit(s),
-                    _ => {
-                        return Err(Error::UnsupportedUnit);
-                    }
-                }),
-                special_fields: Default::default(),
-            }),
+            unit: MessageField::some(self.unit.try_into_cdk()?),
             active: Some(self.active),
             input_fee_ppk: Some(self.input_fee_ppk),
             keys: MessageField::some(protos::Keys {
@@ -111,38 +143,56 @@ impl TryIntoCdk<protos::KeySet> for SignatoryKeySet {
 
 impl TryIntoCdk<SignatoryKeysets> for protos::SignatoryKeysets {
     fn try_into_cdk(self) -> Result<SignatoryKeysets, Error> {
+        let mut keysets = Vec::with_capacity(self.keysets.len());
+        for ks in self.keysets {
+            let id = ks.id.as_deref().map(hex::encode).unwrap_or_default();
+            let unit = ks.unit.clone();
+            match ks.try_into_cdk() {
+                Ok(keyset) => keysets.push(keyset),
+                // newer firmware may serve units the host does not know yet, which must not
+                // make the keysets of the known units unusable
+                Err(Error::UnsupportedUnit) => {
+                    tracing::warn!("Skipping keyset {} of unsupported unit {:?}", id, unit)
+                }
+                Err(err) => return Err(err),
+            }
+        }
         Ok(SignatoryKeysets {
             pubkey: PublicKey::from_slice(&required(self.pubkey, "pubkey")?)?,
-            keysets: self
-                .keysets
-                .into_iter()
-                .map(|ks| ks.try_into_cdk())
-                .collect::<Result<Vec<_>, _>>()?,
+            keysets,
         })
     }
 }
 
-impl TryIntoCdk<SignatoryKeySet> for protos::KeySet {
-    fn try_into_cdk(self) -> Result<SignatoryKeySet, Error> {
-        let unit = required(self.unit.into_option(), "unit")?;
-        let currency_unit = match unit.currency_unit {
+impl TryIntoCdk<CurrencyUnit> for protos::CurrencyUnit {
+    fn try_into_cdk(self) -> Result<CurrencyUnit, Error> {
+        match self.currency_unit {
             Some(protos::currency_unit::Currency_unit::Unit(u)) => {
                 match u.enum_value_or_default() {
-                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_SAT => CurrencyUnit::Sat,
-                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_MSAT => CurrencyUnit::Msat,
-                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_USD => CurrencyUnit::Usd,
-                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_EUR => CurrencyUnit::Eur,
-                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_AUTH => CurrencyUnit::Auth,
-                    _ => return Err(Error::UnsupportedUnit),
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_SAT => Ok(CurrencyUnit::Sat),
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_MSAT => Ok(CurrencyUnit::Msat),
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_USD => Ok(CurrencyUnit::Usd),
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_EUR => Ok(CurrencyUnit::Eur),
+                    protos::CurrencyUnitType::CURRENCY_UNIT_TYPE_AUTH => Ok(CurrencyUnit::Auth),
+                    _ => Err(Error::UnsupportedUnit),
                 }
             }
-            Some(protos::currency_unit::Currency_unit::CustomUnit(s)) => CurrencyUnit::Custom(s),
-            err => {
-                return Err(Error::Custom(
-                    format!("missing or invalid currency unit: {:?}", err).to_string(),
-                ));
+            // parsed so that names of units known to CDK map back to their variant
+            Some(protos::currency_unit::Currency_unit::CustomUnit(s)) => {
+                CurrencyUnit::from_str(&s).map_err(|_| Error::UnsupportedUnit)
             }
-        };
+            err => Err(TrezorSignatoryError::Mapping(format!(
+                "missing or invalid currency unit: {:?}",
+                err
+            ))
+            .into()),
+        }
+    }
+}
+
+impl TryIntoCdk<SignatoryKeySet> for protos::KeySet {
+    fn try_into_cdk(self) -> Result<SignatoryKeySet, Error> {
+        let currency_unit = required(self.unit.into_option(), "unit")?.try_into_cdk()?;
 
         let keys_proto = required(self.keys.into_option(), "keys")?;
         let keys_map: std::collections::BTreeMap<Amount, PublicKey> = keys_proto
@@ -175,7 +225,145 @@ impl TryIntoCdk<protos::Proof> for Proof {
             keyset_id: Some(self.keyset_id.to_bytes()),
             secret: Some(self.secret.as_bytes().to_vec()),
             c: Some(self.c.to_bytes().to_vec()),
+            witness: MessageField::from_option(self.witness.map(|w| w.try_into_cdk()).transpose()?),
+            dleq: MessageField::from_option(self.dleq.map(|d| d.try_into_cdk()).transpose()?),
             special_fields: Default::default(),
         })
     }
 }
+
+impl TryIntoCdk<protos::BlindSignature> for BlindSignature {
+    fn try_into_cdk(self) -> Result<protos::BlindSignature, Error> {
+        Ok(protos::BlindSignature {
+            amount: Some(self.amount.into()),
+            keyset_id: Some(self.keyset_id.to_bytes()),
+            blinded_secret: Some(self.c.to_bytes().to_vec()),
+            dleq: MessageField::from_option(self.dleq.map(|d| d.try_into_cdk()).transpose()?),
+            special_fields: Default::default(),
+        })
+    }
+}
+
+impl TryIntoCdk<protos::BlindSignatureDLEQ> for BlindSignatureDleq {
+    fn try_into_cdk(self) -> Result<protos::BlindSignatureDLEQ, Error> {
+        Ok(protos::BlindSignatureDLEQ {
+            e: Some(self.e.to_secret_bytes().to_vec()),
+            s: Some(self.s.to_secret_bytes().to_vec()),
+            special_fields: Default::default(),
+        })
+    }
+}
+
+impl TryIntoCdk<protos::ProofDLEQ> for ProofDleq {
+    fn try_into_cdk(self) -> Result<protos::ProofDLEQ, Error> {
+        Ok(protos::ProofDLEQ {
+            e: Some(self.e.to_secret_bytes().to_vec()),
+            s: Some(self.s.to_secret_bytes().to_vec()),
+            r: Some(self.r.to_secret_bytes().to_vec()),
+            special_fields: Default::default(),
+        })
+    }
+}
+
+impl TryIntoCdk<protos::CashuRotateKeyset> for RotateKeyArguments {
+    fn try_into_cdk(self) -> Result<protos::CashuRotateKeyset, Error> {
+        if self.amounts.is_empty() {
+            return Err(Error::Custom(
+                "rotation requires at least one amount".to_string(),
+            ));
+        }
+        Ok(protos::CashuRotateKeyset {
+            unit: MessageField::some(self.unit.try_into_cdk()?),
+            amounts: self.amounts,
+            input_fee_ppk: Some(self.input_fee_ppk),
+            final_expiry: self.final_expiry,
+            special_fields: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(unit: CurrencyUnit) -> CurrencyUnit {
+        let proto: protos::CurrencyUnit = unit.try_into_cdk().expect("unit maps to the device");
+        proto.try_into_cdk().expect("unit maps back")
+    }
+
+    #[test]
+    fn known_units_round_trip() {
+        for unit in [
+            CurrencyUnit::Sat,
+            CurrencyUnit::Msat,
+            CurrencyUnit::Usd,
+            CurrencyUnit::Eur,
+            CurrencyUnit::Auth,
+        ] {
+            assert_eq!(round_trip(unit.clone()), unit);
+        }
+    }
+
+    #[test]
+    fn unknown_unit_round_trips_as_custom() {
+        let unit = CurrencyUnit::from_str("gbp").unwrap();
+        assert!(matches!(unit, CurrencyUnit::Custom(_)));
+
+        let proto: protos::CurrencyUnit = unit.clone().try_into_cdk().unwrap();
+        assert!(matches!(
+            &proto.currency_unit,
+            Some(protos::currency_unit::Currency_unit::CustomUnit(name)) if name == "gbp"
+        ));
+        assert_eq!(proto.try_into_cdk().ok(), Some(unit));
+    }
+
+    #[test]
+    fn custom_name_of_known_unit_maps_to_its_variant() {
+        let proto = protos::CurrencyUnit {
+            currency_unit: Some(protos::currency_unit::Currency_unit::CustomUnit(
+                "sat".to_string(),
+            )),
+            ..Default::default()
+        };
+        assert_eq!(proto.try_into_cdk().ok(), Some(CurrencyUnit::Sat));
+    }
+
+    #[test]
+    fn missing_unit_is_rejected() {
+        let proto = protos::CurrencyUnit::default();
+        assert!(TryIntoCdk::<CurrencyUnit>::try_into_cdk(proto).is_err());
+    }
+
+    #[test]
+    fn keysets_of_unknown_units_are_skipped() {
+        let mut mock = crate::mock::MockDevice::new().unwrap();
+        let served = crate::mock::testing::keysets(&mut mock);
+        let mut proto = protos::SignatoryKeysets {
+            pubkey: Some(served.pubkey.to_bytes().to_vec()),
+            keysets: served
+                .keysets
+                .iter()
+                .map(|keyset| keyset.clone().try_into_cdk().unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        let mut newer: protos::KeySet = proto.keysets[0].clone();
+        newer.unit = MessageField::some(protos::CurrencyUnit {
+            currency_unit: Some(protos::currency_unit::Currency_unit::Unit(
+                protobuf::EnumOrUnknown::from_i32(1000),
+            )),
+            ..Default::default()
+        });
+        proto.keysets.push(newer);
+
+        let keysets: SignatoryKeysets = proto.try_into_cdk().unwrap();
+        assert_eq!(keysets.keysets.len(), served.keysets.len());
+        assert!(
+            keysets
+                .keysets
+                .iter()
+                .zip(&served.keysets)
+                .all(|(a, b)| a.id == b.id && a.unit == b.unit)
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use cdk_common::Error;
+use cdk_common::nuts::{BlindedMessage, Id};
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+
+use crate::error::failure_reason;
+use crate::state_file;
+
+/// Counters of signatory calls since start
+#[derive(Debug, Default)]
+pub struct SignatoryMetrics {
+    blind_sign_calls: AtomicU64,
+    blinded_messages: AtomicU64,
+    verify_proofs_calls: AtomicU64,
+    proofs: AtomicU64,
+    failed_calls: AtomicU64,
+    /// Failed calls by [`failure_reason`]
+    failures: Mutex<BTreeMap<&'static str, u64>>,
+    usb_attached: AtomicU64,
+    usb_detached: AtomicU64,
+}
+
+impl SignatoryMetrics {
+    pub fn record_blind_sign<T>(&self, messages: usize, result: &Result<T, Error>) {
+        self.blind_sign_calls.fetch_add(1, Ordering::Relaxed);
+        self.blinded_messages
+            .fetch_add(messages as u64, Ordering::Relaxed);
+        self.record_outcome(result);
+    }
+
+    pub fn record_verify_proofs<T>(&self, proofs: usize, result: &Result<T, Error>) {
+        self.verify_proofs_calls.fetch_add(1, Ordering::Relaxed);
+        self.proofs.fetch_add(proofs as u64, Ordering::Relaxed);
+        self.record_outcome(result);
+    }
+
+    /// A Trezor was plugged in (`attached`) or unplugged
+    pub fn record_usb_event(&self, attached: bool) {
+        let counter = match attached {
+            true => &self.usb_attached,
+            false => &self.usb_detached,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_outcome<T>(&self, result: &Result<T, Error>) {
+        if let Err(err) = result {
+            self.failed_calls.fetch_add(1, Ordering::Relaxed);
+            *self
+                .failures
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(failure_reason(err))
+                .or_default() += 1;
+        }
+    }
+
+    /// Failed calls by reason, e.g. `cancelled` or `device_fault`
+    pub fn failures(&self) -> Vec<(&'static str, u64)> {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.iter().map(|(reason, n)| (*reason, *n)).collect()
+    }
+
+    /// Current value of every counter by name
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            (
+                "blind_sign_calls",
+                self.blind_sign_calls.load(Ordering::Relaxed),
+            ),
+            (
+                "blinded_messages",
+                self.blinded_messages.load(Ordering::Relaxed),
+            ),
+            (
+                "verify_proofs_calls",
+                self.verify_proofs_calls.load(Ordering::Relaxed),
+            ),
+            ("proofs", self.proofs.load(Ordering::Relaxed)),
+            ("failed_calls", self.failed_calls.load(Ordering::Relaxed)),
+            ("usb_attached", self.usb_attached.load(Ordering::Relaxed)),
+            ("usb_detached", self.usb_detached.load(Ordering::Relaxed)),
+        ]
+    }
+}
+
+/// Blind signatures issued for one keyset
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct KeysetCounters {
+    pub signatures: u64,
+    /// Sum of the signed amounts in the keyset's unit
+    pub amount: u64,
+}
+
+/// How often changed keyset statistics are written to their state file
+const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Issuance per keyset id, optionally kept in a file across restarts.
+///
+/// Recording only updates the counters in memory; a background task writes them to the state
+/// file every [`STATS_FLUSH_INTERVAL`] if they changed, so a crash loses at most the
+/// signatures counted since the last write.
+#[derive(Debug, Default)]
+pub struct KeysetStats {
+    counters: Mutex<BTreeMap<Id, KeysetCounters>>,
+    /// Set when the counters changed since they were last written
+    dirty: AtomicBool,
+}
+
+impl KeysetStats {
+    /// Restore the counters from `state_file` if it exists, and start the task writing them
+    /// there from now on
+    pub fn load(state_file: PathBuf) -> Result<Arc<Self>, Error> {
+        let counters = if state_file.exists() {
+            let contents = std::fs::read(&state_file)
+                .map_err(|e| Error::Custom(format!("reading {}: {}", state_file.display(), e)))?;
+            serde_json::from_slice(&contents)
+                .map_err(|e| Error::Custom(format!("parsing {}: {}", state_file.display(), e)))?
+        } else {
+            BTreeMap::new()
+        };
+        let stats = Arc::new(Self {
+            counters: Mutex::new(counters),
+            dirty: AtomicBool::new(false),
+        });
+        tokio::spawn(flush_stats(Arc::downgrade(&stats), state_file));
+        Ok(stats)
+    }
+
+    /// Count the signatures issued for `blinded_messages`
+    pub fn record(&self, blinded_messages: &[BlindedMessage]) {
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        for bm in blinded_messages {
+            let entry = counters.entry(bm.keyset_id).or_default();
+            entry.signatures += 1;
+            entry.amount = entry.amount.saturating_add(u64::from(bm.amount));
+        }
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    pub fn snapshot(&self) -> Vec<(Id, KeysetCounters)> {
+        let counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        counters.iter().map(|(id, c)| (*id, *c)).collect()
+    }
+}
+
+/// Write the counters of `stats` to `path` whenever they changed, until `stats` is dropped
+async fn flush_stats(stats: Weak<KeysetStats>, path: PathBuf) {
+    let mut ticker = tokio::time::interval(STATS_FLUSH_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let Some(stats) = stats.upgrade() else {
+            return;
+        };
+        if !stats.dirty.swap(false, Ordering::SeqCst) {
+            continue;
+        }
+        let counters: BTreeMap<Id, KeysetCounters> = stats.snapshot().into_iter().collect();
+        drop(stats);
+        let result = match serde_json::to_vec(&counters) {
+            Ok(contents) => state_file::write(&path, contents).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result, bail};
+use cdk_common::nuts::{KeysResponse, KeysetResponse};
+use cdk_signatory::signatory::SignatoryKeysets;
+
+/// Check that the keysets a mint advertises are the ones served by the device.
+///
+/// Every keyset listed by the mint must exist on the device with the same unit and keys,
+/// otherwise the signatory would sign for a different key tree than the mint publishes.
+pub async fn check_against_mint(mint_url: &str, device: &SignatoryKeysets) -> Result<()> {
+    let base = mint_url.trim_end_matches('/');
+    let client = reqwest::Client::new();
+    let keysets: KeysetResponse = client
+        .get(format!("{}/v1/keysets", base))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("fetching keysets from {}", base))?
+        .json()
+        .await
+        .context("parsing mint keysets")?;
+    let keys: KeysResponse = client
+        .get(format!("{}/v1/keys", base))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("fetching keys from {}", base))?
+        .json()
+        .await
+        .context("parsing mint keys")?;
+
+    for info in &keysets.keysets {
+        let Some(ours) = device.keysets.iter().find(|ks| ks.id == info.id) else {
+            bail!(
+                "mint advertises keyset {} that the device does not serve",
+                info.id
+            );
+        };
+        if ours.unit != info.unit {
+            bail!(
+                "keyset {} has unit {} at the mint but {} on the device",
+                info.id,
+                info.unit,
+                ours.unit
+            );
+        }
+        if ours.active != info.active {
+            tracing::warn!(
+                "Keyset {} is {} at the mint but {} on the device",
+                info.id,
+                if info.active { "active" } else { "inactive" },
+                if ours.active { "active" } else { "inactive" }
+            );
+        }
+    }
+    for keyset in &keys.keysets {
+        let ours = device.keysets.iter().find(|ks| ks.id == keyset.id);
+        if ours.is_none_or(|ours| ours.keys != keyset.keys) {
+            bail!(
+                "mint publishes keys for keyset {} that differ from the device",
+                keyset.id
+            );
+        }
+    }
+
+    tracing::info!(
+        "Mint {} advertises {} keysets, all served by the device",
+        base,
+        keysets.keysets.len()
+    );
+    Ok(())
+}
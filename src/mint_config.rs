@@ -0,0 +1,120 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::config::ServerConfig;
+use crate::response_signing;
+
+/// Files cdk-mintd reads from its `signatory_certs` directory
+const MINT_FILES: [&str; 3] = ["ca.pem", "client.pem", "client.key"];
+
+/// `[info]` section of a cdk-mintd config connecting to this signatory as configured.
+///
+/// `host` is the name the mint connects to, by default the first listen address that is not
+/// unspecified. Configurations the mint cannot connect with, such as a unix socket, a bearer
+/// token or a client certificate outside `allowed_client_fingerprints`, are refused. The public
+/// key of the response signing key is printed for the mint to pin.
+pub fn render(config: &ServerConfig, host: Option<&str>) -> Result<String> {
+    if config.listen_unix.is_some() {
+        anyhow::bail!("cdk-mintd connects over TCP, unset listen_unix");
+    }
+    if config.auth_token_file.is_some() {
+        anyhow::bail!("cdk-mintd cannot send a bearer token, unset auth_token_file");
+    }
+    let host = match host {
+        Some(host) => host.to_string(),
+        None => config
+            .listen_addr
+            .iter()
+            .find(|addr| {
+                !addr
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_unspecified())
+            })
+            .cloned()
+            .unwrap_or_else(|| "localhost".to_string()),
+    };
+    let host = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]", host),
+        _ => host,
+    };
+
+    let mut out = String::from("# cdk-mintd settings for cdk-signatory-trezor\n[info]\n");
+    if let Some(path) = &config.response_signing_key_file {
+        let pubkey = response_signing::load_key(path)?.public_key();
+        writeln!(
+            out,
+            "# responses are signed by {}, pin it in the mint, do not take it from Info",
+            pubkey.to_hex()
+        )?;
+    }
+    let Some(tls_dir) = &config.tls_dir else {
+        writeln!(
+            out,
+            "signatory_url = \"http://{}:{}\"",
+            host, config.listen_port
+        )?;
+        return Ok(out);
+    };
+    let tls_dir = tls_dir
+        .canonicalize()
+        .with_context(|| format!("resolving {}", tls_dir.display()))?;
+    for file in MINT_FILES {
+        if !tls_dir.join(file).exists() {
+            anyhow::bail!(
+                "{} is missing, create the certificates with gen-certs",
+                tls_dir.join(file).display()
+            );
+        }
+    }
+    let server = fingerprint(&tls_dir.join("server.pem"))?;
+    let client = fingerprint(&tls_dir.join("client.pem"))?;
+    let allowed: Vec<String> = config
+        .allowed_client_fingerprints
+        .iter()
+        .map(|fp| fp.replace(':', "").to_ascii_lowercase())
+        .collect();
+    if !allowed.is_empty() && !allowed.contains(&client) {
+        anyhow::bail!(
+            "client.pem ({}) is not in allowed_client_fingerprints, the mint would be rejected",
+            client
+        );
+    }
+
+    writeln!(
+        out,
+        "signatory_url = \"https://{}:{}\"",
+        host, config.listen_port
+    )?;
+    writeln!(
+        out,
+        "# ca.pem, client.pem and client.key are read from this directory"
+    )?;
+    writeln!(out, "signatory_certs = {:?}", tls_dir.display().to_string())?;
+    writeln!(out, "# server certificate SHA-256: {}", server)?;
+    writeln!(out, "# client certificate SHA-256: {}", client)?;
+    if let Some(client_ca) = &config.client_ca {
+        writeln!(
+            out,
+            "# client certificates are checked against {}, not ca.pem",
+            client_ca.display()
+        )?;
+    }
+    Ok(out)
+}
+
+/// SHA-256 of the first certificate in the PEM file at `path`, as `allowed_client_fingerprints`
+/// takes it
+fn fingerprint(path: &Path) -> Result<String> {
+    let pem = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let cert = rustls_pemfile::certs(&mut pem.as_slice())
+        .next()
+        .with_context(|| format!("{} holds no certificate", path.display()))?
+        .with_context(|| format!("parsing {}", path.display()))?;
+    Ok(hex::encode(Sha256::digest(cert.as_ref())))
+}
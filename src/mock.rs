@@ -0,0 +1,226 @@
+use bip39::Mnemonic;
+use cdk_common::nuts::{CurrencyUnit, Id};
+use cdk_common::{Amount, Error, PublicKey};
+use protobuf::MessageField;
+use trezor_client::protos::features::Capability;
+use trezor_client::{TrezorMessage, protos};
+
+use crate::backend::{CashuDevice, Exchange, RawMessage};
+use crate::error::TrezorSignatoryError;
+use crate::host_signer::HostSigner;
+use crate::mapping::TryIntoCdk;
+use crate::trezor::Interaction;
+
+/// Mnemonic the mock derives its keys from. It is public, never use the mock for real value.
+pub const TEST_MNEMONIC: &str =
+    "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+/// NUTs the mock implements, DLEQ proofs included
+const SUPPORTED_NUTS: [u32; 4] = [0, 1, 2, 12];
+
+/// In-process stand-in for a Trezor running the Cashu app.
+///
+/// Signs with BDHKE like the firmware, with keys derived from [`TEST_MNEMONIC`] by the CDK
+/// scheme, so signatory logic can be exercised without hardware. It starts with one sat
+/// keyset, never prompts and ignores the account. Rotations are lost when it is re-opened.
+pub struct MockDevice {
+    signer: HostSigner,
+    features: protos::Features,
+}
+
+impl MockDevice {
+    pub fn new() -> Result<Self, Error> {
+        let mnemonic = Mnemonic::parse(TEST_MNEMONIC)
+            .map_err(|e| Error::Custom(format!("invalid test mnemonic: {}", e)))?;
+        let mut signer = HostSigner::new(&mnemonic.to_seed_normalized(""), None)?;
+        let amounts: Vec<u64> = (0..32).map(|i| 1 << i).collect();
+        signer.rotate(CurrencyUnit::Sat, &amounts, 0, None)?;
+
+        let mut features = protos::Features::new();
+        features.set_vendor("trezor.io".to_string());
+        features.set_major_version(2);
+        features.set_minor_version(99);
+        features.set_patch_version(0);
+        features.set_device_id("MOCK".to_string());
+        features.set_label("mock".to_string());
+        features.set_unlocked(true);
+        features.capabilities = vec![Capability::Capability_Cashu.into()];
+
+        Ok(Self { signer, features })
+    }
+
+    fn keysets(&self) -> Result<protos::SignatoryKeysets, Error> {
+        let mut proto = protos::SignatoryKeysets::new();
+        proto.set_pubkey(self.signer.pubkey().to_bytes().to_vec());
+        proto.keysets = self
+            .signer
+            .keysets()
+            .iter()
+            .map(|k| k.info().try_into_cdk())
+            .collect::<Result<_, Error>>()?;
+        Ok(proto)
+    }
+
+    fn blind_sign(
+        &self,
+        req: protos::CashuBlindSign,
+    ) -> Result<protos::CashuBlindSignResponse, Error> {
+        let mut resp = protos::CashuBlindSignResponse::new();
+        for message in req.blinded_messages {
+            let signature = self.signer.sign(
+                Id::from_bytes(message.keyset_id())?,
+                Amount::from(message.amount()),
+                &PublicKey::from_slice(message.blinded_secret())?,
+            )?;
+            resp.sigs.push(signature.try_into_cdk()?);
+        }
+        Ok(resp)
+    }
+
+    fn verify_proofs(&self, req: protos::CashuVerifyProofs) -> Result<protos::Success, Error> {
+        for proof in req.proofs.proof.iter() {
+            self.signer.verify(
+                Id::from_bytes(proof.keyset_id())?,
+                proof.amount().into(),
+                PublicKey::from_slice(proof.c())?,
+                proof.secret(),
+            )?;
+        }
+        Ok(protos::Success::new())
+    }
+
+    fn rotate_keyset(
+        &mut self,
+        req: protos::CashuRotateKeyset,
+    ) -> Result<protos::CashuRotateKeysetResponse, Error> {
+        let unit = req
+            .unit
+            .into_option()
+            .ok_or(TrezorSignatoryError::Mapping(
+                "missing unit in rotation".to_string(),
+            ))?
+            .try_into_cdk()?;
+        let keyset =
+            self.signer
+                .rotate(unit, &req.amounts, req.input_fee_ppk(), req.final_expiry)?;
+        let mut resp = protos::CashuRotateKeysetResponse::new();
+        resp.keyset = MessageField::some(keyset.info().try_into_cdk()?);
+        Ok(resp)
+    }
+
+    fn handle(&mut self, req: RawMessage) -> Result<RawMessage, Error> {
+        match req.message_type {
+            t if t == protos::GetFeatures::MESSAGE_TYPE => RawMessage::encode(&self.features),
+            // the mock has no PIN, locking is accepted and has no effect
+            t if t == protos::LockDevice::MESSAGE_TYPE => {
+                RawMessage::encode(&protos::Success::new())
+            }
+            t if t == protos::CashuGetInfo::MESSAGE_TYPE => {
+                let mut info = protos::CashuInfo::new();
+                info.supported_nuts = SUPPORTED_NUTS.to_vec();
+                RawMessage::encode(&info)
+            }
+            t if t == protos::CashuGetKeysets::MESSAGE_TYPE => {
+                let mut resp = protos::CashuGetKeysetsResponse::new();
+                resp.keysets = MessageField::some(self.keysets()?);
+                RawMessage::encode(&resp)
+            }
+            t if t == protos::CashuBlindSign::MESSAGE_TYPE => {
+                RawMessage::encode(&self.blind_sign(req.decode()?)?)
+            }
+            t if t == protos::CashuVerifyProofs::MESSAGE_TYPE => {
+                RawMessage::encode(&self.verify_proofs(req.decode()?)?)
+            }
+            t if t == protos::CashuRotateKeyset::MESSAGE_TYPE => {
+                RawMessage::encode(&self.rotate_keyset(req.decode()?)?)
+            }
+            other => Err(TrezorSignatoryError::Mapping(format!(
+                "mock device does not handle {:?}",
+                other
+            ))
+            .into()),
+        }
+    }
+}
+
+impl CashuDevice for MockDevice {
+    fn features(&self) -> Option<&protos::Features> {
+        Some(&self.features)
+    }
+
+    fn init_device(&mut self, _session_id: Option<&[u8]>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn call(&mut self, req: RawMessage, _interaction: &Interaction) -> Exchange<RawMessage> {
+        Exchange::Done(self.handle(req))
+    }
+
+    fn end_session(&mut self) {}
+}
+
+/// What the mock serves and signs, for unit tests of the checks around device calls
+#[cfg(test)]
+pub mod testing {
+    use cdk_common::SecretKey;
+    use cdk_common::nuts::{BlindSignature, BlindedMessage};
+    use cdk_signatory::signatory::SignatoryKeysets;
+
+    use super::*;
+
+    /// Keysets served by `mock`, initially one active sat keyset of powers of two
+    pub fn keysets(mock: &mut MockDevice) -> SignatoryKeysets {
+        let resp: protos::CashuGetKeysetsResponse = mock
+            .handle(RawMessage::encode(&protos::CashuGetKeysets::new()).unwrap())
+            .and_then(|resp| resp.decode())
+            .unwrap();
+        resp.keysets.unwrap().try_into_cdk().unwrap()
+    }
+
+    /// Blinded message of `amount` for `keyset_id` with a random blinded secret
+    pub fn blinded_message(keyset_id: Id, amount: u64) -> BlindedMessage {
+        BlindedMessage::new(
+            Amount::from(amount),
+            keyset_id,
+            SecretKey::generate().public_key(),
+        )
+    }
+
+    /// Signatures of `mock` on `messages`, as the device would return them
+    pub fn blind_sign(mock: &mut MockDevice, messages: &[BlindedMessage]) -> Vec<BlindSignature> {
+        let mut req = protos::CashuBlindSign::new();
+        req.blinded_messages = messages
+            .iter()
+            .map(|message| message.clone().try_into_cdk().unwrap())
+            .collect();
+        let resp: protos::CashuBlindSignResponse = mock
+            .handle(RawMessage::encode(&req).unwrap())
+            .and_then(|resp| resp.decode())
+            .unwrap();
+        resp.try_into_cdk().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_with_the_keys_it_serves() {
+        let mut mock = MockDevice::new().unwrap();
+        let keysets = testing::keysets(&mut mock);
+        let keyset = &keysets.keysets[0];
+        let messages = [
+            testing::blinded_message(keyset.id, 1),
+            testing::blinded_message(keyset.id, 8),
+        ];
+        let signatures = testing::blind_sign(&mut mock, &messages);
+        assert_eq!(signatures.len(), messages.len());
+        for (message, signature) in messages.iter().zip(&signatures) {
+            assert_eq!(signature.keyset_id, keyset.id);
+            assert_eq!(signature.amount, message.amount);
+            let key = keyset.keys.amount_key(message.amount).unwrap();
+            assert!(signature.verify_dleq(key, message.blinded_secret).is_ok());
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+use crate::progress::ProgressEvents;
+use crate::progress::proto::progress_event::State;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Seconds a confirmation may stay pending on the device before the operator is notified
+    pub after_secs: u64,
+    /// POST a JSON notification to this URL
+    pub webhook_url: Option<String>,
+    /// Show a desktop notification with `notify-send`
+    pub desktop: bool,
+    /// Mail this address through the local `sendmail`
+    pub email: Option<String>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            after_secs: 30,
+            webhook_url: None,
+            desktop: false,
+            email: None,
+        }
+    }
+}
+
+impl NotifyConfig {
+    fn is_enabled(&self) -> bool {
+        self.webhook_url.is_some() || self.desktop || self.email.is_some()
+    }
+}
+
+/// Notify the operator when a confirmation stays pending on the device for too long.
+///
+/// Every device showing a confirmation has its own deadline, so a screen answered on one
+/// device neither cancels nor delays the notification for another. Notifications are sent
+/// once per pending confirmation, failures to deliver them are logged.
+pub fn spawn_notifier(config: NotifyConfig, progress: &ProgressEvents) {
+    if !config.is_enabled() {
+        return;
+    }
+    let mut events = progress.subscribe();
+    let after = Duration::from_secs(config.after_secs);
+    tokio::spawn(async move {
+        // device -> when to notify about its pending confirmation and its button request
+        let mut pending: HashMap<String, (Instant, String)> = HashMap::new();
+        loop {
+            let next = pending
+                .iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(device, (at, _))| (device.clone(), *at));
+            let fire = async {
+                match &next {
+                    Some((_, at)) => tokio::time::sleep_until(*at).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(tagged) => {
+                        let device = tagged.event.device.clone();
+                        match tagged.event.state() {
+                            // a device shows one screen at a time, a new one restarts the wait
+                            State::AwaitingConfirmation => {
+                                pending.insert(
+                                    device,
+                                    (Instant::now() + after, tagged.event.button_request),
+                                );
+                            }
+                            State::ConfirmationAnswered | State::DeviceDetached => {
+                                pending.remove(&device);
+                            }
+                            State::DeviceAttached | State::Unspecified => {}
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!("Notifier skipped {} progress events", skipped);
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = fire => {
+                    if let Some((device, _)) = next {
+                        if let Some((_, button_request)) = pending.remove(&device) {
+                            notify(&config, after, &device, &button_request).await;
+                        }
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn notify(config: &NotifyConfig, after: Duration, device: &str, button_request: &str) {
+    let message = format!(
+        "The Trezor ({}) has been waiting for confirmation ({}) for {} seconds",
+        device,
+        button_request,
+        after.as_secs()
+    );
+    tracing::warn!("{}", message);
+
+    if let Some(url) = &config.webhook_url {
+        if let Err(err) = send_webhook(url, device, button_request, &message).await {
+            tracing::error!("Failed to send webhook notification: {:#}", err);
+        }
+    }
+    if config.desktop {
+        if let Err(err) = send_desktop(&message).await {
+            tracing::error!("Failed to show desktop notification: {:#}", err);
+        }
+    }
+    if let Some(to) = &config.email {
+        if let Err(err) = send_email(to, &message).await {
+            tracing::error!("Failed to send email notification: {:#}", err);
+        }
+    }
+}
+
+async fn send_webhook(url: &str, device: &str, button_request: &str, message: &str) -> Result<()> {
+    reqwest::Client::new()
+        .post(url)
+        .json(&serde_json::json!({
+            "event": "awaiting_confirmation",
+            "device": device,
+            "button_request": button_request,
+            "message": message,
+        }))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("posting to {}", url))?;
+    Ok(())
+}
+
+async fn send_desktop(message: &str) -> Result<()> {
+    let status = Command::new("notify-send")
+        .args(["--urgency=critical", "Trezor signatory", message])
+        .status()
+        .await
+        .context("running notify-send")?;
+    anyhow::ensure!(status.success(), "notify-send exited with {}", status);
+    Ok(())
+}
+
+async fn send_email(to: &str, message: &str) -> Result<()> {
+    let mut child = Command::new("sendmail")
+        .args(["-t", "-oi"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("running sendmail")?;
+    let mail = format!(
+        "To: {}\nSubject: Trezor signatory awaiting confirmation\n\n{}\n",
+        to, message
+    );
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(mail.as_bytes())
+            .await
+            .context("writing to sendmail")?;
+    }
+    let status = child.wait().await.context("waiting for sendmail")?;
+    anyhow::ensure!(status.success(), "sendmail exited with {}", status);
+    Ok(())
+}
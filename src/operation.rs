@@ -0,0 +1,49 @@
+use std::future::Future;
+
+use protobuf::Enum;
+use tonic::Status;
+use tonic::codegen::http::HeaderMap;
+use trezor_client::protos;
+
+/// Metadata key naming the mint operation a call is part of, e.g. `mint`, `swap` or `melt`
+pub const OPERATION_HEADER: &str = "x-cdk-operation";
+
+tokio::task_local! {
+    /// Operation the call being served is part of
+    static OPERATION: protos::Operation;
+}
+
+/// Run `fut` as part of `operation`, if the caller named one
+pub async fn scope<F: Future>(operation: Option<protos::Operation>, fut: F) -> F::Output {
+    match operation {
+        Some(operation) => OPERATION.scope(operation, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Operation of the call being served, `OPERATION_UNSPECIFIED` if the caller named none
+pub fn current() -> protos::Operation {
+    OPERATION
+        .try_with(|operation| *operation)
+        .unwrap_or(protos::Operation::OPERATION_UNSPECIFIED)
+}
+
+/// Operation named in [`OPERATION_HEADER`], any value of the firmware's `Operation` enum
+/// without its `OPERATION_` prefix, in any case.
+///
+/// Like the version check this is opt-in: the cdk gRPC client does not send the entry, so only
+/// mints that add it, e.g. with an interceptor, have their calls shown and checked by the
+/// device per operation.
+pub fn from_headers(headers: &HeaderMap) -> Result<Option<protos::Operation>, Status> {
+    let Some(value) = headers.get(OPERATION_HEADER) else {
+        return Ok(None);
+    };
+    let name = value
+        .to_str()
+        .map_err(|_| Status::invalid_argument(format!("{} is not valid text", OPERATION_HEADER)))?;
+    protos::Operation::from_str(&format!("OPERATION_{}", name.trim().to_ascii_uppercase()))
+        .map(Some)
+        .ok_or_else(|| {
+            Status::invalid_argument(format!("{} {:?} is not known", OPERATION_HEADER, name))
+        })
+}
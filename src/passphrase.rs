@@ -0,0 +1,77 @@
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+use cdk_common::Error;
+
+use crate::error::TrezorSignatoryError;
+
+/// How a passphrase request from the device should be answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PassphraseAnswer {
+    /// Send the passphrase from the host
+    Host(String),
+    /// Let the user type the passphrase on the device itself
+    OnDevice,
+}
+
+/// Source of passphrases for devices with passphrase protection (hidden wallets)
+pub trait PassphraseProvider: Send + Sync {
+    fn get_passphrase(&self) -> Result<PassphraseAnswer, Error>;
+}
+
+/// Passphrase configuration selected by the operator
+pub enum PassphraseSource {
+    /// Standard wallet, answered with an empty passphrase
+    Empty,
+    /// Fixed passphrase supplied on start
+    Static(String),
+    /// Ask on the terminal the first time the device requests it, then reuse
+    Prompt(Mutex<Option<String>>),
+    /// Entered by the user on the device
+    OnDevice,
+}
+
+impl PassphraseSource {
+    pub fn prompt() -> Self {
+        Self::Prompt(Mutex::new(None))
+    }
+
+    /// The passphrase if it is given on the host up front, `None` if it is asked for on the
+    /// terminal or entered on the device
+    pub fn known(&self) -> Option<&str> {
+        match self {
+            Self::Empty => Some(""),
+            Self::Static(passphrase) => Some(passphrase),
+            Self::Prompt(_) | Self::OnDevice => None,
+        }
+    }
+}
+
+impl PassphraseProvider for PassphraseSource {
+    fn get_passphrase(&self) -> Result<PassphraseAnswer, Error> {
+        match self {
+            Self::Empty => Ok(PassphraseAnswer::Host(String::new())),
+            Self::Static(passphrase) => Ok(PassphraseAnswer::Host(passphrase.clone())),
+            Self::OnDevice => Ok(PassphraseAnswer::OnDevice),
+            Self::Prompt(cached) => {
+                let mut cached = cached.lock().map_err(|_| {
+                    TrezorSignatoryError::Interaction("Passphrase cache poisoned".to_string())
+                })?;
+                if let Some(passphrase) = cached.as_ref() {
+                    return Ok(PassphraseAnswer::Host(passphrase.clone()));
+                }
+                if !io::stdin().is_terminal() {
+                    return Err(TrezorSignatoryError::Interaction(
+                        "Device requested a passphrase but no terminal is attached".to_string(),
+                    )
+                    .into());
+                }
+                let passphrase = rpassword::prompt_password("Enter passphrase: ").map_err(|e| {
+                    TrezorSignatoryError::Interaction(format!("Failed to read passphrase: {}", e))
+                })?;
+                *cached = Some(passphrase.clone());
+                Ok(PassphraseAnswer::Host(passphrase))
+            }
+        }
+    }
+}
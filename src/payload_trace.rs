@@ -0,0 +1,156 @@
+use std::sync::OnceLock;
+
+use cdk_common::Error;
+use protobuf::MessageField;
+use serde::Deserialize;
+use trezor_client::protos;
+
+use crate::backend::{CashuDevice, Exchange, RawMessage};
+use crate::trezor::Interaction;
+
+/// What is blanked out of traced payloads. Blanked bytes are zeroed rather than removed, so
+/// field presence and lengths still show in the trace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Redaction {
+    /// Proof secrets, signatures, witnesses and DLEQ blinding factors, anything that could
+    /// spend or link ecash
+    #[default]
+    Secrets,
+    /// Also blinded messages and blind signatures, leaving amounts, keyset ids and metadata
+    Points,
+    /// Nothing, only for devices holding test seeds
+    None,
+}
+
+static TRACE: OnceLock<Redaction> = OnceLock::new();
+
+/// Log every message exchanged with the devices opened from now on, redacted by `redaction`
+pub fn enable(redaction: Redaction) {
+    if TRACE.set(redaction).is_ok() {
+        tracing::warn!(
+            "Tracing device payloads with {:?} redaction, turn this off once done",
+            redaction
+        );
+    }
+}
+
+/// Wrap `inner` so its exchanges are traced under the name `device`, if tracing is enabled
+pub fn wrap(device: String, inner: Box<dyn CashuDevice>) -> Box<dyn CashuDevice> {
+    match TRACE.get() {
+        Some(&redaction) => Box::new(TracingDevice {
+            inner,
+            device,
+            redaction,
+        }),
+        None => inner,
+    }
+}
+
+struct TracingDevice {
+    inner: Box<dyn CashuDevice>,
+    device: String,
+    redaction: Redaction,
+}
+
+impl CashuDevice for TracingDevice {
+    fn features(&self) -> Option<&protos::Features> {
+        self.inner.features()
+    }
+
+    fn init_device(&mut self, session_id: Option<&[u8]>) -> Result<(), Error> {
+        self.inner.init_device(session_id)
+    }
+
+    fn call(&mut self, req: RawMessage, interaction: &Interaction) -> Exchange<RawMessage> {
+        tracing::info!(
+            device = %self.device,
+            "request {:?} {}",
+            req.message_type,
+            redact(&req, self.redaction)
+        );
+        let exchange = self.inner.call(req, interaction);
+        match &exchange {
+            Exchange::Done(Ok(resp)) => tracing::info!(
+                device = %self.device,
+                "response {:?} {}",
+                resp.message_type,
+                redact(resp, self.redaction)
+            ),
+            Exchange::Done(Err(err)) => {
+                tracing::info!(device = %self.device, "response error: {}", err)
+            }
+            Exchange::TransportFailed => {
+                tracing::info!(device = %self.device, "response lost, transport failed")
+            }
+        }
+        exchange
+    }
+
+    fn end_session(&mut self) {
+        self.inner.end_session()
+    }
+}
+
+/// Hex payload of `message` with the fields `redaction` covers zeroed
+fn redact(message: &RawMessage, redaction: Redaction) -> String {
+    let redacted = match (message.message_type, redaction) {
+        (_, Redaction::None) => Ok(None),
+        (protos::MessageType::MessageType_CashuVerifyProofs, _) => message
+            .decode::<protos::CashuVerifyProofs>()
+            .and_then(|mut req| {
+                if let Some(proofs) = req.proofs.as_mut() {
+                    proofs.proof.iter_mut().for_each(redact_proof);
+                }
+                RawMessage::encode(&req).map(Some)
+            }),
+        (protos::MessageType::MessageType_CashuBlindSign, Redaction::Points) => message
+            .decode::<protos::CashuBlindSign>()
+            .and_then(|mut req| {
+                for blinded in &mut req.blinded_messages {
+                    zero(&mut blinded.blinded_secret);
+                    blank(&mut blinded.witness);
+                }
+                RawMessage::encode(&req).map(Some)
+            }),
+        (protos::MessageType::MessageType_CashuBlindSignResponse, Redaction::Points) => message
+            .decode::<protos::CashuBlindSignResponse>()
+            .and_then(|mut resp| {
+                for sig in &mut resp.sigs {
+                    zero(&mut sig.blinded_secret);
+                    blank(&mut sig.dleq);
+                }
+                RawMessage::encode(&resp).map(Some)
+            }),
+        _ => Ok(None),
+    };
+    match redacted {
+        Ok(Some(redacted)) => hex::encode(redacted.payload),
+        Ok(None) => hex::encode(&message.payload),
+        // a payload the mapping cannot parse is what tracing is for, but it may still hold
+        // secrets, so only its length is logged
+        Err(err) => format!("<{} bytes, not redactable: {}>", message.payload.len(), err),
+    }
+}
+
+fn redact_proof(proof: &mut protos::Proof) {
+    zero(&mut proof.secret);
+    zero(&mut proof.c);
+    blank(&mut proof.witness);
+    if let Some(dleq) = proof.dleq.as_mut() {
+        zero(&mut dleq.r);
+    }
+}
+
+fn zero(field: &mut Option<Vec<u8>>) {
+    if let Some(bytes) = field {
+        bytes.fill(0);
+    }
+}
+
+/// Replace a present message with an empty one
+fn blank<M: protobuf::Message>(field: &mut MessageField<M>) {
+    if field.is_some() {
+        *field = MessageField::some(M::new());
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use cdk_common::Error;
+use trezor_client::protos::pin_matrix_request::PinMatrixRequestType;
+
+use crate::error::TrezorSignatoryError;
+
+/// Source of PINs for PIN-protected devices.
+///
+/// The device shows a scrambled keypad and expects the positions of the PIN digits
+/// on a fixed 3x3 layout (7 8 9 / 4 5 6 / 1 2 3), never the digits themselves.
+/// Implementations must return that position-encoded string.
+pub trait PinProvider: Send + Sync {
+    fn get_pin(&self, request_type: PinMatrixRequestType) -> Result<String, Error>;
+}
+
+/// Reads the PIN matrix positions from the controlling terminal
+pub struct TerminalPinProvider;
+
+impl PinProvider for TerminalPinProvider {
+    fn get_pin(&self, request_type: PinMatrixRequestType) -> Result<String, Error> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Err(TrezorSignatoryError::Interaction(
+                "Device requested a PIN but no terminal is attached".to_string(),
+            )
+            .into());
+        }
+
+        let mut stderr = io::stderr();
+        let prompt = match request_type {
+            PinMatrixRequestType::PinMatrixRequestType_NewFirst => "Enter new PIN",
+            PinMatrixRequestType::PinMatrixRequestType_NewSecond => "Re-enter new PIN",
+            _ => "Enter PIN",
+        };
+        writeln!(
+            stderr,
+            "{} using the positions shown on the device:\n  7 8 9\n  4 5 6\n  1 2 3",
+            prompt
+        )
+        .and_then(|_| write!(stderr, "> "))
+        .and_then(|_| stderr.flush())
+        .map_err(|e| {
+            TrezorSignatoryError::Interaction(format!("Failed to write PIN prompt: {}", e))
+        })?;
+
+        let mut line = String::new();
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .map_err(|e| TrezorSignatoryError::Interaction(format!("Failed to read PIN: {}", e)))?;
+
+        let pin = line.trim().to_string();
+        validate_pin_matrix(&pin)?;
+        Ok(pin)
+    }
+}
+
+/// Check that a PIN only contains matrix positions accepted by the device
+fn validate_pin_matrix(pin: &str) -> Result<(), Error> {
+    if pin.is_empty() || pin.len() > 50 {
+        return Err(TrezorSignatoryError::Interaction(
+            "PIN must be between 1 and 50 positions long".to_string(),
+        )
+        .into());
+    }
+    if !pin.chars().all(|c| ('1'..='9').contains(&c)) {
+        return Err(TrezorSignatoryError::Interaction(
+            "PIN may only contain matrix positions 1-9".to_string(),
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,477 @@
+use cdk_common::nuts::{BlindedMessage, CurrencyUnit, Id};
+use cdk_common::{Amount, Error};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use cdk_signatory::signatory::{RotateKeyArguments, SignatoryKeysets};
+use serde::Deserialize;
+
+use crate::error::TrezorSignatoryError;
+
+/// Host-side rules applied to signing requests before they reach the device
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningPolicy {
+    /// Batches worth more than this many sats must be confirmed with a button press on the
+    /// device, smaller batches are signed without interaction. Unset never asks.
+    pub confirm_threshold_sats: Option<u64>,
+    /// Only sign for these keysets, empty allows every keyset served by the device
+    pub allowed_keysets: Vec<Id>,
+    /// Only sign and verify for keysets of these units, empty allows every unit
+    pub allowed_units: Vec<CurrencyUnit>,
+    /// Highest input fee in parts per thousand a rotation may set
+    pub rotation_max_input_fee_ppk: Option<u64>,
+    /// Largest denomination a rotated keyset may include
+    pub rotation_max_amount: Option<u64>,
+    /// Furthest a rotated keyset's final expiry may lie in the future, in seconds
+    pub rotation_max_expiry_secs: Option<u64>,
+    /// Days and hours blind_sign is allowed in, unset signs at any time
+    pub signing_window: Option<SigningWindow>,
+}
+
+impl SigningPolicy {
+    /// Reject blind_sign outside the signing window, `now` in seconds since the Unix epoch
+    pub fn check_window(&self, now: u64) -> Result<(), Error> {
+        match &self.signing_window {
+            Some(window) if !window.contains(now) => Err(TrezorSignatoryError::WindowClosed(
+                format!("signing is only allowed {}", window),
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reject batches referencing keysets outside the allowlist
+    pub fn check_keysets(&self, messages: &[BlindedMessage]) -> Result<(), Error> {
+        if self.allowed_keysets.is_empty() {
+            return Ok(());
+        }
+        match messages
+            .iter()
+            .find(|message| !self.allowed_keysets.contains(&message.keyset_id))
+        {
+            Some(message) => Err(TrezorSignatoryError::Policy(format!(
+                "keyset {} is not allowed",
+                message.keyset_id
+            ))
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reject batches touching keysets whose unit is outside the allowlist
+    pub fn check_units(
+        &self,
+        keyset_ids: impl IntoIterator<Item = Id>,
+        keysets: &SignatoryKeysets,
+    ) -> Result<(), Error> {
+        if self.allowed_units.is_empty() {
+            return Ok(());
+        }
+        for keyset_id in keyset_ids {
+            let keyset = keysets
+                .keysets
+                .iter()
+                .find(|ks| ks.id == keyset_id)
+                .ok_or(Error::UnknownKeySet)?;
+            if !self.allowed_units.contains(&keyset.unit) {
+                return Err(TrezorSignatoryError::Policy(format!(
+                    "unit {} is not allowed",
+                    keyset.unit
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject rotations outside the operator's bounds before they reach the device
+    pub fn check_rotation(&self, args: &RotateKeyArguments) -> Result<(), Error> {
+        let refuse = |reason: String| Err(TrezorSignatoryError::Policy(reason).into());
+        if !self.allowed_units.is_empty() && !self.allowed_units.contains(&args.unit) {
+            return refuse(format!("unit {} is not allowed", args.unit));
+        }
+        if let Some(max) = self.rotation_max_input_fee_ppk {
+            if args.input_fee_ppk > max {
+                return refuse(format!(
+                    "input fee of {} ppk exceeds the maximum of {}",
+                    args.input_fee_ppk, max
+                ));
+            }
+        }
+        if let Some(max) = self.rotation_max_amount {
+            if let Some(amount) = args.amounts.iter().find(|amount| **amount > max) {
+                return refuse(format!(
+                    "amount {} exceeds the maximum denomination of {}",
+                    amount, max
+                ));
+            }
+        }
+        if let Some(expiry) = args.final_expiry {
+            let now = crate::signatory::unix_now();
+            if expiry <= now {
+                return refuse(format!("final expiry {} is in the past", expiry));
+            }
+            if let Some(max) = self.rotation_max_expiry_secs {
+                if expiry - now > max {
+                    return refuse(format!(
+                        "final expiry {} is more than {} s away",
+                        expiry, max
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the batch must be confirmed on the device.
+    ///
+    /// Only `sat` and `msat` keysets count towards the threshold, other units have no
+    /// fixed sat value.
+    pub fn requires_confirmation(
+        &self,
+        messages: &[BlindedMessage],
+        keysets: &SignatoryKeysets,
+    ) -> Result<bool, Error> {
+        let Some(threshold) = self.confirm_threshold_sats else {
+            return Ok(false);
+        };
+        Ok(batch_value_sats(messages, keysets)? > threshold)
+    }
+}
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Days and daily hours blind_sign is allowed in, e.g. `mon-fri 08:00-18:00 +01:00`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct SigningWindow {
+    spec: String,
+    /// Allowed days, Monday first
+    days: [bool; 7],
+    /// Minutes after midnight the window opens and closes, `None` for the whole day
+    hours: Option<(u32, u32)>,
+    /// Offset of the window's local time to UTC in minutes
+    utc_offset: i64,
+}
+
+impl SigningWindow {
+    /// Whether `now`, in seconds since the Unix epoch, falls into the window
+    pub fn contains(&self, now: u64) -> bool {
+        let local = now as i64 + self.utc_offset * 60;
+        // 1970-01-01 was a Thursday
+        let day = (local.div_euclid(86400) + 3).rem_euclid(7) as usize;
+        let minute = (local.rem_euclid(86400) / 60) as u32;
+        match self.hours {
+            None => self.days[day],
+            Some((open, close)) if open < close => {
+                self.days[day] && (open..close).contains(&minute)
+            }
+            // the window runs past midnight, its early hours belong to the day it opened on
+            Some((open, _)) if minute >= open => self.days[day],
+            Some((_, close)) => minute < close && self.days[(day + 6) % 7],
+        }
+    }
+}
+
+impl FromStr for SigningWindow {
+    type Err = String;
+
+    /// Parse whitespace separated days (`mon-fri,sun`), hours (`08:00-18:00`) and UTC offset
+    /// (`+01:00`), each optional. Hours closing before they open run past midnight into the
+    /// day after each allowed day. The offset is fixed, so it does not follow daylight saving
+    /// time.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| format!("invalid signing window {:?}: {}", value, reason);
+        let (mut days, mut hours, mut utc_offset) = (None, None, None);
+        for part in value.split_whitespace() {
+            let duplicate = if part.starts_with(['+', '-']) {
+                utc_offset
+                    .replace(parse_offset(part).ok_or_else(|| invalid("bad UTC offset"))?)
+                    .is_some()
+            } else if part.starts_with(|c: char| c.is_ascii_digit()) {
+                hours
+                    .replace(parse_hours(part).ok_or_else(|| invalid("bad hours"))?)
+                    .is_some()
+            } else {
+                days.replace(parse_days(part).ok_or_else(|| invalid("bad days"))?)
+                    .is_some()
+            };
+            if duplicate {
+                return Err(invalid("days, hours and offset may each be given once"));
+            }
+        }
+        if days.is_none() && hours.is_none() {
+            return Err(invalid("expected days, hours or both"));
+        }
+        Ok(Self {
+            spec: value.trim().to_string(),
+            days: days.unwrap_or([true; 7]),
+            hours,
+            utc_offset: utc_offset.unwrap_or(0),
+        })
+    }
+}
+
+impl TryFrom<String> for SigningWindow {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for SigningWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+/// `mon-fri,sun`, ranges may wrap around the week like `fri-mon`
+fn parse_days(value: &str) -> Option<[bool; 7]> {
+    let index = |day: &str| DAYS.iter().position(|d| d.eq_ignore_ascii_case(day));
+    let mut days = [false; 7];
+    for item in value.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((first, last)) => (index(first)?, index(last)?),
+            None => (index(item)?, index(item)?),
+        };
+        let mut day = first;
+        days[day] = true;
+        while day != last {
+            day = (day + 1) % 7;
+            days[day] = true;
+        }
+    }
+    Some(days)
+}
+
+/// `HH:MM-HH:MM` as minutes after midnight, the end may be `24:00`
+fn parse_hours(value: &str) -> Option<(u32, u32)> {
+    let (open, close) = value.split_once('-')?;
+    let (open, close) = (parse_time(open)?, parse_time(close)?);
+    (open != close && open < 24 * 60).then_some((open, close))
+}
+
+fn parse_time(value: &str) -> Option<u32> {
+    let (hours, minutes) = value.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    let time = hours * 60 + minutes;
+    (minutes < 60 && time <= 24 * 60).then_some(time)
+}
+
+/// `+HH:MM` or `-HH:MM` in minutes
+fn parse_offset(value: &str) -> Option<i64> {
+    let (sign, time) = match value.strip_prefix('-') {
+        Some(time) => (-1, time),
+        None => (1, value.strip_prefix('+')?),
+    };
+    let minutes = i64::from(parse_time(time)?);
+    (minutes <= 14 * 60).then_some(sign * minutes)
+}
+
+/// Total value of the batch in sats, ignoring units without a sat value
+pub fn batch_value_sats(
+    messages: &[BlindedMessage],
+    keysets: &SignatoryKeysets,
+) -> Result<u64, Error> {
+    let mut msats = Amount::ZERO;
+    for message in messages {
+        let keyset = keysets
+            .keysets
+            .iter()
+            .find(|ks| ks.id == message.keyset_id)
+            .ok_or(Error::UnknownKeySet)?;
+        let value_msat = match keyset.unit {
+            CurrencyUnit::Sat => message
+                .amount
+                .checked_mul(Amount::from(1000))
+                .ok_or(Error::AmountOverflow)?,
+            CurrencyUnit::Msat => message.amount,
+            _ => continue,
+        };
+        msats = msats.checked_add(value_msat).ok_or(Error::AmountOverflow)?;
+    }
+    Ok(u64::from(msats) / 1000)
+}
+
+/// Total amount of the batch per unit, in the unit's denomination
+pub fn batch_totals(
+    messages: &[BlindedMessage],
+    keysets: &SignatoryKeysets,
+) -> Result<BTreeMap<String, u64>, Error> {
+    let mut totals: BTreeMap<String, Amount> = BTreeMap::new();
+    for message in messages {
+        let keyset = keysets
+            .keysets
+            .iter()
+            .find(|ks| ks.id == message.keyset_id)
+            .ok_or(Error::UnknownKeySet)?;
+        let total = totals
+            .entry(keyset.unit.to_string())
+            .or_insert(Amount::ZERO);
+        *total = total
+            .checked_add(message.amount)
+            .ok_or(Error::AmountOverflow)?;
+    }
+    Ok(totals
+        .into_iter()
+        .map(|(unit, total)| (unit, u64::from(total)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use cdk_common::{Keys, SecretKey};
+    use cdk_signatory::signatory::SignatoryKeySet;
+
+    use super::*;
+
+    /// Seconds since the Unix epoch at `hour:minute` UTC on day `day` of the first full week
+    /// of 1970, Monday 5 January being day 0
+    fn at(day: u64, hour: u64, minute: u64) -> u64 {
+        (4 + day) * 86400 + hour * 3600 + minute * 60
+    }
+
+    /// One active sat keyset of powers of two, without keys
+    fn sat_keysets() -> (SignatoryKeysets, Id) {
+        let id = Id::from_str("009a1f293253e41e").unwrap();
+        let keyset = SignatoryKeySet {
+            id,
+            unit: CurrencyUnit::Sat,
+            active: true,
+            keys: Keys::new(BTreeMap::new()),
+            amounts: (0..32).map(|i| 1 << i).collect(),
+            input_fee_ppk: 0,
+            final_expiry: None,
+        };
+        let keysets = SignatoryKeysets {
+            pubkey: SecretKey::generate().public_key(),
+            keysets: vec![keyset],
+        };
+        (keysets, id)
+    }
+
+    /// Blinded message of `amount` for `keyset_id` with a random blinded secret
+    fn blinded_message(keyset_id: Id, amount: u64) -> BlindedMessage {
+        BlindedMessage::new(
+            Amount::from(amount),
+            keyset_id,
+            SecretKey::generate().public_key(),
+        )
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window: SigningWindow = "mon-fri 08:00-18:00".parse().unwrap();
+        assert!(window.contains(at(0, 8, 0)));
+        assert!(window.contains(at(4, 17, 59)));
+        assert!(!window.contains(at(0, 18, 0)));
+        assert!(!window.contains(at(0, 7, 59)));
+        assert!(!window.contains(at(5, 12, 0)));
+    }
+
+    #[test]
+    fn window_past_midnight_belongs_to_the_day_it_opened() {
+        let window: SigningWindow = "fri 22:00-06:00".parse().unwrap();
+        assert!(window.contains(at(4, 23, 0)));
+        assert!(window.contains(at(5, 5, 59)));
+        assert!(!window.contains(at(5, 6, 0)));
+        // Friday's early hours belong to Thursday's window, which is closed
+        assert!(!window.contains(at(4, 5, 0)));
+        assert!(!window.contains(at(5, 23, 0)));
+    }
+
+    #[test]
+    fn window_in_local_time() {
+        let window: SigningWindow = "mon 08:00-09:00 +02:00".parse().unwrap();
+        assert!(window.contains(at(0, 6, 30)));
+        assert!(!window.contains(at(0, 8, 30)));
+    }
+
+    #[test]
+    fn window_rejects_invalid_specs() {
+        for spec in [
+            "",
+            "someday",
+            "08:00-08:00",
+            "25:00-26:00",
+            "mon mon",
+            "+15:00 mon",
+        ] {
+            assert!(spec.parse::<SigningWindow>().is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn check_window_refuses_outside_the_window() {
+        let policy = SigningPolicy {
+            signing_window: Some("sat-sun".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(policy.check_window(at(5, 12, 0)).is_ok());
+        assert!(policy.check_window(at(0, 12, 0)).is_err());
+        assert!(SigningPolicy::default().check_window(at(0, 12, 0)).is_ok());
+    }
+
+    #[test]
+    fn check_keysets_allowlist() {
+        let (_, id) = sat_keysets();
+        let messages = [blinded_message(id, 1)];
+        let mut policy = SigningPolicy::default();
+        assert!(policy.check_keysets(&messages).is_ok());
+        policy.allowed_keysets = vec![id];
+        assert!(policy.check_keysets(&messages).is_ok());
+        policy.allowed_keysets = vec![Id::from_str("00deadbeef123456").unwrap()];
+        assert!(policy.check_keysets(&messages).is_err());
+    }
+
+    #[test]
+    fn check_units_allowlist() {
+        let (keysets, id) = sat_keysets();
+        let mut policy = SigningPolicy {
+            allowed_units: vec![CurrencyUnit::Sat],
+            ..Default::default()
+        };
+        assert!(policy.check_units([id], &keysets).is_ok());
+        policy.allowed_units = vec![CurrencyUnit::Usd];
+        assert!(policy.check_units([id], &keysets).is_err());
+    }
+
+    #[test]
+    fn confirmation_above_threshold() {
+        let (keysets, id) = sat_keysets();
+        let policy = SigningPolicy {
+            confirm_threshold_sats: Some(8),
+            ..Default::default()
+        };
+        let small = [blinded_message(id, 8)];
+        let large = [blinded_message(id, 8), blinded_message(id, 1)];
+        assert!(!policy.requires_confirmation(&small, &keysets).unwrap());
+        assert!(policy.requires_confirmation(&large, &keysets).unwrap());
+        assert!(
+            !SigningPolicy::default()
+                .requires_confirmation(&large, &keysets)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn rotation_bounds() {
+        let policy = SigningPolicy {
+            rotation_max_input_fee_ppk: Some(100),
+            rotation_max_amount: Some(1 << 20),
+            ..Default::default()
+        };
+        let args = |input_fee_ppk, amounts: Vec<u64>| RotateKeyArguments {
+            unit: CurrencyUnit::Sat,
+            amounts,
+            input_fee_ppk,
+            final_expiry: None,
+        };
+        assert!(policy.check_rotation(&args(100, vec![1, 2, 4])).is_ok());
+        assert!(policy.check_rotation(&args(101, vec![1, 2, 4])).is_err());
+        assert!(policy.check_rotation(&args(0, vec![1, 1 << 21])).is_err());
+    }
+}
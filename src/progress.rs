@@ -0,0 +1,178 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use futures::Stream;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+pub mod proto {
+    tonic::include_proto!("cdk_signatory_trezor.progress");
+}
+
+use proto::progress_event::State;
+use proto::progress_server::Progress;
+pub use proto::progress_server::ProgressServer;
+
+/// Events buffered per watcher, slower watchers skip the oldest ones
+const EVENT_BUFFER: usize = 64;
+
+/// Header a client sets to tag the progress events of its call with its own id
+pub const CALL_ID_HEADER: &str = "x-request-id";
+
+/// The signatory call device work is done for
+#[derive(Debug, Clone)]
+pub struct CallOrigin {
+    /// Correlation id reported in the call's progress events
+    pub id: String,
+    /// Fingerprint of the caller's client certificate, or its IP without one
+    pub caller: String,
+}
+
+impl CallOrigin {
+    /// Call from `caller`, identified by the id it sent in [`CALL_ID_HEADER`] if it is usable,
+    /// otherwise by a random one
+    pub fn new(caller: String, request_id: Option<&str>) -> Self {
+        let id = request_id
+            .filter(|id| {
+                !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+            })
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        Self { id, caller }
+    }
+}
+
+tokio::task_local! {
+    /// Origin of the signatory call being served
+    static CALL: CallOrigin;
+}
+
+/// Run `fut` as the call `origin`, whose device events are tagged with it
+pub async fn call_scope<F: Future>(origin: CallOrigin, fut: F) -> F::Output {
+    CALL.scope(origin, fut).await
+}
+
+/// Origin of the call being served, `None` outside of signatory calls
+pub fn current_call() -> Option<CallOrigin> {
+    CALL.try_with(CallOrigin::clone).ok()
+}
+
+/// An event with the caller it may be shown to, `None` for events about a device as a whole
+#[derive(Debug, Clone)]
+pub struct Tagged {
+    pub caller: Option<String>,
+    pub event: proto::ProgressEvent,
+}
+
+/// Fan-out of device progress, e.g. confirmation screens, to watching clients
+#[derive(Debug, Clone)]
+pub struct ProgressEvents {
+    sender: broadcast::Sender<Tagged>,
+}
+
+impl Default for ProgressEvents {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl ProgressEvents {
+    pub fn subscribe(&self) -> broadcast::Receiver<Tagged> {
+        self.sender.subscribe()
+    }
+
+    /// `device` shows a confirmation screen for `button_request` during `call`
+    pub fn awaiting_confirmation(
+        &self,
+        device: &str,
+        call: Option<&CallOrigin>,
+        button_request: &str,
+    ) {
+        self.send(State::AwaitingConfirmation, button_request, device, call);
+    }
+
+    /// The confirmation screen for `button_request` on `device` was answered
+    pub fn confirmation_answered(
+        &self,
+        device: &str,
+        call: Option<&CallOrigin>,
+        button_request: &str,
+    ) {
+        self.send(State::ConfirmationAnswered, button_request, device, call);
+    }
+
+    /// The USB link to `device` went away
+    pub fn device_detached(&self, device: &str) {
+        self.send(State::DeviceDetached, "", device, None);
+    }
+
+    /// `device` was plugged in again and reconnected
+    pub fn device_attached(&self, device: &str) {
+        self.send(State::DeviceAttached, "", device, None);
+    }
+
+    fn send(&self, state: State, button_request: &str, device: &str, call: Option<&CallOrigin>) {
+        let timestamp_ms = crate::signatory::unix_now_ms();
+        // no watchers is not an error
+        let _ = self.sender.send(Tagged {
+            caller: call.map(|call| call.caller.clone()),
+            event: proto::ProgressEvent {
+                state: state.into(),
+                button_request: button_request.to_string(),
+                timestamp_ms,
+                device: device.to_string(),
+                call_id: call.map(|call| call.id.clone()).unwrap_or_default(),
+            },
+        });
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<proto::ProgressEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Progress for ProgressEvents {
+    type WatchStream = EventStream;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let watcher = caller(&request);
+        let call_id = request.into_inner().call_id;
+        let events = BroadcastStream::new(self.subscribe()).filter_map(move |event| match event {
+            // other clients' calls are none of the watcher's business
+            Ok(tagged)
+                if tagged
+                    .caller
+                    .as_ref()
+                    .is_some_and(|caller| *caller != watcher) =>
+            {
+                None
+            }
+            Ok(tagged) if !call_id.is_empty() && tagged.event.call_id != call_id => None,
+            Ok(tagged) => Some(Ok(tagged.event)),
+            Err(err) => {
+                tracing::debug!("Progress watcher fell behind: {}", err);
+                None
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Identity of the client making `request`, the same [`CallOrigin::caller`] its signatory
+/// calls get
+fn caller<T>(request: &Request<T>) -> String {
+    let cert = request.peer_certs().and_then(|certs| {
+        certs
+            .first()
+            .map(|cert| crate::auth::fingerprint(cert.as_ref()))
+    });
+    cert.or_else(|| request.remote_addr().map(|addr| addr.ip().to_string()))
+        .unwrap_or_else(|| "local".to_string())
+}
@@ -0,0 +1,53 @@
+use cdk_common::Error;
+use cdk_common::nuts::CurrencyUnit;
+use cdk_signatory::signatory::{RotateKeyArguments, Signatory, SignatoryKeySet};
+
+use crate::error::TrezorSignatoryError;
+use crate::signatory::TrezorSignatory;
+
+/// Number of denominations of a new keyset unless given, matching the initial sat keyset
+pub const DEFAULT_MAX_ORDER: u8 = 32;
+
+/// Denominations `1, 2, 4, ..., 2^(max_order - 1)`
+pub fn power_of_two_amounts(max_order: u8) -> Vec<u64> {
+    (0..max_order.min(64)).map(|i| 1 << i).collect()
+}
+
+/// Provision the first keyset of a unit the device does not serve yet, e.g. usd next to sat.
+///
+/// The device derives the keyset through the rotate message, so it ends up on the unit's own
+/// derivation path. Units that already have a keyset are refused, use a rotation for those.
+pub async fn add_unit(
+    signatory: &TrezorSignatory,
+    unit: CurrencyUnit,
+    amounts: Vec<u64>,
+    input_fee_ppk: u64,
+    final_expiry: Option<u64>,
+) -> Result<SignatoryKeySet, Error> {
+    if amounts.is_empty() {
+        return Err(TrezorSignatoryError::InvalidRequest(
+            "a keyset needs at least one amount".to_string(),
+        )
+        .into());
+    }
+    // check against the device, a restored cache may miss keysets added elsewhere
+    let keysets = signatory.fetch_keysets().await?;
+    if let Some(existing) = keysets.keysets.iter().find(|ks| ks.unit == unit) {
+        return Err(TrezorSignatoryError::InvalidRequest(format!(
+            "unit {} already has keyset {}, rotate it instead",
+            unit, existing.id
+        ))
+        .into());
+    }
+
+    let keyset = signatory
+        .rotate_keyset(RotateKeyArguments {
+            unit: unit.clone(),
+            amounts,
+            input_fee_ppk,
+            final_expiry,
+        })
+        .await?;
+    tracing::info!("Added {} keyset {}", unit, keyset.id);
+    Ok(keyset)
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tonic::Status;
+use tonic::codegen::Service;
+use tonic::codegen::http::{Request, Response};
+use tonic::server::NamedService;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+
+use crate::progress::{CALL_ID_HEADER, CallOrigin};
+
+#[derive(Default)]
+struct QueueState {
+    depth: usize,
+    per_client: HashMap<String, usize>,
+}
+
+/// Admission control in front of the devices.
+///
+/// Calls beyond the configured depth, or beyond one client's share of it, are rejected with
+/// `RESOURCE_EXHAUSTED` and a `retry-after` hint instead of piling up on the device lock.
+/// Clients are told apart by their client certificate, or by their address without one.
+/// Admitted calls are scheduled fairly across clients by [`crate::lanes::DeviceLanes`].
+#[derive(Clone)]
+pub struct RequestQueue {
+    /// Maximum calls waiting for or holding a device, 0 is unlimited
+    max_depth: usize,
+    /// Maximum calls in flight from one client, 0 is unlimited
+    max_per_client: usize,
+    retry_after: Duration,
+    state: Arc<Mutex<QueueState>>,
+}
+
+/// A slot in the queue, released on drop
+struct Ticket {
+    client: String,
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.depth -= 1;
+        if let Some(count) = state.per_client.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                state.per_client.remove(&self.client);
+            }
+        }
+    }
+}
+
+impl RequestQueue {
+    pub fn new(max_depth: usize, max_per_client: usize, retry_after: Duration) -> Self {
+        Self {
+            max_depth,
+            max_per_client,
+            retry_after,
+            state: Arc::new(Mutex::new(QueueState::default())),
+        }
+    }
+
+    /// Wrap a gRPC service so every call has to be admitted first
+    pub fn wrap<S>(&self, inner: S) -> Queued<S> {
+        Queued {
+            queue: self.clone(),
+            inner,
+        }
+    }
+
+    /// Run `fut` once admitted, as a call from `client`, its IP, with the client certificate
+    /// of fingerprint `cert` and the correlation id `request_id` it sent. For calls that do not
+    /// go through a wrapped gRPC service, like those of the HTTP bridge.
+    pub async fn run<F: Future>(
+        &self,
+        client: String,
+        cert: Option<String>,
+        request_id: Option<&str>,
+        fut: F,
+    ) -> Result<F::Output, Status> {
+        let client = cert.clone().unwrap_or(client);
+        let origin = CallOrigin::new(client.clone(), request_id);
+        let _ticket = self.admit(client)?;
+        Ok(crate::progress::call_scope(origin, crate::quota::scope(cert, fut)).await)
+    }
+
+    fn admit(&self, client: String) -> Result<Ticket, Status> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let client_calls = state.per_client.get(&client).copied().unwrap_or(0);
+        let reason = if self.max_depth > 0 && state.depth >= self.max_depth {
+            Some("signatory queue is full")
+        } else if self.max_per_client > 0 && client_calls >= self.max_per_client {
+            Some("too many calls in flight from this client")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            tracing::warn!("Rejected call from {}: {}", client, reason);
+            let mut status = Status::resource_exhausted(reason);
+            if let Ok(value) = self.retry_after.as_secs().to_string().parse() {
+                status.metadata_mut().insert("retry-after", value);
+            }
+            return Err(status);
+        }
+
+        state.depth += 1;
+        *state.per_client.entry(client.clone()).or_default() += 1;
+        Ok(Ticket {
+            client,
+            state: self.state.clone(),
+        })
+    }
+}
+
+/// Service wrapped by [`RequestQueue::wrap`]
+#[derive(Clone)]
+pub struct Queued<S> {
+    queue: RequestQueue,
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for Queued<S>
+where
+    S: Service<Request<B>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let operation = match crate::operation::from_headers(req.headers()) {
+            Ok(operation) => operation,
+            Err(status) => return Box::pin(async move { Ok(status.into_http()) }),
+        };
+        let client_cert = client_cert(&req);
+        let client = client_cert.clone().unwrap_or_else(|| client_addr(&req));
+        let ticket = match self.queue.admit(client.clone()) {
+            Ok(ticket) => ticket,
+            Err(status) => return Box::pin(async move { Ok(status.into_http()) }),
+        };
+        let deadline = crate::deadline::from_request(&req);
+        let request_id = req
+            .headers()
+            .get(CALL_ID_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let origin = CallOrigin::new(client, request_id);
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let _ticket = ticket;
+            // the handler runs inside `fut`, which is how quotas learn who is calling, device
+            // calls how long the caller waits and for which operation, and progress events which
+            // call they belong to
+            let fut = crate::operation::scope(operation, fut);
+            let fut = crate::progress::call_scope(origin, crate::quota::scope(client_cert, fut));
+            crate::deadline::scope(deadline, fut).await
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for Queued<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Remote IP of the caller, unix socket clients all share one identity
+fn client_addr<B>(req: &Request<B>) -> String {
+    let extensions = req.extensions();
+    extensions
+        .get::<TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+        .or_else(|| {
+            extensions
+                .get::<TlsConnectInfo<TcpConnectInfo>>()
+                .and_then(|info| info.get_ref().remote_addr())
+        })
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+/// SHA-256 fingerprint of the caller's client certificate, if it presented one
+fn client_cert<B>(req: &Request<B>) -> Option<String> {
+    let certs = req
+        .extensions()
+        .get::<TlsConnectInfo<TcpConnectInfo>>()?
+        .peer_certs()?;
+    Some(crate::auth::fingerprint(certs.first()?.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use super::*;
+
+    #[test]
+    fn admits_up_to_the_depth_and_the_client_share() {
+        let queue = RequestQueue::new(2, 1, Duration::from_secs(3));
+        let first = queue.admit("a".to_string()).unwrap();
+
+        let status = queue.admit("a".to_string()).err().unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "too many calls in flight from this client"
+        );
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "3");
+
+        let _second = queue.admit("b".to_string()).unwrap();
+        let status = queue.admit("c".to_string()).err().unwrap();
+        assert_eq!(status.message(), "signatory queue is full");
+
+        drop(first);
+        assert!(queue.admit("c".to_string()).is_ok());
+        assert!(queue.state.lock().unwrap().per_client.get("a").is_none());
+    }
+
+    #[test]
+    fn zero_limits_admit_everything() {
+        let queue = RequestQueue::new(0, 0, Duration::from_secs(1));
+        let tickets: Vec<_> = (0..100)
+            .map(|_| queue.admit("a".to_string()).unwrap())
+            .collect();
+        assert_eq!(queue.state.lock().unwrap().depth, tickets.len());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cdk_common::Error;
+use serde::Deserialize;
+
+use crate::error::TrezorSignatoryError;
+use crate::signatory::{SignedVolume, VolumeLimiter, VolumeLimits};
+
+tokio::task_local! {
+    /// SHA-256 fingerprint of the client certificate of the gRPC call being served
+    static CLIENT: Option<String>;
+}
+
+/// Run `fut` as a call from the client with certificate `fingerprint`
+pub async fn scope<F: Future>(fingerprint: Option<String>, fut: F) -> F::Output {
+    CLIENT.scope(fingerprint, fut).await
+}
+
+/// Limits of one client certificate, unset fields are unlimited
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientQuota {
+    /// Maximum sats signed for the client in any 60 minute window
+    pub hourly_sats: Option<u64>,
+    /// Maximum sats signed for the client in any 24 hour window
+    pub daily_sats: Option<u64>,
+    /// Maximum blind_sign and verify_proofs calls of the client in any 60 second window, 0 is
+    /// refused when the config is read as it would refuse every call
+    pub calls_per_minute: Option<NonZeroUsize>,
+}
+
+impl ClientQuota {
+    fn is_unlimited(&self) -> bool {
+        self.hourly_sats.is_none() && self.daily_sats.is_none() && self.calls_per_minute.is_none()
+    }
+}
+
+/// Quotas per client certificate, so mints sharing the signatory cannot starve each other
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientQuotaConfig {
+    /// Quota of every client certificate not listed in `clients`
+    pub default: ClientQuota,
+    /// Quota per SHA-256 client certificate fingerprint (hex, colons optional)
+    pub clients: HashMap<String, ClientQuota>,
+}
+
+/// Signed volume reserved against a client's quota
+pub struct ClientReservation {
+    limiter: Arc<VolumeLimiter>,
+    entry: SignedVolume,
+}
+
+impl ClientReservation {
+    /// Give back a reservation whose signing failed
+    pub async fn release(&self) -> Result<(), Error> {
+        self.limiter.release(&self.entry).await
+    }
+}
+
+/// Enforces [`ClientQuotaConfig`] on the calls of each client certificate.
+///
+/// Clients are told apart by their certificate only. Served calls without one, over the unix
+/// socket, the HTTP bridge or TLS without client authentication, cannot be counted and are
+/// refused, so they cannot sign around the quotas. Calls the signatory makes itself, such as
+/// those of the `bench` and `attest` subcommands, are not counted. Volume is kept in memory and
+/// starts over on restart, the global limits are the ones that persist.
+pub struct ClientQuotas {
+    default: ClientQuota,
+    clients: HashMap<String, ClientQuota>,
+    limiters: Mutex<HashMap<String, Arc<VolumeLimiter>>>,
+    calls: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl ClientQuotas {
+    /// Quotas of `config`, `None` if no client has one
+    pub fn new(config: &ClientQuotaConfig) -> Option<Self> {
+        if config.default.is_unlimited() && config.clients.values().all(ClientQuota::is_unlimited) {
+            return None;
+        }
+        tracing::info!(
+            "Enforcing signing quotas for {} client certificates and a default quota",
+            config.clients.len()
+        );
+        Some(Self {
+            default: config.default,
+            clients: config
+                .clients
+                .iter()
+                .map(|(fp, quota)| (fp.replace(':', "").to_ascii_lowercase(), *quota))
+                .collect(),
+            limiters: Mutex::new(HashMap::new()),
+            calls: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn quota(&self, client: &str) -> ClientQuota {
+        self.clients.get(client).copied().unwrap_or(self.default)
+    }
+
+    /// Count a call of the current client, refusing it once the client is over its rate or
+    /// if it has no client certificate
+    pub fn check_rate(&self) -> Result<(), Error> {
+        let Some(client) = counted_client()? else {
+            return Ok(());
+        };
+        let Some(limit) = self.quota(&client).calls_per_minute.map(NonZeroUsize::get) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let mut calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        // forget clients that went quiet so the map does not grow with every certificate seen
+        calls.retain(|_, times| {
+            times.retain(|at| now.duration_since(*at) < Duration::from_secs(60));
+            !times.is_empty()
+        });
+        let times = calls.entry(client.clone()).or_default();
+        if times.len() >= limit {
+            tracing::warn!(
+                "Client {} is over its rate of {} calls per minute",
+                client,
+                limit
+            );
+            return Err(TrezorSignatoryError::Policy(format!(
+                "client quota of {} calls per minute reached",
+                limit
+            ))
+            .into());
+        }
+        times.push_back(now);
+        Ok(())
+    }
+
+    /// Reserve `sats` against the volume quota of the current client, `None` if the client has
+    /// no volume quota
+    pub async fn reserve(&self, sats: u64) -> Result<Option<ClientReservation>, Error> {
+        let Some(client) = counted_client()? else {
+            return Ok(None);
+        };
+        let quota = self.quota(&client);
+        if quota.hourly_sats.is_none() && quota.daily_sats.is_none() {
+            return Ok(None);
+        }
+        let limiter = self
+            .limiters
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(client.clone())
+            .or_insert_with(|| {
+                Arc::new(VolumeLimiter::new(VolumeLimits {
+                    hourly_sats: quota.hourly_sats,
+                    daily_sats: quota.daily_sats,
+                    ..VolumeLimits::default()
+                }))
+            })
+            .clone();
+        match limiter.reserve(sats, BTreeMap::new()).await {
+            Ok(entry) => Ok(Some(ClientReservation { limiter, entry })),
+            Err(err) => {
+                tracing::warn!("Client {} is over its volume quota: {}", client, err);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Fingerprint of the client of the call being served, refusing served calls without a client
+/// certificate. `None` outside of served calls, for the signatory's own calls.
+fn counted_client() -> Result<Option<String>, Error> {
+    match CLIENT.try_with(|client| client.clone()) {
+        Err(_) => Ok(None),
+        Ok(Some(client)) => Ok(Some(client)),
+        Ok(None) => Err(TrezorSignatoryError::Policy(
+            "client quotas are enforced, but the call has no client certificate to count it \
+             against"
+                .to_string(),
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_quotas(default: ClientQuota, clients: &[(&str, ClientQuota)]) -> ClientQuotas {
+        ClientQuotas::new(&ClientQuotaConfig {
+            default,
+            clients: clients
+                .iter()
+                .map(|(fp, quota)| (fp.to_string(), *quota))
+                .collect(),
+        })
+        .unwrap()
+    }
+
+    fn calls_per_minute(calls: usize) -> ClientQuota {
+        ClientQuota {
+            calls_per_minute: NonZeroUsize::new(calls),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_quotas_without_limits() {
+        assert!(ClientQuotas::new(&ClientQuotaConfig::default()).is_none());
+    }
+
+    #[test]
+    fn zero_calls_per_minute_is_refused() {
+        assert!(toml::from_str::<ClientQuota>("calls_per_minute = 0").is_err());
+        assert!(toml::from_str::<ClientQuota>("calls_per_minute = 1").is_ok());
+    }
+
+    #[tokio::test]
+    async fn rate_per_client_certificate() {
+        let quotas = client_quotas(calls_per_minute(1), &[("AA:BB", calls_per_minute(2))]);
+        let calls = |client: &str| scope(Some(client.to_string()), async { quotas.check_rate() });
+        assert!(calls("cc").await.is_ok());
+        assert!(calls("cc").await.is_err());
+        // listed clients have their own quota, matched without colons and case
+        assert!(calls("aabb").await.is_ok());
+        assert!(calls("aabb").await.is_ok());
+        assert!(calls("aabb").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn calls_without_a_certificate_are_refused_and_own_calls_not_counted() {
+        let quotas = client_quotas(calls_per_minute(1), &[]);
+        assert!(scope(None, async { quotas.check_rate() }).await.is_err());
+        for _ in 0..3 {
+            assert!(quotas.check_rate().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn volume_per_client_certificate() {
+        let quota = ClientQuota {
+            daily_sats: Some(100),
+            ..Default::default()
+        };
+        let quotas = client_quotas(quota, &[]);
+        let reserve = |client: &str, sats| scope(Some(client.to_string()), quotas.reserve(sats));
+        let first = reserve("aa", 60).await.unwrap().unwrap();
+        assert!(reserve("aa", 60).await.is_err());
+        assert!(reserve("bb", 60).await.is_ok());
+        first.release().await.unwrap();
+        assert!(reserve("aa", 60).await.is_ok());
+    }
+}
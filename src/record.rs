@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use cdk_common::Error;
+use protobuf::Enum;
+use serde::{Deserialize, Serialize};
+use trezor_client::protos;
+
+use crate::backend::{CashuDevice, Exchange, RawMessage};
+use crate::error::TrezorSignatoryError;
+use crate::trezor::Interaction;
+
+/// Protobuf message as stored in a recording
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct WireMessage {
+    message_type: i32,
+    /// Name of the message type, for people reading the file
+    #[serde(default)]
+    name: String,
+    /// Hex encoded payload
+    payload: String,
+}
+
+impl From<&RawMessage> for WireMessage {
+    fn from(message: &RawMessage) -> Self {
+        Self {
+            message_type: message.message_type.value(),
+            name: format!("{:?}", message.message_type),
+            payload: hex::encode(&message.payload),
+        }
+    }
+}
+
+impl TryFrom<&WireMessage> for RawMessage {
+    type Error = Error;
+
+    fn try_from(message: &WireMessage) -> Result<Self, Error> {
+        let invalid =
+            |what: String| TrezorSignatoryError::Mapping(format!("invalid recording: {}", what));
+        Ok(Self {
+            message_type: protos::MessageType::from_i32(message.message_type)
+                .ok_or_else(|| invalid(format!("unknown message type {}", message.message_type)))?,
+            payload: hex::decode(&message.payload).map_err(|e| invalid(e.to_string()))?,
+        })
+    }
+}
+
+/// How the device answered a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Response(WireMessage),
+    Error(String),
+    TransportFailed,
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Record {
+    /// A device was opened and reported these features
+    Open {
+        device: String,
+        features: Option<WireMessage>,
+    },
+    /// A request and the final answer of the device
+    Exchange {
+        device: String,
+        request: WireMessage,
+        outcome: Outcome,
+    },
+}
+
+/// Appends every message exchanged with the devices to a JSON lines file.
+///
+/// Only the final answer to each request is stored; PIN and passphrase prompts are answered
+/// inside the exchange and never reach the file.
+pub struct ExchangeRecorder {
+    file: Mutex<File>,
+}
+
+impl ExchangeRecorder {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("opening recording {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn write(&self, record: &Record) {
+        let mut line = serde_json::to_vec(record).expect("record always serializes");
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(err) = file.write_all(&line) {
+            tracing::warn!("Failed to write device recording: {}", err);
+        }
+    }
+
+    /// Wrap `inner` so its exchanges are recorded under the name `device`
+    pub fn wrap(
+        self: &Arc<Self>,
+        device: String,
+        inner: Box<dyn CashuDevice>,
+    ) -> Box<dyn CashuDevice> {
+        self.write(&Record::Open {
+            device: device.clone(),
+            features: inner
+                .features()
+                .and_then(|features| RawMessage::encode(features).ok())
+                .map(|features| WireMessage::from(&features)),
+        });
+        Box::new(RecordingDevice {
+            inner,
+            device,
+            recorder: self.clone(),
+        })
+    }
+}
+
+struct RecordingDevice {
+    inner: Box<dyn CashuDevice>,
+    device: String,
+    recorder: Arc<ExchangeRecorder>,
+}
+
+impl CashuDevice for RecordingDevice {
+    fn features(&self) -> Option<&protos::Features> {
+        self.inner.features()
+    }
+
+    fn init_device(&mut self, session_id: Option<&[u8]>) -> Result<(), Error> {
+        self.inner.init_device(session_id)
+    }
+
+    fn call(&mut self, req: RawMessage, interaction: &Interaction) -> Exchange<RawMessage> {
+        let request = WireMessage::from(&req);
+        let exchange = self.inner.call(req, interaction);
+        let outcome = match &exchange {
+            Exchange::Done(Ok(resp)) => Outcome::Response(resp.into()),
+            Exchange::Done(Err(err)) => Outcome::Error(err.to_string()),
+            Exchange::TransportFailed => Outcome::TransportFailed,
+        };
+        self.recorder.write(&Record::Exchange {
+            device: self.device.clone(),
+            request,
+            outcome,
+        });
+        exchange
+    }
+
+    fn end_session(&mut self) {
+        self.inner.end_session()
+    }
+}
+
+/// Serves the answers of a recording back in order, standing in for the device.
+///
+/// The requests must come in the recorded order and with the recorded message types. Their
+/// payloads may differ, e.g. freshly blinded messages, which is only logged. Recorded transport
+/// failures are returned as errors rather than triggering a reconnect, since re-opening would
+/// start the recording over.
+pub struct ReplayDevice {
+    features: Option<protos::Features>,
+    exchanges: VecDeque<(WireMessage, Outcome)>,
+}
+
+impl ReplayDevice {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let io_err =
+            |e: std::io::Error| Error::Custom(format!("reading {}: {}", path.display(), e));
+        let file = File::open(path).map_err(io_err)?;
+
+        // only the first device of the recording is replayed
+        let mut replayed: Option<String> = None;
+        let mut features = None;
+        let mut exchanges = VecDeque::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(io_err)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: Record = serde_json::from_str(&line).map_err(|e| {
+                Error::Custom(format!("{} line {}: {}", path.display(), index + 1, e))
+            })?;
+            match record {
+                Record::Open {
+                    device,
+                    features: recorded,
+                } if replayed.is_none() => {
+                    if let Some(recorded) = recorded {
+                        features = Some(RawMessage::try_from(&recorded)?.decode()?);
+                    }
+                    replayed = Some(device);
+                }
+                Record::Exchange {
+                    device,
+                    request,
+                    outcome,
+                } if replayed.as_ref() == Some(&device) => {
+                    exchanges.push_back((request, outcome));
+                }
+                _ => {}
+            }
+        }
+        tracing::info!(
+            "Replaying {} device exchanges from {}",
+            exchanges.len(),
+            path.display()
+        );
+        Ok(Self {
+            features,
+            exchanges,
+        })
+    }
+
+    fn replay(&mut self, req: RawMessage) -> Result<RawMessage, Error> {
+        let request = WireMessage::from(&req);
+        let Some((recorded, outcome)) = self.exchanges.pop_front() else {
+            return Err(TrezorSignatoryError::Transport(format!(
+                "recording exhausted at {}",
+                request.name
+            ))
+            .into());
+        };
+        if recorded.message_type != request.message_type {
+            return Err(TrezorSignatoryError::ResponseMismatch(format!(
+                "replay expected {}, got {}",
+                recorded.name, request.name
+            ))
+            .into());
+        }
+        if recorded.payload != request.payload {
+            tracing::debug!(
+                "Replayed {} differs from the recorded request",
+                request.name
+            );
+        }
+        match outcome {
+            Outcome::Response(resp) => RawMessage::try_from(&resp),
+            Outcome::Error(err) => Err(Error::Custom(err)),
+            Outcome::TransportFailed => Err(TrezorSignatoryError::Transport(
+                "recorded transport failure".to_string(),
+            )
+            .into()),
+        }
+    }
+}
+
+impl CashuDevice for ReplayDevice {
+    fn features(&self) -> Option<&protos::Features> {
+        self.features.as_ref()
+    }
+
+    fn init_device(&mut self, _session_id: Option<&[u8]>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn call(&mut self, req: RawMessage, _interaction: &Interaction) -> Exchange<RawMessage> {
+        Exchange::Done(self.replay(req))
+    }
+
+    fn end_session(&mut self) {}
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Weak};
+
+use cdk_common::Error;
+use cdk_common::nuts::{BlindSignature, BlindedMessage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+
+use crate::state_file;
+
+/// Settings for remembering recent blind_sign responses
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResponseCacheConfig {
+    /// Number of recent batches remembered, 0 disables the cache
+    pub capacity: usize,
+    /// File the cache is kept in so retries across a restart are answered too
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    key: String,
+    signatures: Vec<BlindSignature>,
+}
+
+/// Least recently used first
+#[derive(Default)]
+struct CacheState {
+    order: VecDeque<String>,
+    responses: HashMap<String, Vec<BlindSignature>>,
+}
+
+/// Bounded LRU of blind_sign responses keyed by the batch contents.
+///
+/// Blinded secrets are random per request, so a batch seen again is a retry of the same
+/// request. Answering it from the cache returns the same signatures the device would
+/// produce, without another device round trip or counting it twice against volume limits.
+///
+/// The cache holds signatures the mint has already received, so it is off by default. The
+/// state file is written by a background task after each insert, never on the request path.
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    state: Mutex<CacheState>,
+    /// Wakes the task writing the state file
+    changed: Arc<Notify>,
+}
+
+impl ResponseCache {
+    /// Create the cache, restoring it from the state file if there is one, and start the
+    /// task keeping that file up to date
+    pub fn load(config: ResponseCacheConfig) -> Result<Arc<Self>, Error> {
+        let mut state = CacheState::default();
+        if let Some(path) = config.state_file.as_ref().filter(|path| path.exists()) {
+            let contents = std::fs::read(path)
+                .map_err(|e| Error::Custom(format!("reading {}: {}", path.display(), e)))?;
+            let stored: Vec<CachedResponse> = serde_json::from_slice(&contents)
+                .map_err(|e| Error::Custom(format!("parsing {}: {}", path.display(), e)))?;
+            for response in stored {
+                state.order.push_back(response.key.clone());
+                state.responses.insert(response.key, response.signatures);
+            }
+        }
+        let cache = Arc::new(Self {
+            config,
+            state: Mutex::new(state),
+            changed: Arc::new(Notify::new()),
+        });
+        if let Some(path) = cache.config.state_file.clone() {
+            tokio::spawn(persist(Arc::downgrade(&cache), cache.changed.clone(), path));
+        }
+        Ok(cache)
+    }
+
+    /// Signatures returned for an identical batch before
+    pub fn get(&self, messages: &[BlindedMessage]) -> Option<Vec<BlindSignature>> {
+        let key = batch_key(messages);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let signatures = state.responses.get(&key)?.clone();
+        state.order.retain(|k| k != &key);
+        state.order.push_back(key);
+        Some(signatures)
+    }
+
+    pub fn insert(&self, messages: &[BlindedMessage], signatures: &[BlindSignature]) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        let key = batch_key(messages);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state
+            .responses
+            .insert(key.clone(), signatures.to_vec())
+            .is_none()
+        {
+            state.order.push_back(key);
+        }
+        while state.order.len() > self.config.capacity {
+            if let Some(evicted) = state.order.pop_front() {
+                state.responses.remove(&evicted);
+            }
+        }
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    /// The cached responses, least recently used first, as written to the state file
+    fn stored(&self) -> Vec<CachedResponse> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .order
+            .iter()
+            .filter_map(|key| {
+                state.responses.get(key).map(|signatures| CachedResponse {
+                    key: key.clone(),
+                    signatures: signatures.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Drop for ResponseCache {
+    fn drop(&mut self) {
+        // lets the writing task see the cache is gone
+        self.changed.notify_one();
+    }
+}
+
+/// Write the cache to `path` whenever `changed` fires, until the cache is dropped. Inserts
+/// made while a write is in progress are written together by the next one.
+async fn persist(cache: Weak<ResponseCache>, changed: Arc<Notify>, path: PathBuf) {
+    loop {
+        changed.notified().await;
+        let Some(stored) = cache.upgrade().map(|cache| cache.stored()) else {
+            return;
+        };
+        let result = match serde_json::to_vec(&stored) {
+            Ok(contents) => state_file::write(&path, contents).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = result {
+            tracing::warn!("Failed to write {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Hash of everything the device signs for each message, in order
+fn batch_key(messages: &[BlindedMessage]) -> String {
+    let mut hasher = Sha256::new();
+    for message in messages {
+        hasher.update(message.keyset_id.to_bytes());
+        hasher.update(u64::from(message.amount).to_be_bytes());
+        hasher.update(message.blinded_secret.to_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
@@ -0,0 +1,307 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use anyhow::{Context as _, Result};
+use bytes::Bytes;
+use cdk_common::bitcoin::secp256k1::schnorr::Signature;
+use cdk_common::{PublicKey, SecretKey};
+use futures::future::BoxFuture;
+use http_body::{Body, Frame, SizeHint};
+use sha2::{Digest, Sha256};
+use tonic::codegen::Service;
+use tonic::codegen::http::{HeaderMap, HeaderValue, Request, Response};
+use tonic::server::NamedService;
+
+/// Trailer carrying the hex encoded BIP-340 signature of the response
+pub const SIGNATURE_TRAILER: &str = "x-signatory-signature";
+
+/// Prefix of every signed message, so the key cannot be tricked into signing anything else
+const DOMAIN: &[u8] = b"cdk-signatory-trezor/response/v1";
+
+/// Read the hex encoded secp256k1 secret key responses are signed with
+pub fn load_key(path: &Path) -> Result<SecretKey> {
+    let hex =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let key = SecretKey::from_hex(hex.trim())
+        .with_context(|| format!("{} does not hold a hex secret key", path.display()))?;
+    tracing::info!("Signing responses with key {}", key.public_key().to_hex());
+    Ok(key)
+}
+
+/// Check the [`SIGNATURE_TRAILER`] of one call, as a mint does with the public key it was
+/// configured with.
+///
+/// `path` is the method path, e.g. `/cdk_signatory.Signatory/BlindSign`, `request` and
+/// `response` the bodies as sent on the wire, with their gRPC frame headers, and `status` the
+/// `grpc-status` trailer.
+pub fn verify(
+    pubkey: &PublicKey,
+    path: &str,
+    request: &[u8],
+    response: &[u8],
+    status: &[u8],
+    signature: &str,
+) -> Result<()> {
+    let signature: Signature = signature
+        .trim()
+        .parse()
+        .context("the signature is not a hex BIP-340 signature")?;
+    let message = signed_message(
+        path,
+        Sha256::digest(request).into(),
+        Sha256::digest(response).into(),
+        status,
+    );
+    pubkey
+        .verify(&message, &signature)
+        .context("the signature does not match the response")
+}
+
+/// Message signed for one call: the method, the request and the response bytes as sent on the
+/// wire, and the gRPC status. The mint computes the same from its side of the channel.
+fn signed_message(path: &str, request: [u8; 32], response: [u8; 32], status: &[u8]) -> Vec<u8> {
+    let mut message = DOMAIN.to_vec();
+    message.push(0);
+    message.extend_from_slice(path.as_bytes());
+    message.push(0);
+    message.extend_from_slice(&request);
+    message.extend_from_slice(&response);
+    message.extend_from_slice(status);
+    message
+}
+
+/// Sign every response of `inner` with `key` in the [`SIGNATURE_TRAILER`] trailer, or pass
+/// calls through unchanged without a key
+pub fn wrap<S>(key: Option<Arc<SecretKey>>, inner: S) -> Signed<S> {
+    Signed { key, inner }
+}
+
+/// Service wrapped by [`wrap`]
+#[derive(Clone)]
+pub struct Signed<S> {
+    key: Option<Arc<SecretKey>>,
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for Signed<S>
+where
+    S: Service<Request<HashingBody<B>>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = Response<SignedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let path = req.uri().path().to_string();
+        let request = Arc::new(Mutex::new(Sha256::new()));
+        let req = req.map(|inner| HashingBody {
+            inner,
+            hasher: self.key.is_some().then(|| request.clone()),
+        });
+        let key = self.key.clone();
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let response = fut.await?;
+            // errors raised before the handler, e.g. by authentication or the queue, are sent
+            // as headers only and are left unsigned
+            let trailers_only = response.headers().contains_key("grpc-status");
+            Ok(response.map(|inner| SignedBody {
+                inner,
+                signing: key.filter(|_| !trailers_only).map(|key| Signing {
+                    key,
+                    path,
+                    request,
+                    response: Sha256::new(),
+                }),
+                done: false,
+            }))
+        })
+    }
+}
+
+impl<S: NamedService> NamedService for Signed<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Request body that feeds the bytes read from it into a shared hash
+pub struct HashingBody<B> {
+    inner: B,
+    hasher: Option<Arc<Mutex<Sha256>>>,
+}
+
+impl<B> Body for HashingBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let frame = std::task::ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let (Some(Ok(frame)), Some(hasher)) = (&frame, &self.hasher) {
+            if let Some(data) = frame.data_ref() {
+                hasher
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .update(data);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+struct Signing {
+    key: Arc<SecretKey>,
+    path: String,
+    request: Arc<Mutex<Sha256>>,
+    response: Sha256,
+}
+
+impl Signing {
+    fn sign(&self, trailers: &mut HeaderMap) {
+        let status = trailers
+            .get("grpc-status")
+            .map(HeaderValue::as_bytes)
+            .unwrap_or_default();
+        let request = self
+            .request
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .finalize();
+        let message = signed_message(
+            &self.path,
+            request.into(),
+            self.response.clone().finalize().into(),
+            status,
+        );
+        match self.key.sign(&message) {
+            Ok(signature) => {
+                if let Ok(value) = HeaderValue::from_str(&signature.to_string()) {
+                    trailers.insert(SIGNATURE_TRAILER, value);
+                }
+            }
+            Err(err) => tracing::error!("Failed to sign response: {}", err),
+        }
+    }
+}
+
+/// Response body that appends the signature of the response to its trailers
+pub struct SignedBody<B> {
+    inner: B,
+    signing: Option<Signing>,
+    /// Set once the trailers were sent
+    done: bool,
+}
+
+impl<B> Body for SignedBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let this = &mut *self;
+        let Some(signing) = &mut this.signing else {
+            return Pin::new(&mut this.inner).poll_frame(cx);
+        };
+        match std::task::ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+            Some(Ok(mut frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    signing.response.update(data);
+                } else if let Some(trailers) = frame.trailers_mut() {
+                    signing.sign(trailers);
+                    this.done = true;
+                }
+                Poll::Ready(Some(Ok(frame)))
+            }
+            // bodies ending without trailers get trailers of their own for the signature
+            None => {
+                let mut trailers = HeaderMap::new();
+                signing.sign(&mut trailers);
+                this.done = true;
+                Poll::Ready(Some(Ok(Frame::trailers(trailers))))
+            }
+            err => Poll::Ready(err),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || (self.signing.is_none() && self.inner.is_end_stream())
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "/cdk_signatory.Signatory/BlindSign";
+
+    fn sign(key: &SecretKey, request: &[u8], response: &[u8], status: &[u8]) -> String {
+        let message = signed_message(
+            PATH,
+            Sha256::digest(request).into(),
+            Sha256::digest(response).into(),
+            status,
+        );
+        key.sign(&message).unwrap().to_string()
+    }
+
+    #[test]
+    fn verifies_signed_response() {
+        let key = SecretKey::generate();
+        let signature = sign(&key, b"request", b"response", b"0");
+        verify(
+            &key.public_key(),
+            PATH,
+            b"request",
+            b"response",
+            b"0",
+            &signature,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn rejects_altered_response() {
+        let key = SecretKey::generate();
+        let signature = sign(&key, b"request", b"response", b"0");
+        let pubkey = key.public_key();
+        assert!(verify(&pubkey, PATH, b"request", b"altered", b"0", &signature).is_err());
+        assert!(verify(&pubkey, PATH, b"altered", b"response", b"0", &signature).is_err());
+        assert!(verify(&pubkey, PATH, b"request", b"response", b"13", &signature).is_err());
+        assert!(verify(&pubkey, "/other", b"request", b"response", b"0", &signature).is_err());
+        let other = SecretKey::generate().public_key();
+        assert!(verify(&other, PATH, b"request", b"response", b"0", &signature).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use cdk_common::nuts::CurrencyUnit;
+use cdk_signatory::signatory::{RotateKeyArguments, Signatory, SignatoryKeySet, SignatoryKeysets};
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::signatory::{TrezorSignatory, derivation_indexes, unix_now};
+use crate::state_file;
+
+/// Pause before retrying a rotation that failed, e.g. while the device was unplugged
+const RETRY_DELAY: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RotationConfig {
+    /// Rotate the keysets this often, e.g. `30d` or `12h`. Unset disables scheduled rotation.
+    #[serde(deserialize_with = "interval")]
+    pub rotate_every: Option<Duration>,
+    /// Units to rotate, empty rotates every unit the device serves
+    pub units: Vec<CurrencyUnit>,
+    /// Give each new keyset a `final_expiry` this far after its rotation, e.g. `90d`
+    #[serde(deserialize_with = "interval")]
+    pub expire_after: Option<Duration>,
+    /// File recording the last rotation of each unit, so the schedule survives restarts
+    pub state_file: Option<PathBuf>,
+    /// POST the new keysets to this URL after every rotation, e.g. a hook restarting the mint
+    pub notify_url: Option<String>,
+}
+
+/// Parse an interval like `90s`, `30m`, `12h`, `30d` or `2w`, the unit is required
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval {:?}, expected e.g. 30d or 12h", value))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "" => {
+            return Err(format!(
+                "interval {:?} needs a unit, e.g. {}d or {}h",
+                value, number, number
+            ));
+        }
+        _ => {
+            return Err(format!(
+                "unknown interval unit {:?}, use s, m, h, d or w",
+                unit
+            ));
+        }
+    };
+    match number.checked_mul(unit_secs) {
+        Some(0) => Err("interval must not be zero".to_string()),
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(format!("interval {:?} is too long", value)),
+    }
+}
+
+fn interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| parse_interval(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RotationState {
+    /// Unix timestamp in seconds of the last rotation, by unit
+    last_rotation: BTreeMap<String, u64>,
+}
+
+fn load_state(path: &Path) -> Result<RotationState> {
+    if !path.exists() {
+        return Ok(RotationState::default());
+    }
+    let contents = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_slice(&contents).with_context(|| format!("parsing {}", path.display()))
+}
+
+async fn store_state(path: &Path, state: &RotationState) -> Result<()> {
+    let contents = serde_json::to_vec(state)?;
+    state_file::write(path, contents)
+        .await
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+/// Rotate the keysets on the configured schedule in the background.
+///
+/// Each unit is rotated on its own schedule, so a unit that failed to rotate is retried
+/// without rotating the others again. The device deactivates the previous keyset of a unit
+/// when it rotates, and `rotate_keyset` refreshes the keyset cache, so mints pick up the new
+/// keysets on their next keyset read. Units without a recorded rotation are first rotated one
+/// interval after start.
+pub fn spawn_scheduled_rotation(
+    signatory: TrezorSignatory,
+    config: RotationConfig,
+) -> Result<Option<JoinHandle<()>>> {
+    let Some(every) = config.rotate_every else {
+        return Ok(None);
+    };
+    let mut state = match &config.state_file {
+        Some(path) => load_state(path)?,
+        None => RotationState::default(),
+    };
+    let started = unix_now();
+    tracing::info!("Rotating keysets every {} hours", every.as_secs() / 3600);
+
+    Ok(Some(tokio::spawn(async move {
+        let mut next_due = started.saturating_add(every.as_secs());
+        if let Some(earliest) = state.last_rotation.values().min() {
+            next_due = next_due.min(earliest.saturating_add(every.as_secs()));
+        }
+        loop {
+            tokio::time::sleep(Duration::from_secs(next_due.saturating_sub(unix_now()))).await;
+
+            if signatory.is_standby() || signatory.is_locked() {
+                tracing::info!(
+                    "Keyset rotation due, but {}, checking again in {} minutes",
+                    match signatory.is_standby() {
+                        true => "on standby",
+                        false => "the devices are locked",
+                    },
+                    RETRY_DELAY.as_secs() / 60
+                );
+                next_due = unix_now().saturating_add(RETRY_DELAY.as_secs());
+                continue;
+            }
+            let keysets = match signatory.keysets().await {
+                Ok(keysets) => keysets,
+                Err(err) => {
+                    tracing::error!(
+                        "Scheduled keyset rotation failed, retrying in {} minutes: {}",
+                        RETRY_DELAY.as_secs() / 60,
+                        err
+                    );
+                    next_due = unix_now().saturating_add(RETRY_DELAY.as_secs());
+                    continue;
+                }
+            };
+
+            let mut rotated = Vec::new();
+            next_due = u64::MAX;
+            for unit in rotated_units(&keysets, &config) {
+                let last = state
+                    .last_rotation
+                    .get(&unit.to_string())
+                    .copied()
+                    .unwrap_or(started);
+                let due = last.saturating_add(every.as_secs());
+                if due > unix_now() {
+                    next_due = next_due.min(due);
+                    continue;
+                }
+                match rotate_unit(&signatory, &keysets, &unit, &config).await {
+                    Ok(keyset) => {
+                        let now = unix_now();
+                        state.last_rotation.insert(unit.to_string(), now);
+                        next_due = next_due.min(now.saturating_add(every.as_secs()));
+                        if let Some(path) = &config.state_file {
+                            if let Err(err) = store_state(path, &state).await {
+                                tracing::error!("Failed to record keyset rotation: {:#}", err);
+                            }
+                        }
+                        rotated.push(keyset);
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            "Scheduled rotation of the {} keyset failed, retrying in {} minutes: {}",
+                            unit,
+                            RETRY_DELAY.as_secs() / 60,
+                            err
+                        );
+                        next_due = next_due.min(unix_now().saturating_add(RETRY_DELAY.as_secs()));
+                    }
+                }
+            }
+            if next_due == u64::MAX {
+                // no unit to rotate yet, look again in one interval
+                next_due = unix_now().saturating_add(every.as_secs());
+            }
+            if let Some(url) = config.notify_url.as_ref().filter(|_| !rotated.is_empty()) {
+                if let Err(err) = notify_mint(url, &rotated).await {
+                    tracing::error!("Failed to notify about keyset rotation: {:#}", err);
+                }
+            }
+        }
+    })))
+}
+
+/// Units of `keysets` the schedule rotates
+fn rotated_units(keysets: &SignatoryKeysets, config: &RotationConfig) -> Vec<CurrencyUnit> {
+    let mut units: Vec<CurrencyUnit> = Vec::new();
+    for keyset in &keysets.keysets {
+        if !units.contains(&keyset.unit)
+            && (config.units.is_empty() || config.units.contains(&keyset.unit))
+        {
+            units.push(keyset.unit.clone());
+        }
+    }
+    units
+}
+
+/// Rotate `unit`, keeping the amounts and input fee of its current keyset
+async fn rotate_unit(
+    signatory: &TrezorSignatory,
+    keysets: &SignatoryKeysets,
+    unit: &CurrencyUnit,
+    config: &RotationConfig,
+) -> Result<SignatoryKeySet, cdk_common::Error> {
+    let indexes = derivation_indexes(keysets);
+    // the active keyset, or the last derived one if they all expired
+    let current = keysets
+        .keysets
+        .iter()
+        .filter(|ks| ks.unit == *unit)
+        .max_by_key(|ks| (ks.active, indexes.get(&ks.id)))
+        .expect("units are taken from the keysets");
+    let args = RotateKeyArguments {
+        unit: unit.clone(),
+        amounts: current.amounts.clone(),
+        input_fee_ppk: current.input_fee_ppk,
+        final_expiry: config
+            .expire_after
+            .map(|after| unix_now().saturating_add(after.as_secs())),
+    };
+    let keyset = signatory.rotate_keyset(args).await?;
+    tracing::info!("Rotated {} keyset {} to {}", unit, current.id, keyset.id);
+    Ok(keyset)
+}
+
+async fn notify_mint(url: &str, rotated: &[SignatoryKeySet]) -> Result<()> {
+    let keysets: Vec<_> = rotated
+        .iter()
+        .map(|ks| {
+            serde_json::json!({
+                "id": ks.id.to_string(),
+                "unit": ks.unit.to_string(),
+                "final_expiry": ks.final_expiry,
+            })
+        })
+        .collect();
+    reqwest::Client::new()
+        .post(url)
+        .json(&serde_json::json!({
+            "event": "keysets_rotated",
+            "keysets": keysets,
+        }))
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("posting to {}", url))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_interval_units() {
+        let cases = [
+            ("90s", 90),
+            ("30m", 30 * 60),
+            ("12h", 12 * 3600),
+            ("30d", 30 * 86400),
+            ("2w", 14 * 86400),
+            (" 1d ", 86400),
+        ];
+        for (value, secs) in cases {
+            assert_eq!(
+                parse_interval(value),
+                Ok(Duration::from_secs(secs)),
+                "{:?}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn parse_interval_requires_a_unit() {
+        let err = parse_interval("30").unwrap_err();
+        assert!(err.contains("needs a unit"), "{}", err);
+    }
+
+    #[test]
+    fn parse_interval_rejects_invalid_values() {
+        for value in ["", "d", "5x", "1.5d", "0d", "-1d", "18446744073709551615w"] {
+            assert!(parse_interval(value).is_err(), "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Result, bail};
+use cdk_common::Error;
+use cdk_common::nuts::{CurrencyUnit, Id};
+use cdk_signatory::signatory::SignatoryKeysets;
+use serde::Deserialize;
+
+use crate::signatory::TrezorPool;
+use crate::software::SoftwareSigner;
+use crate::trezor::{DeviceSelector, DeviceTransport};
+
+/// Which backend serves the keysets of each unit
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// Backend name per unit, e.g. `usd = "usd-trezor"`. Units not listed go to the devices
+    /// of the `[device]` section.
+    pub units: HashMap<CurrencyUnit, String>,
+    pub backends: HashMap<String, BackendConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackendConfig {
+    /// Trezor devices on the transport of the `[device]` section, initialized from the same
+    /// seed and selected by serial or label
+    Device {
+        #[serde(default)]
+        serial: Vec<String>,
+        label: Option<String>,
+    },
+    /// Keys derived on the host from the BIP-39 mnemonic in `seed_file`
+    Software {
+        seed_file: PathBuf,
+        /// File recording the keysets, so rotations survive restarts
+        state_file: Option<PathBuf>,
+    },
+}
+
+impl BackendConfig {
+    /// One selector per device of a device backend
+    pub fn selectors(&self, transport: &DeviceTransport) -> Vec<DeviceSelector> {
+        let Self::Device { serial, label } = self else {
+            return Vec::new();
+        };
+        if serial.is_empty() {
+            return vec![DeviceSelector {
+                transport: transport.clone(),
+                serial: None,
+                label: label.clone(),
+            }];
+        }
+        serial
+            .iter()
+            .map(|serial| DeviceSelector {
+                transport: transport.clone(),
+                serial: Some(serial.clone()),
+                label: label.clone(),
+            })
+            .collect()
+    }
+}
+
+impl RoutingConfig {
+    /// Units routed to each named backend, checking that every backend exists and that
+    /// device backends do not fall back to whichever device is connected
+    pub fn routed_backends(&self) -> Result<Vec<(&String, &BackendConfig, Vec<CurrencyUnit>)>> {
+        for (unit, name) in &self.units {
+            if !self.backends.contains_key(name) {
+                bail!("unit {} is routed to unknown backend {:?}", unit, name);
+            }
+        }
+        let mut routed = Vec::new();
+        for (name, backend) in &self.backends {
+            if let BackendConfig::Device { serial, label } = backend {
+                if serial.is_empty() && label.is_none() {
+                    bail!("device backend {:?} needs a serial or label", name);
+                }
+            }
+            let units: Vec<CurrencyUnit> = self
+                .units
+                .iter()
+                .filter(|(_, routed_to)| *routed_to == name)
+                .map(|(unit, _)| unit.clone())
+                .collect();
+            if units.is_empty() {
+                tracing::warn!("No unit is routed to backend {:?}", name);
+                continue;
+            }
+            routed.push((name, backend, units));
+        }
+        Ok(routed)
+    }
+}
+
+/// Signer behind a route
+#[derive(Clone)]
+pub enum Backend {
+    Pool(Arc<TrezorPool>),
+    Software(Arc<SoftwareSigner>),
+}
+
+impl Backend {
+    fn same(&self, other: &Backend) -> bool {
+        match (self, other) {
+            (Self::Pool(a), Self::Pool(b)) => Arc::ptr_eq(a, b),
+            (Self::Software(a), Self::Software(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Route {
+    pub units: Vec<CurrencyUnit>,
+    pub backend: Backend,
+}
+
+/// Units served by other backends than the default device pool
+#[derive(Clone, Default)]
+pub struct Routes {
+    routes: Vec<Route>,
+}
+
+impl Routes {
+    /// Route `units` to `backend`, replacing earlier routes of those units
+    pub fn add(&mut self, units: Vec<CurrencyUnit>, backend: Backend) {
+        for route in &mut self.routes {
+            route.units.retain(|unit| !units.contains(unit));
+        }
+        self.routes.retain(|route| !route.units.is_empty());
+        self.routes.push(Route { units, backend });
+    }
+
+    /// Backend of `unit`, `None` for the default device pool
+    pub fn backend(&self, unit: &CurrencyUnit) -> Option<&Backend> {
+        self.routes
+            .iter()
+            .find(|route| route.units.contains(unit))
+            .map(|route| &route.backend)
+    }
+
+    /// Device pools of the routes, without the default pool
+    pub fn pools(&self) -> impl Iterator<Item = (&Arc<TrezorPool>, &[CurrencyUnit])> {
+        self.routes.iter().filter_map(|route| match &route.backend {
+            Backend::Pool(pool) => Some((pool, route.units.as_slice())),
+            Backend::Software(_) => None,
+        })
+    }
+
+    pub fn software(&self) -> impl Iterator<Item = &Arc<SoftwareSigner>> {
+        self.routes.iter().filter_map(|route| match &route.backend {
+            Backend::Software(software) => Some(software),
+            Backend::Pool(_) => None,
+        })
+    }
+
+    /// Group the positions of `keyset_ids` by the backend serving their keyset's unit, units
+    /// without a route going to `default`
+    pub fn split(
+        &self,
+        keyset_ids: impl Iterator<Item = Id>,
+        keysets: &SignatoryKeysets,
+        default: &Arc<TrezorPool>,
+    ) -> Result<Vec<(Backend, Vec<usize>)>, Error> {
+        let mut groups: Vec<(Backend, Vec<usize>)> = Vec::new();
+        for (index, keyset_id) in keyset_ids.enumerate() {
+            let unit = &keysets
+                .keysets
+                .iter()
+                .find(|ks| ks.id == keyset_id)
+                .ok_or(Error::UnknownKeySet)?
+                .unit;
+            let backend = self
+                .backend(unit)
+                .cloned()
+                .unwrap_or_else(|| Backend::Pool(default.clone()));
+            match groups.iter_mut().find(|(other, _)| other.same(&backend)) {
+                Some((_, positions)) => positions.push(index),
+                None => groups.push((backend, vec![index])),
+            }
+        }
+        Ok(groups)
+    }
+}
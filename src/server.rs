@@ -0,0 +1,342 @@
+use std::fs::{DirBuilder, Permissions};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use cdk_signatory::proto::server::CdkSignatoryServer;
+use cdk_signatory::proto::signatory_server::{self, SignatoryServer};
+use tokio::net::TcpListener;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+use tonic::Request;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Server;
+use tonic_health::ServingStatus;
+use tonic_health::server::HealthReporter;
+
+use crate::admin::{AdminServer, AdminService};
+use crate::auth::ClientAuth;
+use crate::config::ServerConfig;
+use crate::device::{DeviceHealth, SessionState};
+use crate::info::{InfoServer, InfoService};
+use crate::progress::{ProgressEvents, ProgressServer};
+use crate::queue::RequestQueue;
+use crate::response_signing;
+use crate::signatory::TrezorSignatory;
+use crate::systemd;
+use crate::tls::ReloadingTls;
+use crate::version;
+
+/// How often the device is probed to update the health service
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Descriptors of the admin, info and progress services, for gRPC reflection
+const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("descriptor");
+/// Descriptor of the cdk-signatory service
+const CDK_SIGNATORY_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("cdk_signatory_descriptor");
+
+/// Where the gRPC server accepts connections
+#[derive(Debug)]
+pub enum Listener {
+    /// One TCP listener per address
+    Tcp(Vec<SocketAddr>),
+    /// Unix domain socket, `mode` sets the socket file permissions (e.g. `0o660`)
+    Unix {
+        path: PathBuf,
+        mode: Option<u32>,
+    },
+    /// Sockets bound by systemd and passed in with socket activation
+    ActivatedTcp(std::net::TcpListener),
+    ActivatedUnix(std::os::unix::net::UnixListener),
+}
+
+impl Listener {
+    fn is_unix(&self) -> bool {
+        matches!(self, Listener::Unix { .. } | Listener::ActivatedUnix(_))
+    }
+}
+
+/// Serve the signatory gRPC service together with the standard `grpc.health.v1.Health` service
+/// and the progress service streaming `progress` events, and server reflection if enabled.
+///
+/// Once `shutdown` completes no new connections are accepted and the call returns after
+/// requests already in flight are answered.
+pub async fn serve(
+    signatory: Arc<TrezorSignatory>,
+    progress: ProgressEvents,
+    listener: Listener,
+    config: &ServerConfig,
+    queue: RequestQueue,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<()> {
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    spawn_health_monitor(signatory.clone(), health_reporter);
+    systemd::spawn_watchdog(signatory.clone());
+
+    // without TLS there is no client certificate, so an allow list would silently admit anyone
+    if !config.allowed_client_fingerprints.is_empty()
+        && (config.tls_dir.is_none() || listener.is_unix())
+    {
+        anyhow::bail!(
+            "allowed_client_fingerprints needs TLS, set tls_dir and listen on a TCP address"
+        );
+    }
+
+    let mut auth = ClientAuth::default();
+    let mut tls = None;
+    if let Some(tls_dir) = &config.tls_dir {
+        if listener.is_unix() {
+            tracing::warn!("TLS is not used on unix sockets, ignoring tls_dir");
+        } else {
+            let reloading = ReloadingTls::load(tls_dir, config.client_ca.as_deref(), &[b"h2"])?;
+            reloading.spawn_reload();
+            tls = Some(reloading);
+            auth = auth.with_fingerprints(&config.allowed_client_fingerprints);
+        }
+    }
+
+    if let Some(path) = &config.auth_token_file {
+        auth = auth.with_token(&load_token(path)?);
+    }
+
+    let signing_key = match &config.response_signing_key_file {
+        Some(path) => Some(Arc::new(response_signing::load_key(path)?)),
+        None => None,
+    };
+
+    let info_auth = auth.clone();
+    let info_service = InfoServer::with_interceptor(
+        InfoService::new(signatory.clone())
+            .with_response_key(signing_key.as_ref().map(|key| key.public_key())),
+        move |req: Request<()>| {
+            info_auth.check(&req)?;
+            Ok(req)
+        },
+    );
+    let (reflection_v1, reflection_v1alpha) = if config.reflection {
+        let reflection_auth = auth.clone();
+        let check = move |req: Request<()>| {
+            reflection_auth.check(&req)?;
+            Ok(req)
+        };
+        let builder = || {
+            tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(CDK_SIGNATORY_DESCRIPTOR_SET)
+                .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+        };
+        // grpcurl and older tools still ask the v1alpha service
+        (
+            Some(InterceptedService::new(
+                builder().build_v1()?,
+                check.clone(),
+            )),
+            Some(InterceptedService::new(builder().build_v1alpha()?, check)),
+        )
+    } else {
+        (None, None)
+    };
+    let progress_auth = auth.clone();
+    let progress_service = ProgressServer::with_interceptor(progress, move |req: Request<()>| {
+        progress_auth.check(&req)?;
+        Ok(req)
+    });
+    let signatory_service = SignatoryServer::with_interceptor(
+        CdkSignatoryServer::new(signatory.clone()),
+        move |req: Request<()>| {
+            auth.check(&req)?;
+            version::check(&req)?;
+            Ok(req)
+        },
+    );
+    let admin_service = match &config.admin_token_file {
+        Some(path) => {
+            let admin_auth = ClientAuth::default().with_token(&load_token(path)?);
+            Some(AdminServer::with_interceptor(
+                AdminService::new(signatory.clone()),
+                move |req: Request<()>| {
+                    admin_auth.check(&req)?;
+                    Ok(req)
+                },
+            ))
+        }
+        None => None,
+    };
+    let router = Server::builder()
+        .add_service(health_service)
+        .add_service(response_signing::wrap(
+            signing_key,
+            queue.wrap(signatory_service),
+        ))
+        .add_service(info_service)
+        .add_service(progress_service)
+        .add_optional_service(admin_service)
+        .add_optional_service(reflection_v1)
+        .add_optional_service(reflection_v1alpha);
+
+    let listeners = match listener {
+        Listener::Tcp(addrs) => {
+            let mut listeners = Vec::with_capacity(addrs.len());
+            for addr in addrs {
+                let listener = TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("binding {}", addr))?;
+                tracing::info!("Signatory listening on {}", addr);
+                listeners.push(listener);
+            }
+            listeners
+        }
+        Listener::ActivatedTcp(listener) => {
+            let listener = TcpListener::from_std(listener)?;
+            tracing::info!(
+                "Signatory listening on {} (socket activation)",
+                listener.local_addr()?
+            );
+            vec![listener]
+        }
+        Listener::Unix { path, mode } => {
+            let unix_listener = bind_unix(&path, mode)?;
+            tracing::info!("Signatory listening on unix:{}", path.display());
+            systemd::notify_ready();
+            router
+                .serve_with_incoming_shutdown(UnixListenerStream::new(unix_listener), shutdown)
+                .await?;
+            return Ok(());
+        }
+        Listener::ActivatedUnix(listener) => {
+            let listener = UnixListener::from_std(listener)?;
+            tracing::info!("Signatory listening on unix socket (socket activation)");
+            systemd::notify_ready();
+            router
+                .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+                .await?;
+            return Ok(());
+        }
+    };
+
+    systemd::notify_ready();
+    match tls {
+        // TLS is terminated here rather than by tonic so the certificate can be swapped
+        Some(tls) => {
+            router
+                .serve_with_incoming_shutdown(tls.incoming(listeners), shutdown)
+                .await?
+        }
+        None => {
+            let incoming = listeners.into_iter().map(TcpListenerStream::new);
+            router
+                .serve_with_incoming_shutdown(futures::stream::select_all(incoming), shutdown)
+                .await?
+        }
+    }
+
+    Ok(())
+}
+
+/// Bind a unix socket, replacing a stale socket file left behind by a previous run.
+///
+/// The socket is bound in a private directory next to `path` and only moved into place once
+/// it has `mode`, so other users cannot connect in between whatever the umask.
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<UnixListener> {
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("removing stale socket {}", path.display()))?;
+    }
+
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a socket path", path.display()))?;
+    let staging = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    // left behind by a previous run that crashed while binding under the same pid
+    let _ = std::fs::remove_dir_all(&staging);
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("creating {}", staging.display()))?;
+    let bound = bind_staged(&staging.join("socket"), path, mode);
+    if let Err(err) = std::fs::remove_dir_all(&staging) {
+        tracing::warn!("Failed to remove {}: {}", staging.display(), err);
+    }
+    bound
+}
+
+/// Bind at `staged`, set `mode` and move the socket to `path`
+fn bind_staged(staged: &Path, path: &Path, mode: Option<u32>) -> Result<UnixListener> {
+    let listener =
+        UnixListener::bind(staged).with_context(|| format!("binding {}", path.display()))?;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(staged, Permissions::from_mode(mode))
+            .with_context(|| format!("setting permissions on {}", path.display()))?;
+    }
+    std::fs::rename(staged, path)
+        .with_context(|| format!("moving socket to {}", path.display()))?;
+    Ok(listener)
+}
+
+pub fn load_token(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)
+        .with_context(|| format!("reading {}", path.display()))?
+        .trim()
+        .to_string();
+    if token.is_empty() {
+        anyhow::bail!("auth token file {} is empty", path.display());
+    }
+    Ok(token)
+}
+
+/// Periodically probe the devices and report NOT_SERVING while a pool has no device that can sign.
+///
+/// A device becoming disconnected, e.g. on a USB detach event, triggers a probe right away.
+fn spawn_health_monitor(signatory: Arc<TrezorSignatory>, reporter: HealthReporter) {
+    let mut sessions: Vec<_> = signatory
+        .devices()
+        .map(|device| device.session().subscribe())
+        .collect();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        let mut last = None;
+        loop {
+            let disconnected = futures::future::select_all(sessions.iter_mut().map(|session| {
+                Box::pin(async move {
+                    while session.changed().await.is_ok() {
+                        if *session.borrow_and_update() == SessionState::Disconnected {
+                            return;
+                        }
+                    }
+                    std::future::pending::<()>().await
+                })
+            }));
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = disconnected => {}
+            }
+            let health = signatory.health().await;
+            let status = match health {
+                _ if signatory.is_standby() => ServingStatus::NotServing,
+                DeviceHealth::Ready => ServingStatus::Serving,
+                DeviceHealth::Locked | DeviceHealth::Disconnected => ServingStatus::NotServing,
+            };
+            if last != Some(health) {
+                tracing::info!("Device health changed to {:?}", health);
+                last = Some(health);
+            }
+            // overall server status ("") and the signatory service itself
+            reporter.set_service_status("", status).await;
+            reporter
+                .set_service_status(signatory_server::SERVICE_NAME, status)
+                .await;
+        }
+    });
+}
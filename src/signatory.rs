@@ -1,125 +1,1801 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+use tokio::task::JoinHandle;
 
+use crate::approval::Approver;
+use crate::audit::AuditLog;
+use crate::device::{DeviceHealth, TrezorDevice};
+use crate::error::TrezorSignatoryError;
+use crate::fallback::SoftwareFallback;
+use crate::keyset_cache;
 use crate::mapping::TryIntoCdk;
-use crate::trezor::handle_trezor_call;
-use cdk_common::nuts::{BlindSignature, BlindedMessage, Proof};
-use cdk_common::{Error, Keys};
+use crate::metrics::{KeysetStats, SignatoryMetrics};
+use crate::policy::{SigningPolicy, batch_totals, batch_value_sats};
+use crate::quota::{ClientQuotas, ClientReservation};
+use crate::response_cache::ResponseCache;
+use crate::routing::{Backend, Routes};
+use crate::state_file;
+use crate::trezor::{Interaction, interaction_scope};
+use cdk_common::bitcoin::bip32::{ChildNumber, DerivationPath};
+use cdk_common::nuts::{
+    BlindSignature, BlindedMessage, Conditions, CurrencyUnit, Id, Proof, SigFlag,
+    SpendingConditions,
+};
+use cdk_common::{Amount, Error, Keys};
+use cdk_signatory::common::derivation_path_from_unit;
 use cdk_signatory::signatory::{RotateKeyArguments, Signatory, SignatoryKeySet, SignatoryKeysets};
-use trezor_client::{Trezor, protos};
+use serde::{Deserialize, Serialize};
+use trezor_client::{TrezorMessage, protos};
+use uuid::Uuid;
 
 const CACHE_ENABLED: bool = true;
 
+/// Tunables for how requests are forwarded to the device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignatoryOptions {
+    /// Maximum number of blinded messages or proofs sent to the device in a single message
+    pub max_batch_size: usize,
+    /// Check the DLEQ proof of every signature against the cached keyset keys
+    pub verify_dleq: bool,
+    /// Check proofs against the cached keysets before sending them to the device
+    pub preverify_proofs: bool,
+    /// Verify proofs that carry a DLEQ proof on the host, only the rest reach the device
+    pub host_verify_proofs: bool,
+    /// Check the P2PK (NUT-11) and HTLC (NUT-14) witnesses of locked proofs on the host
+    /// before verifying them
+    pub verify_spending_conditions: bool,
+    /// BIP32 account of the Cashu key tree on the device, so several mints can share one
+    /// device with distinct keys. Unset uses the firmware default.
+    pub account: Option<u32>,
+    /// Serve keysets and verify proofs only, refusing blind_sign and rotation.
+    /// Proofs carrying a DLEQ proof are verified on the host.
+    pub watch_only: bool,
+    /// Run every check against the policy but never sign: blind_sign logs the batch and fails,
+    /// verify_proofs only verifies on the host and rotation is refused
+    pub dry_run: bool,
+    /// Warn about keysets whose `final_expiry` is less than this many seconds away
+    pub expiry_warning_secs: u64,
+}
+
+impl Default for SignatoryOptions {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 32,
+            verify_dleq: false,
+            preverify_proofs: false,
+            host_verify_proofs: false,
+            verify_spending_conditions: false,
+            account: None,
+            watch_only: false,
+            dry_run: false,
+            expiry_warning_secs: 7 * DAY_SECS,
+        }
+    }
+}
+
+/// Caps on the value signed within rolling time windows, counted in sats across the sat and
+/// msat units, and per unit in the unit's own denomination
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VolumeLimits {
+    /// Maximum sats signed in any 60 minute window
+    pub hourly_sats: Option<u64>,
+    /// Maximum sats signed in any 24 hour window
+    pub daily_sats: Option<u64>,
+    /// Caps per unit, keyed by unit name, for units like `usd` that have no value in sats
+    pub units: BTreeMap<String, UnitVolumeLimits>,
+    /// File recording signed volume so limits survive restarts
+    pub state_file: Option<PathBuf>,
+}
+
+/// Caps on the amount of one unit signed, in the unit's denomination
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnitVolumeLimits {
+    /// Maximum amount signed in any 60 minute window
+    pub hourly: Option<u64>,
+    /// Maximum amount signed in any 24 hour window
+    pub daily: Option<u64>,
+}
+
+/// Value signed at a point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedVolume {
+    /// Unix timestamp in seconds
+    at: u64,
+    sats: u64,
+    /// Amount signed per unit, in the unit's denomination
+    #[serde(default)]
+    amounts: BTreeMap<String, u64>,
+}
+
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * HOUR_SECS;
+
+/// Enforces [`VolumeLimits`] so a compromised mint cannot drain value through the signatory
+pub struct VolumeLimiter {
+    limits: VolumeLimits,
+    history: Mutex<Vec<SignedVolume>>,
+}
+
+impl VolumeLimiter {
+    /// Create the limiter with no volume signed yet
+    pub fn new(limits: VolumeLimits) -> Self {
+        Self {
+            limits,
+            history: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Create the limiter, restoring history from the state file if there is one
+    pub fn load(limits: VolumeLimits) -> Result<Self, Error> {
+        let history = match &limits.state_file {
+            Some(path) if path.exists() => {
+                let contents = std::fs::read(path)
+                    .map_err(|e| Error::Custom(format!("reading {}: {}", path.display(), e)))?;
+                serde_json::from_slice(&contents)
+                    .map_err(|e| Error::Custom(format!("parsing {}: {}", path.display(), e)))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            limits,
+            history: Mutex::new(history),
+        })
+    }
+
+    /// Record `sats` and the per unit `amounts` as signed if that stays within the limits
+    pub async fn reserve(
+        &self,
+        sats: u64,
+        amounts: BTreeMap<String, u64>,
+    ) -> Result<SignedVolume, Error> {
+        let now = unix_now();
+        let mut history = self.history.lock().await;
+        history.retain(|entry| entry.at + DAY_SECS > now);
+
+        let window_total = |secs: u64, amount: &dyn Fn(&SignedVolume) -> u64| -> u64 {
+            history
+                .iter()
+                .filter(|entry| entry.at + secs > now)
+                .map(amount)
+                .sum()
+        };
+        for (cap, secs, name) in [
+            (self.limits.hourly_sats, HOUR_SECS, "hourly"),
+            (self.limits.daily_sats, DAY_SECS, "daily"),
+        ] {
+            if let Some(cap) = cap {
+                if window_total(secs, &|entry| entry.sats).saturating_add(sats) > cap {
+                    return Err(TrezorSignatoryError::Policy(format!(
+                        "{} signing limit of {} sats reached",
+                        name, cap
+                    ))
+                    .into());
+                }
+            }
+        }
+        for (unit, limits) in &self.limits.units {
+            let Some(&amount) = amounts.get(unit) else {
+                continue;
+            };
+            for (cap, secs, name) in [
+                (limits.hourly, HOUR_SECS, "hourly"),
+                (limits.daily, DAY_SECS, "daily"),
+            ] {
+                if let Some(cap) = cap {
+                    let signed = window_total(secs, &|entry| {
+                        entry.amounts.get(unit).copied().unwrap_or_default()
+                    });
+                    if signed.saturating_add(amount) > cap {
+                        return Err(TrezorSignatoryError::Policy(format!(
+                            "{} signing limit of {} {} reached",
+                            name, cap, unit
+                        ))
+                        .into());
+                    }
+                }
+            }
+        }
+
+        let entry = SignedVolume {
+            at: now,
+            sats,
+            amounts,
+        };
+        history.push(entry.clone());
+        self.persist(&history).await?;
+        Ok(entry)
+    }
+
+    /// Give back a reservation whose signing failed
+    pub async fn release(&self, entry: &SignedVolume) -> Result<(), Error> {
+        let mut history = self.history.lock().await;
+        if let Some(pos) = history.iter().position(|e| e == entry) {
+            history.remove(pos);
+        }
+        self.persist(&history).await
+    }
+
+    /// Write `history` to the state file, called with the history lock held so writes land in
+    /// order
+    async fn persist(&self, history: &[SignedVolume]) -> Result<(), Error> {
+        let Some(path) = &self.limits.state_file else {
+            return Ok(());
+        };
+        let contents = serde_json::to_vec(history).map_err(|e| Error::Custom(e.to_string()))?;
+        state_file::write(path, contents)
+            .await
+            .map_err(|e| Error::Custom(format!("writing {}: {}", path.display(), e)))
+    }
+}
+
+/// Seconds since the unix epoch
+pub fn unix_now() -> u64 {
+    unix_now_ms() / 1000
+}
+
+/// Milliseconds since the unix epoch
+pub fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Devices initialized from the same seed, used interchangeably to sign in parallel
+pub struct TrezorPool {
+    devices: Vec<Arc<TrezorDevice>>,
+    next: AtomicUsize,
+    /// Interaction of the signatory owning the pool, used for its calls on shared devices
+    interaction: Option<Interaction>,
+}
+
+impl TrezorPool {
+    pub fn new(devices: Vec<Arc<TrezorDevice>>) -> Result<Self, Error> {
+        if devices.is_empty() {
+            return Err(Error::Custom("Device pool must not be empty".to_string()));
+        }
+        Ok(Self {
+            devices,
+            next: AtomicUsize::new(0),
+            interaction: None,
+        })
+    }
+
+    /// Answer interaction requests of the pool's calls through `interaction` rather than the
+    /// one the devices were opened with, for devices shared with other signatories
+    pub fn with_interaction(mut self, interaction: Interaction) -> Self {
+        self.interaction = Some(interaction);
+        self
+    }
+
+    /// Run `fut`, which calls devices of this pool, with the pool's interaction
+    pub async fn scope<F: Future>(&self, fut: F) -> F::Output {
+        match &self.interaction {
+            Some(interaction) => interaction_scope(interaction.clone(), fut).await,
+            None => fut.await,
+        }
+    }
+
+    /// Device used for keyset reads
+    pub fn primary(&self) -> &Arc<TrezorDevice> {
+        &self.devices[0]
+    }
+
+    pub fn devices(&self) -> &[Arc<TrezorDevice>] {
+        &self.devices
+    }
+
+    /// Pick an idle device if there is one, otherwise queue round-robin
+    fn pick(&self) -> &Arc<TrezorDevice> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let len = self.devices.len();
+        (0..len)
+            .map(|i| &self.devices[(start + i) % len])
+            .find(|device| !device.is_busy())
+            .unwrap_or(&self.devices[start % len])
+    }
+
+    /// Health of the best device in the pool as of the last call or probe of each
+    pub fn cached_health(&self) -> DeviceHealth {
+        DeviceHealth::best(self.devices.iter().map(|device| device.cached_health()))
+    }
+
+    /// Health of the best device in the pool, signing is possible as long as one is ready
+    pub async fn health(&self) -> DeviceHealth {
+        let mut health = DeviceHealth::Disconnected;
+        for device in &self.devices {
+            match device.health().await {
+                DeviceHealth::Ready => return DeviceHealth::Ready,
+                DeviceHealth::Locked => health = DeviceHealth::Locked,
+                DeviceHealth::Disconnected => {}
+            }
+        }
+        health
+    }
+
+    /// Close every device, waiting up to `timeout` for calls in progress
+    pub async fn close(&self, timeout: Duration) {
+        futures::future::join_all(self.devices.iter().map(|device| device.close(timeout))).await;
+    }
+
+    /// Send `req` to the next available device
+    pub async fn call<S, R>(&self, req: S) -> Result<R, Error>
+    where
+        S: TrezorMessage + Clone + Send + 'static,
+        R: TrezorMessage + Send + 'static,
+    {
+        self.scope(self.pick().call(req)).await
+    }
+}
+
 #[derive(Clone)]
 pub struct TrezorSignatory {
-    pub trezor: Arc<Mutex<Trezor>>,
-    pub cached_keysets: Option<SignatoryKeysets>,
+    pub pool: Arc<TrezorPool>,
+    pub cached_keysets: Arc<RwLock<Option<SignatoryKeysets>>>,
+    /// Protobuf form of `cached_keysets` per pool, keyed by the pool's address and cleared
+    /// whenever the cache is replaced
+    keysets_proto: Arc<std::sync::Mutex<HashMap<usize, Arc<Vec<protos::KeySet>>>>>,
+    pub options: SignatoryOptions,
+    pub policy: SigningPolicy,
+    pub limiter: Arc<VolumeLimiter>,
+    pub audit: Option<Arc<AuditLog>>,
+    /// File the keyset cache is persisted to
+    pub keyset_cache_file: Option<PathBuf>,
+    /// Recent blind_sign responses, answers retried requests without the device
+    pub responses: Option<Arc<ResponseCache>>,
+    /// Software signer used while no device is reachable
+    pub fallback: Option<Arc<SoftwareFallback>>,
+    /// External sign-off required for large batches
+    approver: Option<Arc<Approver>>,
+    /// Rate and volume quotas per client certificate
+    quotas: Option<Arc<ClientQuotas>>,
+    /// Units served by other devices or in software instead of `pool`
+    pub routes: Routes,
+    /// Held while the cache is filled from the device so concurrent cold reads fetch once
+    cold_fetch: Arc<Mutex<()>>,
+    /// Set while signing is paused by an operator
+    paused: Arc<AtomicBool>,
+    /// Set while the devices are locked by an operator, until they are unlocked again
+    locked: Arc<AtomicBool>,
+    /// Set while the peer of a standby pair serves instead of this instance
+    standby: Arc<AtomicBool>,
+    pub metrics: Arc<SignatoryMetrics>,
+    /// Signatures issued per keyset
+    pub keyset_stats: Arc<KeysetStats>,
 }
 
 impl TrezorSignatory {
-    pub async fn new(trezor: Arc<Mutex<Trezor>>) -> Result<Self, Error> {
+    pub async fn new(
+        pool: Arc<TrezorPool>,
+        options: SignatoryOptions,
+        policy: SigningPolicy,
+        limits: VolumeLimits,
+    ) -> Result<Self, Error> {
+        if options.max_batch_size == 0 {
+            return Err(Error::Custom(
+                "max_batch_size must be at least 1".to_string(),
+            ));
+        }
         Ok(Self {
-            trezor,
-            cached_keysets: None,
+            pool,
+            cached_keysets: Arc::new(RwLock::new(None)),
+            keysets_proto: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            options,
+            policy,
+            limiter: Arc::new(VolumeLimiter::load(limits)?),
+            audit: None,
+            keyset_cache_file: None,
+            responses: None,
+            fallback: None,
+            approver: None,
+            quotas: None,
+            routes: Routes::default(),
+            cold_fetch: Arc::new(Mutex::new(())),
+            paused: Arc::new(AtomicBool::new(false)),
+            locked: Arc::new(AtomicBool::new(false)),
+            standby: Arc::new(AtomicBool::new(false)),
+            metrics: Arc::new(SignatoryMetrics::default()),
+            keyset_stats: Arc::new(KeysetStats::default()),
         })
     }
 
-    pub async fn update_cached_keysets(&mut self) -> Result<(), Error> {
-        self.cached_keysets = Some(self.keysets().await?);
+    /// Record every blind_sign and verify_proofs call in `audit`
+    pub fn with_audit_log(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Answer retried blind_sign requests from `responses`
+    pub fn with_response_cache(mut self, responses: Arc<ResponseCache>) -> Self {
+        self.responses = Some(responses);
+        self
+    }
+
+    /// Count issued signatures per keyset in `stats`, e.g. ones persisted across restarts
+    pub fn with_keyset_stats(mut self, stats: Arc<KeysetStats>) -> Self {
+        self.keyset_stats = stats;
+        self
+    }
+
+    /// Sign and verify with `fallback` while every device in the pool is unreachable
+    pub fn with_fallback(mut self, fallback: Arc<SoftwareFallback>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Have `approver` sign off batches above its threshold before they are signed
+    pub fn with_approver(mut self, approver: Arc<Approver>) -> Self {
+        self.approver = Some(approver);
+        self
+    }
+
+    /// Hold each client certificate to its quota in `quotas`
+    pub fn with_client_quotas(mut self, quotas: Arc<ClientQuotas>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Serve the units of `routes` from their backends, `pool` keeps every other unit
+    pub fn with_routes(mut self, routes: Routes) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// Device pool serving `unit`, `None` if it is signed in software
+    fn pool_for_unit(&self, unit: &CurrencyUnit) -> Option<&Arc<TrezorPool>> {
+        match self.routes.backend(unit) {
+            None => Some(&self.pool),
+            Some(Backend::Pool(pool)) => Some(pool),
+            Some(Backend::Software(_)) => None,
+        }
+    }
+
+    /// The default pool followed by the pools of the routes
+    pub fn pools(&self) -> impl Iterator<Item = &Arc<TrezorPool>> {
+        std::iter::once(&self.pool).chain(self.routes.pools().map(|(pool, _)| pool))
+    }
+
+    /// Every device of every pool
+    pub fn devices(&self) -> impl Iterator<Item = &Arc<TrezorDevice>> {
+        self.pools().flat_map(|pool| pool.devices())
+    }
+
+    /// Health of the worst pool as of the last call or probe of its devices, for status
+    /// reports that must not compete with signing for the devices
+    pub fn cached_health(&self) -> DeviceHealth {
+        DeviceHealth::worst(self.pools().map(|pool| pool.cached_health()))
+    }
+
+    /// Health of the worst pool, as the units it serves cannot be signed without it
+    pub async fn health(&self) -> DeviceHealth {
+        let mut health = DeviceHealth::Ready;
+        for pool in self.pools() {
+            match pool.health().await {
+                DeviceHealth::Disconnected => return DeviceHealth::Disconnected,
+                DeviceHealth::Locked => health = DeviceHealth::Locked,
+                DeviceHealth::Ready => {}
+            }
+        }
+        health
+    }
+
+    /// Persist the keyset cache to `path`, seeding the cache from it if it already exists.
+    ///
+    /// Returns whether the cache was seeded, in which case `keysets()` can be served before
+    /// the device has answered.
+    pub async fn with_keyset_cache_file(mut self, path: PathBuf) -> Result<(Self, bool), Error> {
+        let stored = keyset_cache::load(&path)?;
+        let seeded = stored.is_some();
+        if let Some(keysets) = stored {
+            tracing::info!(
+                "Loaded {} keysets from {}",
+                keysets.keysets.len(),
+                path.display()
+            );
+            self.replace_cached_keysets(&mut *self.cached_keysets.write().await, keysets);
+        }
+        self.keyset_cache_file = Some(path);
+        Ok((self, seeded))
+    }
+
+    /// Refuse blind_sign and verify_proofs until [`Self::resume`], keysets are still served
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            tracing::warn!("Signing paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            tracing::info!("Signing resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Lock every device and forget its session, so PIN and passphrase are needed again.
+    ///
+    /// Signing is refused and the keysets are not re-read from the devices until
+    /// [`Self::unlock_devices`], so nothing prompts for the PIN in the meantime.
+    pub async fn lock_devices(&self) -> Result<(), Error> {
+        self.locked.store(true, Ordering::SeqCst);
+        for device in self.devices() {
+            device.lock().await?;
+        }
+        tracing::warn!("Devices locked, signing is refused until they are unlocked");
+        Ok(())
+    }
+
+    /// Read the keysets from every device, which asks for PIN and passphrase, then allow
+    /// signing again. The devices stay locked if that fails.
+    pub async fn unlock_devices(&self) -> Result<(), Error> {
+        self.check_pool_consistency().await?;
+        self.refresh_keysets().await?;
+        if self.locked.swap(false, Ordering::SeqCst) {
+            tracing::info!("Devices unlocked");
+        }
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::SeqCst)
+    }
+
+    /// Refuse blind_sign and verify_proofs while the peer of a standby pair serves
+    pub fn set_standby(&self, standby: bool) {
+        self.standby.store(standby, Ordering::SeqCst);
+    }
+
+    pub fn is_standby(&self) -> bool {
+        self.standby.load(Ordering::SeqCst)
+    }
+
+    fn check_not_watch_only(&self, call: &str) -> Result<(), Error> {
+        if self.options.watch_only {
+            return Err(TrezorSignatoryError::Unsupported(format!(
+                "{} is disabled in watch-only mode",
+                call
+            ))
+            .into());
+        }
         Ok(())
     }
 
-    pub fn get_cached_keysets_proto(&self) -> Result<Vec<protos::KeySet>, Error> {
-        if let Some(keysets) = &self.cached_keysets {
-            return keysets
+    fn check_not_dry_run(&self, call: &str) -> Result<(), Error> {
+        if self.options.dry_run {
+            return Err(TrezorSignatoryError::Unsupported(format!(
+                "{} is not performed in dry-run mode",
+                call
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn check_not_paused(&self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Err(TrezorSignatoryError::Unavailable(
+                "signing is paused for maintenance".to_string(),
+            )
+            .into());
+        }
+        if self.is_locked() {
+            return Err(TrezorSignatoryError::Unavailable(
+                "the devices are locked by the operator".to_string(),
+            )
+            .into());
+        }
+        if self.is_standby() {
+            return Err(TrezorSignatoryError::Unavailable(
+                "this signatory is on standby, its peer is serving".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Check that every device in each pool serves the same key tree
+    pub async fn check_pool_consistency(&self) -> Result<(), Error> {
+        for pool in self.pools() {
+            let expected = self.fetch_keysets_from(pool.primary()).await?;
+            for device in pool.devices().iter().skip(1) {
+                let keysets = self.fetch_keysets_from(device).await?;
+                if keysets.pubkey != expected.pubkey
+                    || keyset_summary(&keysets) != keyset_summary(&expected)
+                {
+                    return Err(Error::Custom(
+                        "Devices in the pool are not initialized from the same seed".to_string(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn update_cached_keysets(&self) -> Result<(), Error> {
+        self.refresh_keysets().await.map(|_| ())
+    }
+
+    /// Re-read keysets from the device and replace the cache.
+    ///
+    /// Returns `true` if the set of keysets or their active flags changed. A device serving a
+    /// different key tree than the cached one, e.g. wiped and restored from another seed,
+    /// leaves the cache untouched and pauses signing until an operator resumes it.
+    pub async fn refresh_keysets(&self) -> Result<bool, Error> {
+        let keysets = self.fetch_keysets().await?;
+        let mut cached = self.cached_keysets.write().await;
+        if let Some(old) = cached.as_ref() {
+            if old.pubkey != keysets.pubkey {
+                tracing::error!(
+                    "Device serves key tree {} instead of the cached {}, pausing signing",
+                    keysets.pubkey,
+                    old.pubkey
+                );
+                self.pause();
+                return Err(TrezorSignatoryError::KeyTreeChanged(keysets.pubkey.to_hex()).into());
+            }
+        }
+        let changed = cached
+            .as_ref()
+            .is_none_or(|old| keyset_summary(old) != keyset_summary(&keysets));
+        if changed && cached.is_some() {
+            tracing::info!("Device keysets changed: {:?}", keyset_summary(&keysets));
+        }
+        if changed {
+            if let Some(path) = &self.keyset_cache_file {
+                if let Err(err) = keyset_cache::store(path, &keysets).await {
+                    tracing::warn!("Failed to persist keyset cache: {}", err);
+                }
+            }
+        }
+        warn_expiring(&keysets, self.options.expiry_warning_secs);
+        self.replace_cached_keysets(&mut cached, keysets);
+        Ok(changed)
+    }
+
+    /// Replace the cache behind its write lock `cached`, dropping the protobuf form of the old
+    /// keysets while no reader can convert them again
+    fn replace_cached_keysets(
+        &self,
+        cached: &mut Option<SignatoryKeysets>,
+        keysets: SignatoryKeysets,
+    ) {
+        *cached = Some(keysets);
+        self.keysets_proto
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+
+    /// Re-check the pool and refresh keysets whenever a device had to be reconnected.
+    ///
+    /// A device coming back may have rebooted into new firmware or been wiped and restored,
+    /// so the cached keysets cannot be trusted until they are read again.
+    pub fn spawn_refresh_on_reconnect(&self) -> Vec<JoinHandle<()>> {
+        self.devices()
+            .map(|device| {
+                let signatory = self.clone();
+                let mut reconnects = device.subscribe_reconnects();
+                tokio::spawn(async move {
+                    while reconnects.changed().await.is_ok() {
+                        // re-checked on unlock, reading now would prompt for the PIN
+                        if signatory.is_locked() {
+                            continue;
+                        }
+                        if let Err(err) = signatory.check_pool_consistency().await {
+                            // a device of the pool may have been wiped or restored from another
+                            // seed, so none of them is trusted until an operator resumes
+                            tracing::error!(
+                                "Pool inconsistent after reconnect, pausing signing: {}",
+                                err
+                            );
+                            signatory.pause();
+                            continue;
+                        }
+                        if let Err(err) = signatory.refresh_keysets().await {
+                            tracing::error!("Failed to re-check keysets after reconnect: {}", err);
+                        }
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Periodically refresh the keyset cache in the background
+    pub fn spawn_keyset_refresh(&self, interval: Duration) -> JoinHandle<()> {
+        let signatory = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick completes immediately and the cache is already warm
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if signatory.is_locked() {
+                    continue;
+                }
+                if let Err(err) = signatory.refresh_keysets().await {
+                    tracing::warn!("Failed to refresh keysets: {}", err);
+                }
+            }
+        })
+    }
+
+    /// The keyset cache, filled from the devices first if nothing was cached yet.
+    ///
+    /// Keysets only change on rotation or refresh, so warm reads never wait for a device, and
+    /// concurrent cold reads fetch once.
+    async fn filled_keyset_cache(&self) -> Result<RwLockReadGuard<'_, SignatoryKeysets>, Error> {
+        // the cache is never emptied once filled, so this loops at most twice
+        loop {
+            if let Ok(cached) =
+                RwLockReadGuard::try_map(self.cached_keysets.read().await, |cached| cached.as_ref())
+            {
+                return Ok(cached);
+            }
+            let _fetching = self.cold_fetch.lock().await;
+            if self.cached_keysets.read().await.is_none() {
+                self.refresh_keysets().await?;
+            }
+        }
+    }
+
+    /// Cached keysets of the units `pool` serves, as sent along with its requests.
+    ///
+    /// They are converted once per pool and cache refresh, since a keyset of 64 denominations
+    /// is costly to convert on every request.
+    pub async fn get_cached_keysets_proto(
+        &self,
+        pool: &Arc<TrezorPool>,
+    ) -> Result<Arc<Vec<protos::KeySet>>, Error> {
+        // the read lock is held until the conversion is stored, so it cannot outlive a refresh
+        let keysets = self.filled_keyset_cache().await?;
+        let key = Arc::as_ptr(pool) as usize;
+        if let Some(proto) = self
+            .keysets_proto
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return Ok(proto.clone());
+        }
+        let proto = Arc::new(
+            keysets
                 .keysets
                 .iter()
-                .map(|ks| {
-                    let mut ks2 = ks.clone();
-                    //ks2.keys = Keys::new(BTreeMap::new());
-                    ks2.try_into_cdk()
+                .filter(|ks| {
+                    self.pool_for_unit(&ks.unit)
+                        .is_some_and(|p| Arc::ptr_eq(p, pool))
                 })
-                .collect::<Result<Vec<_>, Error>>();
-        } else {
-            return Err(Error::Custom("Keysets must be cached".to_string()));
+                .map(|ks| ks.clone().try_into_cdk())
+                .collect::<Result<Vec<_>, Error>>()?,
+        );
+        self.keysets_proto
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, proto.clone());
+        Ok(proto)
+    }
+
+    /// Fetch keysets from the devices, bypassing the cache.
+    ///
+    /// Each routed pool contributes the keysets of its units and the default pool the rest.
+    /// Keysets signed in software are not included.
+    pub async fn fetch_keysets(&self) -> Result<SignatoryKeysets, Error> {
+        let mut keysets = self.fetch_keysets_from(self.pool.primary()).await?;
+        keysets
+            .keysets
+            .retain(|ks| self.routes.backend(&ks.unit).is_none());
+        for (pool, units) in self.routes.pools() {
+            let routed = self.fetch_keysets_from(pool.primary()).await?;
+            keysets.keysets.extend(
+                routed
+                    .keysets
+                    .into_iter()
+                    .filter(|ks| units.contains(&ks.unit)),
+            );
         }
+        Ok(keysets)
     }
-}
 
-#[async_trait::async_trait]
-impl Signatory for TrezorSignatory {
-    fn name(&self) -> String {
-        format!("Trezor Signatory {}", env!("CARGO_PKG_VERSION"))
+    pub async fn fetch_keysets_from(
+        &self,
+        device: &TrezorDevice,
+    ) -> Result<SignatoryKeysets, Error> {
+        let mut req = protos::CashuGetKeysets::new();
+        if let Some(account) = self.options.account {
+            req.set_account(account);
+        }
+
+        // every pool of the signatory shares its interaction
+        let result: protos::CashuGetKeysetsResponse = self.pool.scope(device.call(req)).await?;
+
+        let keysets = result
+            .keysets
+            .into_option()
+            .ok_or(TrezorSignatoryError::Mapping(
+                "missing keysets in response".to_string(),
+            ))?;
+        keysets.try_into_cdk()
     }
 
-    async fn blind_sign(
+    /// Sign `blinded_messages` for a mint operation.
+    ///
+    /// The firmware uses the operation for its confirmation screens and per-operation policy.
+    /// The CDK [`Signatory`] trait does not carry it, so calls through the trait take it from
+    /// the metadata of the call, see [`crate::operation`].
+    #[tracing::instrument(skip_all, fields(
+        ?operation,
+        batch_size = blinded_messages.len(),
+        amount = tracing::field::Empty,
+        correlation_id = tracing::field::Empty,
+    ))]
+    async fn blind_sign_with_operation(
         &self,
         blinded_messages: Vec<BlindedMessage>,
+        operation: protos::Operation,
     ) -> Result<Vec<BlindSignature>, Error> {
-        let mut req = protos::CashuBlindSign::new();
-        req.blinded_messages = blinded_messages
-            .into_iter()
-            .map(|bm| bm.try_into_cdk())
-            .collect::<Result<Vec<_>, Error>>()?;
-        req.set_operation(protos::Operation::OPERATION_UNSPECIFIED);
-        if CACHE_ENABLED {
-            req.keysets = self.get_cached_keysets_proto()?;
+        let correlation_id = Uuid::new_v4().to_string();
+        let span = tracing::Span::current();
+        span.record("correlation_id", correlation_id.as_str());
+        span.record(
+            "amount",
+            total_amount(blinded_messages.iter().map(|bm| bm.amount)),
+        );
+        let result = self.blind_sign_batch(&blinded_messages, operation).await;
+        self.metrics
+            .record_blind_sign(blinded_messages.len(), &result);
+        if let Some(audit) = &self.audit {
+            audit
+                .record(
+                    "blind_sign",
+                    &correlation_id,
+                    blinded_messages.iter().map(|bm| (bm.keyset_id, bm.amount)),
+                    &result,
+                )
+                .await?;
+        }
+        result
+    }
+
+    /// Verify `proofs` for a known mint operation, see [`Self::blind_sign_with_operation`]
+    #[tracing::instrument(skip_all, fields(
+        ?operation,
+        batch_size = proofs.len(),
+        amount = tracing::field::Empty,
+        correlation_id = tracing::field::Empty,
+    ))]
+    async fn verify_proofs_with_operation(
+        &self,
+        proofs: Vec<Proof>,
+        operation: protos::Operation,
+    ) -> Result<(), Error> {
+        let correlation_id = Uuid::new_v4().to_string();
+        let span = tracing::Span::current();
+        span.record("correlation_id", correlation_id.as_str());
+        span.record("amount", total_amount(proofs.iter().map(|p| p.amount)));
+        let result = crate::lanes::verify_scope(
+            proofs.len(),
+            self.verify_proofs_batch(&proofs, operation, &correlation_id),
+        )
+        .await;
+        self.metrics.record_verify_proofs(proofs.len(), &result);
+        if let Some(audit) = &self.audit {
+            audit
+                .record(
+                    "verify_proofs",
+                    &correlation_id,
+                    proofs.iter().map(|p| (p.keyset_id, p.amount)),
+                    &result,
+                )
+                .await?;
+        }
+        result
+    }
+
+    async fn blind_sign_batch(
+        &self,
+        blinded_messages: &[BlindedMessage],
+        operation: protos::Operation,
+    ) -> Result<Vec<BlindSignature>, Error> {
+        self.check_not_watch_only("blind_sign")?;
+        self.check_not_paused()?;
+        self.policy.check_window(unix_now())?;
+        self.policy.check_keysets(blinded_messages)?;
+        if let Some(quotas) = &self.quotas {
+            quotas.check_rate()?;
+        }
+        let signing_keysets = self.keysets().await?;
+        check_amounts(
+            &signing_keysets,
+            "blinded message",
+            blinded_messages.iter().map(|bm| (bm.keyset_id, bm.amount)),
+        )?;
+        check_not_expired(&signing_keysets, blinded_messages)?;
+        self.policy.check_units(
+            blinded_messages.iter().map(|bm| bm.keyset_id),
+            &signing_keysets,
+        )?;
+        // a retry is only answered while the batch would still be signed, so an expired
+        // keyset or a unit taken off the allowlist is refused like for a new batch
+        if let Some(signatures) = self
+            .responses
+            .as_ref()
+            .and_then(|responses| responses.get(blinded_messages))
+        {
+            tracing::info!("Answering retried blind_sign from the response cache");
+            return Ok(signatures);
+        }
+
+        // a repeated message gets the same signature, so only the unique messages are issued
+        // and counted against confirmation thresholds, limits and statistics
+        let (unique, positions) = dedup_blinded_messages(blinded_messages);
+        if unique.len() < blinded_messages.len() {
+            tracing::warn!(
+                "blind_sign batch repeats {} blinded messages, signing each once",
+                blinded_messages.len() - unique.len()
+            );
+        }
+        // decided for the whole batch, every chunk of a large batch is confirmed separately
+        let mut confirm = self
+            .policy
+            .requires_confirmation(&unique, &signing_keysets)?;
+        // a dry run must not ask anyone to approve a batch that is never signed
+        if let Some(approver) = self.approver.as_ref().filter(|_| !self.options.dry_run) {
+            confirm |= approver.approve(&unique, &signing_keysets).await?;
+        }
+        let value_sats = batch_value_sats(&unique, &signing_keysets)?;
+        let totals = batch_totals(&unique, &signing_keysets)?;
+        let reservation = self.limiter.reserve(value_sats, totals).await?;
+        let client_reservation = match &self.quotas {
+            Some(quotas) => match quotas.reserve(value_sats).await {
+                Ok(client_reservation) => client_reservation,
+                Err(err) => {
+                    self.limiter.release(&reservation).await?;
+                    return Err(err);
+                }
+            },
+            None => None,
+        };
+        if self.options.dry_run {
+            self.release_volume(&reservation, client_reservation.as_ref())
+                .await?;
+            log_dry_run(&unique, confirm);
+            self.check_not_dry_run("blind_sign")?;
         }
 
-        let mut trezor = self.trezor.lock().await;
         let duration = Instant::now();
-        let result = handle_trezor_call(
-            trezor.call(req, Box::new(|_, m: protos::CashuBlindSignResponse| Ok(m))),
+        let mut signed: Vec<Option<BlindSignature>> = vec![None; unique.len()];
+        let routed = self.routes.split(
+            unique.iter().map(|bm| bm.keyset_id),
+            &signing_keysets,
+            &self.pool,
         )?;
+        for (backend, indexes) in routed {
+            let messages: Vec<BlindedMessage> =
+                indexes.iter().map(|i| unique[*i].clone()).collect();
+            let result = match &backend {
+                Backend::Pool(pool) => {
+                    self.blind_sign_on(pool, &messages, &signing_keysets, operation, confirm)
+                        .await
+                }
+                // no device to show the batch on, so it could only be signed unconfirmed
+                Backend::Software(_) if confirm => Err(TrezorSignatoryError::Policy(
+                    "the batch needs a confirmation on the device, but its unit is signed in \
+                     software"
+                        .to_string(),
+                )
+                .into()),
+                Backend::Software(software) => software.blind_sign(&messages),
+            };
+            match result {
+                Ok(signatures) => {
+                    for (index, signature) in indexes.into_iter().zip(signatures) {
+                        signed[index] = Some(signature);
+                    }
+                }
+                // nothing from this batch reaches the mint, so it does not count towards the limit
+                Err(err) => {
+                    self.release_volume(&reservation, client_reservation.as_ref())
+                        .await?;
+                    return Err(err);
+                }
+            }
+        }
+        let signatures: Vec<BlindSignature> = positions
+            .iter()
+            .map(|i| signed[*i].clone().expect("every message is routed"))
+            .collect();
         let elapsed = duration.elapsed();
-        println!("Trezor blind_sign took {} ms", elapsed.as_millis(),);
-        result.try_into_cdk()
+        tracing::info!(
+            duration_ms = elapsed.as_millis() as u64,
+            "Trezor blind_sign took {} ms",
+            elapsed.as_millis()
+        );
+        // retries answered from the response cache above are not counted again
+        self.keyset_stats.record(&unique);
+        if let Some(responses) = &self.responses {
+            responses.insert(blinded_messages, &signatures);
+        }
+        Ok(signatures)
     }
 
-    async fn verify_proofs(&self, proofs: Vec<Proof>) -> Result<(), Error> {
+    /// Give back the volume reserved for a batch that is not signed
+    async fn release_volume(
+        &self,
+        reservation: &SignedVolume,
+        client_reservation: Option<&ClientReservation>,
+    ) -> Result<(), Error> {
+        self.limiter.release(reservation).await?;
+        match client_reservation {
+            Some(client_reservation) => client_reservation.release().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn verify_proofs_batch(
+        &self,
+        proofs: &[Proof],
+        operation: protos::Operation,
+        correlation_id: &str,
+    ) -> Result<(), Error> {
+        self.check_not_paused()?;
+        if let Some(quotas) = &self.quotas {
+            quotas.check_rate()?;
+        }
+        let signing_keysets = self.keysets().await?;
+        self.policy
+            .check_units(proofs.iter().map(|p| p.keyset_id), &signing_keysets)?;
+        check_amounts(
+            &signing_keysets,
+            "proof",
+            proofs.iter().map(|p| (p.keyset_id, p.amount)),
+        )?;
+        if self.options.preverify_proofs {
+            preverify_proofs(&signing_keysets, proofs)?;
+        }
+        if self.options.verify_spending_conditions {
+            verify_spending_conditions(proofs)?;
+        }
+        let host_only = self.options.dry_run;
+        let proofs = if self.options.host_verify_proofs || self.options.watch_only || host_only {
+            let remaining = host_verify_proofs(&signing_keysets, proofs)?;
+            if remaining.is_empty() {
+                return Ok(());
+            }
+            if host_only {
+                return Err(TrezorSignatoryError::Unsupported(format!(
+                    "{} proofs without a DLEQ proof cannot be verified in dry-run mode",
+                    remaining.len()
+                ))
+                .into());
+            }
+            remaining
+        } else {
+            proofs.to_vec()
+        };
+
+        // backends are independent, so proofs of several units are verified at once
+        let duration = Instant::now();
+        let routed = self.routes.split(
+            proofs.iter().map(|p| p.keyset_id),
+            &signing_keysets,
+            &self.pool,
+        )?;
+        futures::future::try_join_all(routed.into_iter().map(|(backend, indexes)| {
+            let group: Vec<Proof> = indexes.iter().map(|i| proofs[*i].clone()).collect();
+            async move {
+                match &backend {
+                    Backend::Pool(pool) => {
+                        self.verify_proofs_on(pool, &group, operation, correlation_id)
+                            .await
+                    }
+                    Backend::Software(software) => software.verify_proofs(&group),
+                }
+            }
+        }))
+        .await?;
+        let elapsed = duration.elapsed();
+        tracing::info!(
+            duration_ms = elapsed.as_millis() as u64,
+            "Trezor verify_proofs took {} ms",
+            elapsed.as_millis()
+        );
+        Ok(())
+    }
+
+    /// Verify `proofs` on `pool` in device-sized chunks
+    async fn verify_proofs_on(
+        &self,
+        pool: &Arc<TrezorPool>,
+        proofs: &[Proof],
+        operation: protos::Operation,
+        correlation_id: &str,
+    ) -> Result<(), Error> {
+        let keysets = if CACHE_ENABLED {
+            self.get_cached_keysets_proto(pool).await?
+        } else {
+            Arc::default()
+        };
+
+        // chunks are independent, so a pool can verify them on several devices at once
+        futures::future::try_join_all(proofs.chunks(self.options.max_batch_size).map(|chunk| {
+            self.verify_proofs_chunk(pool, chunk, &keysets, operation, correlation_id)
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Verify one device-sized batch of proofs
+    async fn verify_proofs_chunk(
+        &self,
+        pool: &Arc<TrezorPool>,
+        chunk: &[Proof],
+        keysets: &[protos::KeySet],
+        operation: protos::Operation,
+        correlation_id: &str,
+    ) -> Result<(), Error> {
         let mut req = protos::CashuVerifyProofs::new();
         let mut proofs_msg = protos::Proofs::new();
-        proofs_msg.proof = proofs
-            .into_iter()
+        proofs_msg.proof = chunk
+            .iter()
+            .cloned()
             .map(|p| p.try_into_cdk())
             .collect::<Result<Vec<_>, Error>>()?;
-        proofs_msg.set_operation(protos::Operation::OPERATION_UNSPECIFIED);
-        proofs_msg.set_correlation_id("verify".to_string());
+        proofs_msg.set_operation(operation);
+        proofs_msg.set_correlation_id(correlation_id.to_string());
         req.proofs = ::protobuf::MessageField::some(proofs_msg);
         if CACHE_ENABLED {
-            req.keysets = self.get_cached_keysets_proto()?;
+            req.keysets = keysets_for(keysets, chunk.iter().map(|p| (p.keyset_id, p.amount)));
+        }
+        if let Some(account) = self.options.account {
+            req.set_account(account);
         }
 
-        let mut trezor = self.trezor.lock().await;
-        let duration = Instant::now();
-        handle_trezor_call(trezor.call(req, Box::new(|_, m: protos::Success| Ok(m))))?;
-        let elapsed = duration.elapsed();
-        println!("Trezor verify_proofs took {} ms", elapsed.as_millis(),);
+        if let Err(err) = pool.call::<_, protos::Success>(req).await {
+            let fallback = self.fallback_for(pool, err).await?;
+            return fallback.verify_proofs(chunk);
+        }
         Ok(())
     }
 
-    async fn keysets(&self) -> Result<SignatoryKeysets, Error> {
-        let req = protos::CashuGetKeysets::new();
+    /// Device keysets with those signed in software, keysets past their expiry inactive
+    fn served_keysets(&self, mut keysets: SignatoryKeysets) -> SignatoryKeysets {
+        for software in self.routes.software() {
+            keysets.keysets.extend(software.keysets());
+        }
+        mark_expired_inactive(keysets)
+    }
+
+    /// The software fallback if one is configured and no device of the default pool is
+    /// reachable, otherwise `err`. The fallback seed only derives the default pool's keys.
+    async fn fallback_for(
+        &self,
+        pool: &Arc<TrezorPool>,
+        err: Error,
+    ) -> Result<&SoftwareFallback, Error> {
+        let Some(fallback) = &self.fallback else {
+            return Err(err);
+        };
+        if !Arc::ptr_eq(pool, &self.pool) || pool.health().await != DeviceHealth::Disconnected {
+            return Err(err);
+        }
+        tracing::warn!(
+            "No Trezor reachable ({}), using the SOFTWARE FALLBACK signer",
+            err
+        );
+        Ok(fallback)
+    }
+
+    /// Sign `messages` on `pool` in device-sized chunks
+    async fn blind_sign_on(
+        &self,
+        pool: &Arc<TrezorPool>,
+        messages: &[BlindedMessage],
+        signing_keysets: &SignatoryKeysets,
+        operation: protos::Operation,
+        confirm: bool,
+    ) -> Result<Vec<BlindSignature>, Error> {
+        let keysets = if CACHE_ENABLED {
+            self.get_cached_keysets_proto(pool).await?
+        } else {
+            Arc::default()
+        };
 
-        // keysets will be the same for the lifetime of the device connection, so we can cache them
-        if let Some(cached) = &self.cached_keysets {
-            return Ok(cached.clone());
+        let mut signatures = Vec::with_capacity(messages.len());
+        for chunk in messages.chunks(self.options.max_batch_size) {
+            let chunk_signatures = self
+                .blind_sign_chunk(pool, chunk, &keysets, operation, confirm)
+                .await?;
+            if self.options.verify_dleq {
+                verify_signatures_dleq(signing_keysets, chunk, &chunk_signatures)?;
+            }
+            signatures.extend(chunk_signatures);
         }
+        Ok(signatures)
+    }
 
-        let mut trezor = self.trezor.lock().await;
-        let result = handle_trezor_call(
-            trezor.call(req, Box::new(|_, m: protos::CashuGetKeysetsResponse| Ok(m))),
-        )?;
+    /// Sign one device-sized batch of blinded messages
+    async fn blind_sign_chunk(
+        &self,
+        pool: &Arc<TrezorPool>,
+        chunk: &[BlindedMessage],
+        keysets: &[protos::KeySet],
+        operation: protos::Operation,
+        confirm: bool,
+    ) -> Result<Vec<BlindSignature>, Error> {
+        let mut req = protos::CashuBlindSign::new();
+        req.blinded_messages = chunk
+            .iter()
+            .cloned()
+            .map(|bm| bm.try_into_cdk())
+            .collect::<Result<Vec<_>, Error>>()?;
+        req.set_operation(operation);
+        req.set_require_confirmation(confirm);
+        if let Some(account) = self.options.account {
+            req.set_account(account);
+        }
+        if CACHE_ENABLED {
+            req.keysets = keysets_for(keysets, chunk.iter().map(|bm| (bm.keyset_id, bm.amount)));
+        }
 
-        let keysets = result
+        let result: protos::CashuBlindSignResponse = match pool.call(req).await {
+            Ok(result) => result,
+            // the software signer cannot ask for confirmation, so it never signs those batches
+            Err(err) if confirm => return Err(err),
+            Err(err) => return self.fallback_for(pool, err).await?.blind_sign(chunk),
+        };
+        let signatures: Vec<BlindSignature> = result.try_into_cdk()?;
+        check_signatures_match(chunk, &signatures)?;
+        Ok(signatures)
+    }
+}
+
+/// Check that the device signed exactly the blinded messages it was sent, in order
+fn check_signatures_match(
+    chunk: &[BlindedMessage],
+    signatures: &[BlindSignature],
+) -> Result<(), Error> {
+    if signatures.len() != chunk.len() {
+        return Err(TrezorSignatoryError::ResponseMismatch(format!(
+            "{} signatures for {} blinded messages",
+            signatures.len(),
+            chunk.len()
+        ))
+        .into());
+    }
+    for (index, (message, signature)) in chunk.iter().zip(signatures).enumerate() {
+        let mismatch = if signature.keyset_id != message.keyset_id {
+            Some(format!(
+                "keyset {} instead of {}",
+                signature.keyset_id, message.keyset_id
+            ))
+        } else if signature.amount != message.amount {
+            Some(format!(
+                "amount {} instead of {}",
+                signature.amount, message.amount
+            ))
+        } else if signature.c == message.blinded_secret {
+            Some("the blinded message itself".to_string())
+        } else {
+            None
+        };
+        if let Some(mismatch) = mismatch {
+            return Err(TrezorSignatoryError::ResponseMismatch(format!(
+                "signature {} has {}",
+                index, mismatch
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Sum of `amounts` for span fields, saturating instead of failing on overflow
+fn total_amount(amounts: impl Iterator<Item = Amount>) -> u64 {
+    amounts.map(u64::from).fold(0, u64::saturating_add)
+}
+
+/// Keyset ids with their active flag, used to detect changes on refresh
+fn keyset_summary(keysets: &SignatoryKeysets) -> Vec<(Id, bool)> {
+    keysets
+        .keysets
+        .iter()
+        .map(|ks| (ks.id, ks.active))
+        .collect()
+}
+
+/// Check that each signature carries a valid DLEQ proof for the key it claims to be signed with
+fn verify_signatures_dleq(
+    keysets: &SignatoryKeysets,
+    messages: &[BlindedMessage],
+    signatures: &[BlindSignature],
+) -> Result<(), Error> {
+    for (message, signature) in messages.iter().zip(signatures) {
+        let keyset = keysets
             .keysets
-            .into_option()
-            .ok_or(Error::Custom("missing keysets in response".to_string()))?;
-        keysets.try_into_cdk()
+            .iter()
+            .find(|ks| ks.id == signature.keyset_id)
+            .ok_or(Error::UnknownKeySet)?;
+        let mint_pubkey = keyset
+            .keys
+            .amount_key(signature.amount)
+            .ok_or(Error::AmountKey)?;
+        signature
+            .verify_dleq(mint_pubkey, message.blinded_secret)
+            .map_err(|e| TrezorSignatoryError::Mapping(format!("invalid DLEQ proof: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Reject blinded messages or proofs (`what`) for unknown keysets or amounts the keyset has no
+/// key for, such as zero or amounts that are not a supported denomination
+fn check_amounts(
+    keysets: &SignatoryKeysets,
+    what: &str,
+    items: impl IntoIterator<Item = (Id, Amount)>,
+) -> Result<(), Error> {
+    for (index, (keyset_id, amount)) in items.into_iter().enumerate() {
+        let keyset = keysets
+            .keysets
+            .iter()
+            .find(|ks| ks.id == keyset_id)
+            .ok_or_else(|| {
+                TrezorSignatoryError::InvalidRequest(format!(
+                    "{} {} uses unknown keyset {}",
+                    what, index, keyset_id
+                ))
+            })?;
+        if !keyset.amounts.contains(&u64::from(amount)) {
+            return Err(TrezorSignatoryError::InvalidRequest(format!(
+                "{} {} has amount {}, keyset {} supports {:?}",
+                what, index, amount, keyset_id, keyset.amounts
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// The keysets a device request uses, each with only the keys of the amounts it uses.
+///
+/// Sending the full keysets with every request would make it grow with the number of keysets
+/// and denominations rather than with the batch.
+fn keysets_for(
+    keysets: &[protos::KeySet],
+    used: impl IntoIterator<Item = (Id, Amount)>,
+) -> Vec<protos::KeySet> {
+    let mut amounts: BTreeMap<Vec<u8>, BTreeSet<u64>> = BTreeMap::new();
+    for (keyset_id, amount) in used {
+        amounts
+            .entry(keyset_id.to_bytes())
+            .or_default()
+            .insert(amount.into());
+    }
+    keysets
+        .iter()
+        .filter_map(|keyset| {
+            let used = amounts.get(keyset.id())?;
+            let mut keyset = keyset.clone();
+            if let Some(keys) = keyset.keys.as_mut() {
+                keys.keys.retain(|amount, _| used.contains(amount));
+            }
+            Some(keyset)
+        })
+        .collect()
+}
+
+/// Refuse to sign for keysets past their `final_expiry`, whatever the device would do
+fn check_not_expired(
+    keysets: &SignatoryKeysets,
+    blinded_messages: &[BlindedMessage],
+) -> Result<(), Error> {
+    let now = unix_now();
+    for keyset in &keysets.keysets {
+        let Some(expiry) = keyset.final_expiry.filter(|expiry| *expiry <= now) else {
+            continue;
+        };
+        if blinded_messages.iter().any(|bm| bm.keyset_id == keyset.id) {
+            return Err(TrezorSignatoryError::Policy(format!(
+                "keyset {} expired at {}",
+                keyset.id, expiry
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Serve keysets past their `final_expiry` as inactive, so mints stop issuing from them
+fn mark_expired_inactive(mut keysets: SignatoryKeysets) -> SignatoryKeysets {
+    let now = unix_now();
+    for keyset in &mut keysets.keysets {
+        if keyset.final_expiry.is_some_and(|expiry| expiry <= now) {
+            keyset.active = false;
+        }
+    }
+    keysets
+}
+
+/// Seconds until each keyset with a `final_expiry` expires, 0 once it has
+pub fn expires_in(keysets: &SignatoryKeysets) -> Vec<(Id, u64)> {
+    let now = unix_now();
+    keysets
+        .keysets
+        .iter()
+        .filter_map(|ks| Some((ks.id, ks.final_expiry?.saturating_sub(now))))
+        .collect()
+}
+
+/// Derivation index of each keyset. The device lists the keysets of a unit in the order it
+/// derived them, from index 0.
+pub fn derivation_indexes(keysets: &SignatoryKeysets) -> HashMap<Id, u32> {
+    let mut next: HashMap<String, u32> = HashMap::new();
+    keysets
+        .keysets
+        .iter()
+        .map(|ks| {
+            let index = next.entry(ks.unit.to_string()).or_default();
+            *index += 1;
+            (ks.id, *index - 1)
+        })
+        .collect()
+}
+
+/// Derivation path of keyset `index` of `unit` in the key tree of `account`. The account takes
+/// the place of the first, hardened level of the CDK path, which is account 0, the firmware
+/// default.
+pub fn keyset_derivation_path(
+    unit: CurrencyUnit,
+    index: u32,
+    account: Option<u32>,
+) -> Option<DerivationPath> {
+    let path = derivation_path_from_unit(unit, index)?;
+    let Some(account) = account else {
+        return Some(path);
+    };
+    let mut children = path.as_ref().to_vec();
+    children[0] = ChildNumber::from_hardened_idx(account).ok()?;
+    Some(DerivationPath::from(children))
+}
+
+fn warn_expiring(keysets: &SignatoryKeysets, warning_secs: u64) {
+    for (id, remaining) in expires_in(keysets) {
+        if remaining == 0 {
+            tracing::warn!(keyset_id = %id, "Keyset has expired and is served as inactive");
+        } else if remaining < warning_secs {
+            tracing::warn!(
+                keyset_id = %id,
+                "Keyset expires in {} hours, rotate to a new keyset",
+                remaining / HOUR_SECS
+            );
+        }
+    }
+}
+
+/// Drop repeated blinded messages from a batch.
+///
+/// Returns the distinct messages in order of first appearance, and for every message of the
+/// batch the position of its signature among them.
+fn dedup_blinded_messages(
+    blinded_messages: &[BlindedMessage],
+) -> (Vec<BlindedMessage>, Vec<usize>) {
+    let mut unique = Vec::new();
+    let mut seen = HashMap::new();
+    let positions = blinded_messages
+        .iter()
+        .map(|bm| {
+            *seen
+                .entry((bm.keyset_id, bm.amount, bm.blinded_secret))
+                .or_insert_with(|| {
+                    unique.push(bm.clone());
+                    unique.len() - 1
+                })
+        })
+        .collect();
+    (unique, positions)
+}
+
+/// Log what a dry run would have sent to the device
+fn log_dry_run(blinded_messages: &[BlindedMessage], confirm: bool) {
+    let mut per_keyset: BTreeMap<Id, Vec<u64>> = BTreeMap::new();
+    for bm in blinded_messages {
+        per_keyset
+            .entry(bm.keyset_id)
+            .or_default()
+            .push(u64::from(bm.amount));
+    }
+    for (keyset_id, amounts) in per_keyset {
+        tracing::info!(
+            %keyset_id,
+            confirm,
+            "Dry run: would sign {} messages worth {} with amounts {:?}",
+            amounts.len(),
+            amounts.iter().fold(0u64, |sum, a| sum.saturating_add(*a)),
+            amounts
+        );
+    }
+}
+
+/// Verify proofs carrying a DLEQ proof on the host and return the ones left for the device.
+///
+/// Checking `C = k*Y` directly needs the private key, which never leaves the device. A valid
+/// NUT-12 DLEQ proof shows the same relation against the cached public key, so those proofs
+/// are settled here. Proofs without one, or for keys that are not cached, go to the device.
+fn host_verify_proofs(keysets: &SignatoryKeysets, proofs: &[Proof]) -> Result<Vec<Proof>, Error> {
+    let mut remaining = Vec::new();
+    for proof in proofs {
+        let mint_pubkey = keysets
+            .keysets
+            .iter()
+            .find(|ks| ks.id == proof.keyset_id)
+            .and_then(|ks| ks.keys.amount_key(proof.amount));
+        match mint_pubkey {
+            Some(mint_pubkey) if proof.dleq.is_some() => proof.verify_dleq(mint_pubkey)?,
+            _ => remaining.push(proof.clone()),
+        }
+    }
+    Ok(remaining)
+}
+
+/// Reject proofs the device would refuse anyway, without a device round trip.
+///
+/// Only the device holds the private keys, so this checks that every proof names a known
+/// keyset and amount, and that the DLEQ proofs carried by some proofs are valid.
+fn preverify_proofs(keysets: &SignatoryKeysets, proofs: &[Proof]) -> Result<(), Error> {
+    for proof in proofs {
+        let keyset = keysets
+            .keysets
+            .iter()
+            .find(|ks| ks.id == proof.keyset_id)
+            .ok_or(Error::UnknownKeySet)?;
+        let mint_pubkey = keyset
+            .keys
+            .amount_key(proof.amount)
+            .ok_or(Error::AmountKey)?;
+        if proof.dleq.is_some() {
+            proof.verify_dleq(mint_pubkey)?;
+        }
+    }
+    Ok(())
+}
+
+/// Enforce the spending conditions of locked proofs: P2PK (NUT-11) and HTLC (NUT-14).
+///
+/// Runs the checks of cdk itself: the hash lock of HTLCs, the witness signatures over the
+/// secret, the number of signatures required and, past the locktime, the refund keys.
+/// `SIG_ALL` signatures commit to the outputs of the whole transaction, which the signatory
+/// never sees, so those proofs are left to the mint.
+fn verify_spending_conditions(proofs: &[Proof]) -> Result<(), Error> {
+    for proof in proofs {
+        match SpendingConditions::try_from(&proof.secret) {
+            Ok(SpendingConditions::P2PKConditions { conditions, .. }) => {
+                if !is_sig_all(&conditions) {
+                    proof.verify_p2pk()?;
+                }
+            }
+            // the hash lock, or past the locktime the refund keys, and the signatures, failing
+            // with the NUT-14 error the mint would return
+            Ok(SpendingConditions::HTLCConditions { conditions, .. }) => {
+                if !is_sig_all(&conditions) {
+                    proof.verify_htlc()?;
+                }
+            }
+            // plain secrets carry no conditions
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_sig_all(conditions: &Option<Conditions>) -> bool {
+    let sig_all = conditions
+        .as_ref()
+        .is_some_and(|c| c.sig_flag == SigFlag::SigAll);
+    if sig_all {
+        tracing::debug!("Skipping SIG_ALL proof, its witness covers outputs not seen here");
+    }
+    sig_all
+}
+
+#[async_trait::async_trait]
+impl Signatory for TrezorSignatory {
+    fn name(&self) -> String {
+        format!("Trezor Signatory {}", env!("CARGO_PKG_VERSION"))
+    }
+
+    async fn blind_sign(
+        &self,
+        blinded_messages: Vec<BlindedMessage>,
+    ) -> Result<Vec<BlindSignature>, Error> {
+        self.blind_sign_with_operation(blinded_messages, crate::operation::current())
+            .await
+    }
+
+    async fn verify_proofs(&self, proofs: Vec<Proof>) -> Result<(), Error> {
+        self.verify_proofs_with_operation(proofs, crate::operation::current())
+            .await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn keysets(&self) -> Result<SignatoryKeysets, Error> {
+        let cached = self.filled_keyset_cache().await?;
+        Ok(self.served_keysets(cached.clone()))
+    }
+
+    async fn rotate_keyset(&self, args: RotateKeyArguments) -> Result<SignatoryKeySet, Error> {
+        self.check_not_watch_only("rotate_keyset")?;
+        self.check_not_dry_run("rotate_keyset")?;
+        if self.is_locked() {
+            return Err(TrezorSignatoryError::Unavailable(
+                "the devices are locked by the operator".to_string(),
+            )
+            .into());
+        }
+        if self.is_standby() {
+            // the serving peer would keep signing with the keyset this rotation deactivates
+            return Err(TrezorSignatoryError::Unavailable(
+                "this signatory is on standby, rotate keysets on its peer".to_string(),
+            )
+            .into());
+        }
+        self.policy.check_rotation(&args)?;
+        let pool = match self.routes.backend(&args.unit) {
+            Some(Backend::Software(software)) => return software.rotate_keyset(args).await,
+            Some(Backend::Pool(pool)) => pool.clone(),
+            None => self.pool.clone(),
+        };
+        let mut req: protos::CashuRotateKeyset = args.try_into_cdk()?;
+        if let Some(account) = self.options.account {
+            req.set_account(account);
+        }
+
+        // every device must derive the same new keyset, otherwise the pool would diverge
+        let mut keyset: Option<SignatoryKeySet> = None;
+        for device in pool.devices() {
+            let result: protos::CashuRotateKeysetResponse =
+                pool.scope(device.call(req.clone())).await?;
+            let rotated: SignatoryKeySet = result
+                .keyset
+                .into_option()
+                .ok_or(TrezorSignatoryError::Mapping(
+                    "missing keyset in response".to_string(),
+                ))?
+                .try_into_cdk()?;
+            if let Some(first) = &keyset {
+                if first.id != rotated.id {
+                    return Err(Error::Custom(
+                        "Devices in the pool rotated to different keysets".to_string(),
+                    ));
+                }
+            } else {
+                keyset = Some(rotated);
+            }
+        }
+        let keyset = keyset.ok_or(Error::Custom("Device pool is empty".to_string()))?;
+
+        // the device deactivates the previous keyset for the unit, so the whole cache is stale
+        self.update_cached_keysets().await?;
+
+        Ok(keyset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use cdk_common::SecretKey;
+
+    use super::*;
+
+    /// One active sat keyset of powers of two, without keys
+    fn sat_keysets() -> (SignatoryKeysets, Id) {
+        let id = Id::from_str("009a1f293253e41e").unwrap();
+        let keyset = SignatoryKeySet {
+            id,
+            unit: CurrencyUnit::Sat,
+            active: true,
+            keys: Keys::new(BTreeMap::new()),
+            amounts: (0..32).map(|i| 1 << i).collect(),
+            input_fee_ppk: 0,
+            final_expiry: None,
+        };
+        let keysets = SignatoryKeysets {
+            pubkey: SecretKey::generate().public_key(),
+            keysets: vec![keyset],
+        };
+        (keysets, id)
+    }
+
+    /// Blinded message of `amount` for `keyset_id` with a random blinded secret
+    fn blinded_message(keyset_id: Id, amount: u64) -> BlindedMessage {
+        BlindedMessage::new(
+            Amount::from(amount),
+            keyset_id,
+            SecretKey::generate().public_key(),
+        )
+    }
+
+    /// Signature as the device returns it for `message`, with a random signature point
+    fn signature(message: &BlindedMessage) -> BlindSignature {
+        BlindSignature {
+            amount: message.amount,
+            keyset_id: message.keyset_id,
+            c: SecretKey::generate().public_key(),
+            dleq: None,
+        }
+    }
+
+    #[test]
+    fn dedup_signs_repeated_messages_once() {
+        let (_, id) = sat_keysets();
+        let (a, b) = (blinded_message(id, 1), blinded_message(id, 2));
+        let (unique, positions) = dedup_blinded_messages(&[a.clone(), b.clone(), a.clone()]);
+        assert_eq!(unique, vec![a, b]);
+        assert_eq!(positions, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn dedup_keeps_same_secret_for_other_amount() {
+        let (_, id) = sat_keysets();
+        let a = blinded_message(id, 1);
+        let other_amount = BlindedMessage::new(Amount::from(2), id, a.blinded_secret);
+        let (unique, positions) = dedup_blinded_messages(&[a, other_amount]);
+        assert_eq!(unique.len(), 2);
+        assert_eq!(positions, vec![0, 1]);
+    }
+
+    #[test]
+    fn check_amounts_accepts_keyset_denominations() {
+        let (keysets, id) = sat_keysets();
+        let items = [(id, Amount::from(1)), (id, Amount::from(1 << 20))];
+        assert!(check_amounts(&keysets, "blinded message", items).is_ok());
+    }
+
+    #[test]
+    fn check_amounts_rejects_other_amounts_and_keysets() {
+        let (keysets, id) = sat_keysets();
+        for amount in [0, 3] {
+            assert!(check_amounts(&keysets, "proof", [(id, Amount::from(amount))]).is_err());
+        }
+        let unknown = Id::from_str("00deadbeef123456").unwrap();
+        assert!(check_amounts(&keysets, "proof", [(unknown, Amount::from(1))]).is_err());
+    }
+
+    #[test]
+    fn check_signatures_match_accepts_device_signatures() {
+        let (_, id) = sat_keysets();
+        let messages = [blinded_message(id, 1), blinded_message(id, 2)];
+        let signatures: Vec<_> = messages.iter().map(signature).collect();
+        assert!(check_signatures_match(&messages, &signatures).is_ok());
+    }
+
+    #[test]
+    fn check_signatures_match_rejects_missing_and_reordered_signatures() {
+        let (_, id) = sat_keysets();
+        let messages = [blinded_message(id, 1), blinded_message(id, 2)];
+        let mut signatures: Vec<_> = messages.iter().map(signature).collect();
+        assert!(check_signatures_match(&messages, &signatures[..1]).is_err());
+        signatures.swap(0, 1);
+        assert!(check_signatures_match(&messages, &signatures).is_err());
     }
 
-    async fn rotate_keyset(&self, _args: RotateKeyArguments) -> Result<SignatoryKeySet, Error> {
-        Err(Error::Custom("Operation not supported".to_string()))
+    #[test]
+    fn check_signatures_match_rejects_echoed_message() {
+        let (_, id) = sat_keysets();
+        let messages = [blinded_message(id, 1)];
+        let mut signatures = vec![signature(&messages[0])];
+        signatures[0].c = messages[0].blinded_secret;
+        assert!(check_signatures_match(&messages, &signatures).is_err());
     }
 }
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use anyhow::{Context, Result};
+use bip39::Mnemonic;
+use cdk_common::Error;
+use cdk_common::nuts::{BlindSignature, BlindedMessage, CurrencyUnit, KeySetVersion, Proof};
+use cdk_signatory::signatory::{RotateKeyArguments, SignatoryKeySet};
+use serde::{Deserialize, Serialize};
+
+use crate::host_signer::{HostKeyset, HostSigner};
+use crate::provision::{DEFAULT_MAX_ORDER, power_of_two_amounts};
+use crate::state_file;
+
+/// Signs blind auth tokens (NUT-21/22) on the host instead of the device
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthSignerConfig {
+    /// File holding the BIP-39 mnemonic the auth keys are derived from. Unset sends auth
+    /// requests to the device like any other unit.
+    pub seed_file: Option<PathBuf>,
+    /// File recording the auth keysets, so rotations survive restarts
+    pub state_file: Option<PathBuf>,
+}
+
+/// Keyset parameters kept in the state file, the keys are derived again on load
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeysetRecord {
+    unit: CurrencyUnit,
+    /// Derivation index within the unit
+    index: u32,
+    amounts: Vec<u64>,
+    input_fee_ppk: u64,
+    final_expiry: Option<u64>,
+    active: bool,
+}
+
+impl From<&HostKeyset> for KeysetRecord {
+    fn from(keyset: &HostKeyset) -> Self {
+        Self {
+            unit: keyset.keyset.unit.clone(),
+            index: keyset.index,
+            amounts: keyset.amounts.clone(),
+            input_fee_ppk: keyset.input_fee_ppk,
+            final_expiry: keyset.keyset.final_expiry,
+            active: keyset.active,
+        }
+    }
+}
+
+/// Signatory for units that do not need the hardware wallet, with keys held on the host.
+///
+/// Blind auth tokens only gate access to the mint and carry no value, so signing them in
+/// software spares the device a round trip per token. Keys are derived from a local mnemonic
+/// by the CDK scheme, which should not be the device's. Each unit starts with one keyset,
+/// of amount 1 for auth and power of two amounts for other units.
+pub struct SoftwareSigner {
+    units: Vec<CurrencyUnit>,
+    signer: RwLock<HostSigner>,
+    state_file: Option<PathBuf>,
+    /// Held through a rotation, so rotations and their state file writes land in order
+    rotating: tokio::sync::Mutex<()>,
+}
+
+impl SoftwareSigner {
+    /// Signer for the auth unit configured in `config`, if there is one
+    pub fn for_auth(config: &AuthSignerConfig, account: Option<u32>) -> Result<Option<Self>> {
+        let Some(seed_file) = &config.seed_file else {
+            return Ok(None);
+        };
+        Self::load(
+            seed_file,
+            config.state_file.clone(),
+            vec![CurrencyUnit::Auth],
+            account,
+        )
+        .map(Some)
+    }
+
+    /// Derive the keysets of `units` under `account` from the mnemonic in `seed_file`,
+    /// restoring rotations from `state_file` if it exists
+    pub fn load(
+        seed_file: &Path,
+        state_file: Option<PathBuf>,
+        units: Vec<CurrencyUnit>,
+        account: Option<u32>,
+    ) -> Result<Self> {
+        let words = std::fs::read_to_string(seed_file)
+            .with_context(|| format!("reading {}", seed_file.display()))?;
+        let mnemonic = Mnemonic::parse(words.trim())
+            .with_context(|| format!("{} does not hold a valid mnemonic", seed_file.display()))?;
+        let mut signer = HostSigner::new(&mnemonic.to_seed_normalized(""), account)?;
+
+        let mut records: Vec<KeysetRecord> = match &state_file {
+            Some(path) if path.exists() => {
+                let contents =
+                    std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+                serde_json::from_slice(&contents)
+                    .with_context(|| format!("parsing {}", path.display()))?
+            }
+            _ => Vec::new(),
+        };
+        records.retain(|record| units.contains(&record.unit));
+        for unit in &units {
+            if !records.iter().any(|record| &record.unit == unit) {
+                records.push(KeysetRecord {
+                    unit: unit.clone(),
+                    index: 0,
+                    amounts: initial_amounts(unit),
+                    input_fee_ppk: 0,
+                    final_expiry: None,
+                    active: true,
+                });
+            }
+        }
+        for record in records {
+            let keyset = signer.derive(
+                record.unit,
+                record.index,
+                &record.amounts,
+                record.final_expiry,
+                KeySetVersion::Version00,
+            )?;
+            signer.insert(HostKeyset {
+                keyset,
+                index: record.index,
+                amounts: record.amounts,
+                input_fee_ppk: record.input_fee_ppk,
+                active: record.active,
+            });
+        }
+
+        let signer = Self {
+            units,
+            signer: RwLock::new(signer),
+            state_file,
+            rotating: tokio::sync::Mutex::new(()),
+        };
+        // the initial keysets are derived again on every start, so the state file is only
+        // written once a unit rotates
+        if signer.state_file.is_none() {
+            tracing::warn!(
+                "No state file for the software signer, its keyset rotations are lost on restart"
+            );
+        }
+        tracing::info!(
+            "Software signer serves {:?} with keys held on this host",
+            signer.units
+        );
+        Ok(signer)
+    }
+
+    /// Whether keysets of `unit` are served by this signer rather than the device
+    pub fn serves_unit(&self, unit: &CurrencyUnit) -> bool {
+        self.units.contains(unit)
+    }
+
+    pub fn keysets(&self) -> Vec<SignatoryKeySet> {
+        self.read().keysets().iter().map(HostKeyset::info).collect()
+    }
+
+    pub fn blind_sign(&self, messages: &[BlindedMessage]) -> Result<Vec<BlindSignature>, Error> {
+        self.read().blind_sign(messages)
+    }
+
+    pub fn verify_proofs(&self, proofs: &[Proof]) -> Result<(), Error> {
+        self.read().verify_proofs(proofs)
+    }
+
+    /// Derive the next keyset of the unit and make it the only active one
+    pub async fn rotate_keyset(&self, args: RotateKeyArguments) -> Result<SignatoryKeySet, Error> {
+        if !self.serves_unit(&args.unit) {
+            return Err(Error::UnsupportedUnit);
+        }
+        let _rotating = self.rotating.lock().await;
+        let mut signer = self.signer.write().unwrap_or_else(|e| e.into_inner());
+        let info = signer
+            .rotate(
+                args.unit.clone(),
+                &args.amounts,
+                args.input_fee_ppk,
+                args.final_expiry,
+            )?
+            .info();
+        let records: Vec<KeysetRecord> = signer.keysets().iter().map(KeysetRecord::from).collect();
+        drop(signer);
+        self.persist(&records)
+            .await
+            .map_err(|e| Error::Custom(format!("{:#}", e)))?;
+        tracing::info!("Rotated software {} keyset to {}", args.unit, info.id);
+        Ok(info)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HostSigner> {
+        self.signer.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn persist(&self, records: &[KeysetRecord]) -> Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        let contents = serde_json::to_vec_pretty(records)?;
+        state_file::write(path, contents)
+            .await
+            .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn initial_amounts(unit: &CurrencyUnit) -> Vec<u64> {
+    match unit {
+        CurrencyUnit::Auth => vec![1],
+        _ => power_of_two_amounts(DEFAULT_MAX_ORDER),
+    }
+}
@@ -0,0 +1,313 @@
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::net::UdpSocket;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use crate::device::DeviceHealth;
+use crate::signatory::{TrezorSignatory, unix_now};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the HMAC-SHA256 tag in front of every heartbeat
+const TAG_LEN: usize = 32;
+
+/// Which of the two instances serves while both are healthy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StandbyRole {
+    Primary,
+    Standby,
+}
+
+/// Hot standby pair of signatories, each with its own device initialized from the same seed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StandbyConfig {
+    /// Role of this instance. Unset runs a single signatory.
+    pub role: Option<StandbyRole>,
+    /// UDP address heartbeats from the peer arrive on, e.g. `0.0.0.0:15062`
+    pub listen_addr: Option<String>,
+    /// UDP address of the peer's `listen_addr`
+    pub peer_addr: Option<String>,
+    /// File holding the key both instances authenticate their heartbeats with
+    pub key_file: Option<PathBuf>,
+    /// Seconds between heartbeats
+    pub interval_secs: u64,
+    /// Seconds without a heartbeat after which the peer is considered gone
+    pub failover_after_secs: u64,
+    /// Run when this instance starts serving, e.g. to claim the virtual IP
+    pub takeover_command: Vec<String>,
+    /// Run when this instance stops serving, e.g. to release the virtual IP
+    pub release_command: Vec<String>,
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        Self {
+            role: None,
+            listen_addr: None,
+            peer_addr: None,
+            key_file: None,
+            interval_secs: 1,
+            failover_after_secs: 5,
+            takeover_command: Vec::new(),
+            release_command: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Heartbeat {
+    role: StandbyRole,
+    /// Whether every device pool of the sender can sign
+    healthy: bool,
+    /// Unix timestamp in seconds, heartbeats older than the failover timeout are ignored
+    at: u64,
+}
+
+/// What this instance last heard from its peer
+struct Peer {
+    heartbeat: Heartbeat,
+    received: Instant,
+}
+
+/// Whether an instance in `role` should serve, given its own health and its peer's last
+/// heartbeat, `None` while there is none.
+///
+/// The primary serves whenever its devices can sign. The standby only serves while the
+/// primary is gone or reports that its devices cannot sign, and steps back as soon as the
+/// primary recovers.
+fn should_serve(
+    role: StandbyRole,
+    healthy: bool,
+    peer: Option<&Heartbeat>,
+    peer_gone: bool,
+) -> bool {
+    healthy
+        && match (role, peer) {
+            (StandbyRole::Primary, _) => true,
+            (StandbyRole::Standby, Some(peer)) => !peer.healthy,
+            // the peer may not have announced itself yet
+            (StandbyRole::Standby, None) => peer_gone,
+        }
+}
+
+/// Exchange heartbeats with the peer and serve or stand by accordingly, if `config` has a role.
+///
+/// Both instances start in standby. The signatory of an instance on standby refuses
+/// blind_sign and verify_proofs and reports NOT_SERVING, so health-checking load balancers
+/// and the takeover and release commands steer the mint to the serving one.
+pub async fn spawn(
+    signatory: Arc<TrezorSignatory>,
+    config: &StandbyConfig,
+) -> Result<Option<JoinHandle<()>>> {
+    let Some(role) = config.role else {
+        return Ok(None);
+    };
+    let (Some(listen_addr), Some(peer_addr), Some(key_file)) =
+        (&config.listen_addr, &config.peer_addr, &config.key_file)
+    else {
+        anyhow::bail!("standby needs listen_addr, peer_addr and key_file");
+    };
+    if config.interval_secs == 0 || config.failover_after_secs <= config.interval_secs {
+        anyhow::bail!("standby failover_after_secs must be longer than interval_secs");
+    }
+    let key = crate::server::load_token(key_file)?;
+    let socket = UdpSocket::bind(listen_addr)
+        .await
+        .with_context(|| format!("binding {}", listen_addr))?;
+    let peer_addr = tokio::net::lookup_host(peer_addr)
+        .await
+        .with_context(|| format!("resolving {}", peer_addr))?
+        .next()
+        .with_context(|| format!("{} does not resolve", peer_addr))?;
+    signatory.set_standby(true);
+    tracing::info!(
+        "Running as {:?} of a standby pair, heartbeats on {} to {}",
+        role,
+        socket.local_addr()?,
+        peer_addr
+    );
+
+    let config = config.clone();
+    Ok(Some(tokio::spawn(async move {
+        let interval = Duration::from_secs(config.interval_secs);
+        let failover_after = Duration::from_secs(config.failover_after_secs);
+        let mut ticker = tokio::time::interval(interval);
+        let started = Instant::now();
+        let mut peer: Option<Peer> = None;
+        let mut buf = [0u8; 1024];
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                received = socket.recv_from(&mut buf) => {
+                    match received {
+                        Ok((len, from)) => match open(key.as_bytes(), &buf[..len], failover_after) {
+                            Some(heartbeat) if heartbeat.role == role => {
+                                tracing::error!("Peer {} is configured as {:?} too", from, role);
+                            }
+                            Some(heartbeat) => {
+                                peer = Some(Peer { heartbeat, received: Instant::now() });
+                            }
+                            None => tracing::warn!("Ignoring invalid heartbeat from {}", from),
+                        },
+                        Err(err) => tracing::warn!("Failed to receive heartbeat: {}", err),
+                    }
+                    continue;
+                }
+            }
+
+            if peer
+                .as_ref()
+                .is_some_and(|peer| peer.received.elapsed() > failover_after)
+            {
+                tracing::warn!("No heartbeat from the peer for {:?}", failover_after);
+                peer = None;
+            }
+            let healthy = signatory.health().await == DeviceHealth::Ready;
+            let serve = should_serve(
+                role,
+                healthy,
+                peer.as_ref().map(|peer| &peer.heartbeat),
+                started.elapsed() > failover_after,
+            );
+            if serve == signatory.is_standby() {
+                if serve {
+                    tracing::warn!("Serving as the {:?} of the standby pair", role);
+                    run_hook(&config.takeover_command).await;
+                } else {
+                    tracing::warn!("Standing by for the peer");
+                    run_hook(&config.release_command).await;
+                }
+                signatory.set_standby(!serve);
+            }
+
+            let heartbeat = Heartbeat {
+                role,
+                healthy,
+                at: unix_now(),
+            };
+            if let Err(err) = socket
+                .send_to(&seal(key.as_bytes(), &heartbeat), peer_addr)
+                .await
+            {
+                tracing::debug!("Failed to send heartbeat to {}: {}", peer_addr, err);
+            }
+        }
+    })))
+}
+
+fn seal(key: &[u8], heartbeat: &Heartbeat) -> Vec<u8> {
+    let body = serde_json::to_vec(heartbeat).expect("heartbeats serialize");
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&body);
+    let mut datagram = mac.finalize().into_bytes().to_vec();
+    datagram.extend_from_slice(&body);
+    datagram
+}
+
+/// Heartbeat in `datagram` if it carries a valid tag and is recent
+fn open(key: &[u8], datagram: &[u8], max_age: Duration) -> Option<Heartbeat> {
+    if datagram.len() < TAG_LEN {
+        return None;
+    }
+    let (tag, body) = datagram.split_at(TAG_LEN);
+    let mut mac = HmacSha256::new_from_slice(key).ok()?;
+    mac.update(body);
+    mac.verify_slice(tag).ok()?;
+    let heartbeat: Heartbeat = serde_json::from_slice(body).ok()?;
+    (heartbeat.at + max_age.as_secs() >= unix_now()).then_some(heartbeat)
+}
+
+async fn run_hook(command: &[String]) {
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    match Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .status()
+        .await
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => tracing::error!("{} exited with {}", program, status),
+        Err(err) => tracing::error!("Failed to run {}: {}", program, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(role: StandbyRole, healthy: bool) -> Heartbeat {
+        Heartbeat {
+            role,
+            healthy,
+            at: unix_now(),
+        }
+    }
+
+    #[test]
+    fn primary_serves_while_healthy() {
+        let peer = heartbeat(StandbyRole::Standby, true);
+        assert!(should_serve(StandbyRole::Primary, true, Some(&peer), false));
+        assert!(should_serve(StandbyRole::Primary, true, None, false));
+        assert!(!should_serve(StandbyRole::Primary, false, None, true));
+    }
+
+    #[test]
+    fn standby_serves_only_without_a_healthy_primary() {
+        let healthy = heartbeat(StandbyRole::Primary, true);
+        let unhealthy = heartbeat(StandbyRole::Primary, false);
+        assert!(!should_serve(
+            StandbyRole::Standby,
+            true,
+            Some(&healthy),
+            true
+        ));
+        assert!(should_serve(
+            StandbyRole::Standby,
+            true,
+            Some(&unhealthy),
+            false
+        ));
+        assert!(!should_serve(
+            StandbyRole::Standby,
+            false,
+            Some(&unhealthy),
+            false
+        ));
+        // no heartbeat yet, wait for the primary to announce itself before taking over
+        assert!(!should_serve(StandbyRole::Standby, true, None, false));
+        assert!(should_serve(StandbyRole::Standby, true, None, true));
+    }
+
+    #[test]
+    fn heartbeats_are_authenticated_and_recent() {
+        let max_age = Duration::from_secs(5);
+        let datagram = seal(b"key", &heartbeat(StandbyRole::Primary, true));
+        let opened = open(b"key", &datagram, max_age).unwrap();
+        assert_eq!(opened.role, StandbyRole::Primary);
+        assert!(opened.healthy);
+
+        assert!(open(b"other key", &datagram, max_age).is_none());
+        let mut tampered = datagram.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(b"key", &tampered, max_age).is_none());
+        assert!(open(b"key", &datagram[..TAG_LEN - 1], max_age).is_none());
+
+        let stale = Heartbeat {
+            at: unix_now() - 60,
+            ..heartbeat(StandbyRole::Primary, true)
+        };
+        assert!(open(b"key", &seal(b"key", &stale), max_age).is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replace `path` with `contents` so a crash leaves either the old or the new file, never a
+/// truncated one.
+///
+/// The contents go to a temporary file next to `path`, which is synced before it is renamed
+/// over `path`, and the directory is synced after so the rename itself survives a power loss.
+/// Runs on the blocking pool, as syncing can stall for a while on a busy disk. Callers writing
+/// the same file concurrently must order their writes themselves.
+pub async fn write(path: &Path, contents: Vec<u8>) -> io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_synced(&path, &contents))
+        .await
+        .map_err(io::Error::other)?
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    File::open(parent_dir(path))?.sync_all()
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
@@ -0,0 +1,72 @@
+use std::os::fd::{FromRawFd, IntoRawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use sd_notify::NotifyState;
+
+use crate::device::DeviceHealth;
+use crate::server::Listener;
+use crate::signatory::TrezorSignatory;
+
+/// Socket passed in by systemd socket activation (`LISTEN_FDS`), if any
+pub fn activated_listener() -> Result<Option<Listener>> {
+    let mut fds = sd_notify::listen_fds().context("reading LISTEN_FDS")?;
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+    if fds.next().is_some() {
+        tracing::warn!("systemd passed several sockets, only the first one is used");
+    }
+
+    // SAFETY: systemd hands the descriptor over to this process and nothing else owns it
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    // getsockname only yields an address for inet sockets
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+        return Ok(Some(Listener::ActivatedTcp(tcp)));
+    }
+    // SAFETY: ownership moves back out of the TcpListener, which is not used afterwards
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true)?;
+    Ok(Some(Listener::ActivatedUnix(unix)))
+}
+
+/// Tell systemd the service is ready, a no-op outside of systemd
+pub fn notify_ready() {
+    notify(NotifyState::Ready);
+}
+
+pub fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+fn notify(state: NotifyState) {
+    if let Err(err) = sd_notify::notify(false, &[state]) {
+        tracing::debug!("Failed to notify systemd: {}", err);
+    }
+}
+
+/// Ping the systemd watchdog while a device responds.
+///
+/// Pings stop while no device is reachable, so systemd restarts the service once
+/// `WatchdogSec` passes without one.
+pub fn spawn_watchdog(signatory: Arc<TrezorSignatory>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec) / 2;
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(period);
+        loop {
+            ticker.tick().await;
+            match signatory.health().await {
+                DeviceHealth::Disconnected => {
+                    tracing::warn!("No device responding, withholding the systemd watchdog ping")
+                }
+                DeviceHealth::Ready | DeviceHealth::Locked => notify(NotifyState::Watchdog),
+            }
+        }
+    });
+}
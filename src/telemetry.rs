@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing::Subscriber;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+const SERVICE_NAME: &str = "cdk-signatory-trezor";
+
+/// Spans exported to an OTLP collector over gRPC
+pub struct OtlpExport {
+    provider: SdkTracerProvider,
+}
+
+impl OtlpExport {
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .with_context(|| format!("creating OTLP exporter for {}", endpoint))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+            .build();
+        opentelemetry::global::set_tracer_provider(provider.clone());
+        Ok(Self { provider })
+    }
+
+    /// `tracing` layer forwarding spans to the exporter
+    pub fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer(SERVICE_NAME))
+    }
+
+    /// Flush spans still buffered
+    pub fn shutdown(&self) {
+        if let Err(err) = self.provider.shutdown() {
+            tracing::warn!("Failed to flush OTLP spans: {}", err);
+        }
+    }
+}
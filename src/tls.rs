@@ -0,0 +1,196 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// How often `tls_dir` is checked for a renewed server certificate
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// Connections that do not finish the handshake in time are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mutual TLS for the TCP listeners, with a server certificate that is reloaded from
+/// `tls_dir` when it changes, e.g. after certbot renewed it.
+///
+/// Connections already established keep the certificate they were opened with.
+pub struct ReloadingTls {
+    acceptor: TlsAcceptor,
+    cert: Arc<ReloadingCert>,
+}
+
+impl ReloadingTls {
+    /// Load the server identity and client CA from the directory created by `gen-certs` or
+    /// `generate_certs.sh`.
+    ///
+    /// Clients must present a certificate signed by the CA, `client_ca` replaces `ca.pem` from
+    /// the directory. `alpn` lists the application protocols offered, e.g. `h2` for gRPC.
+    pub fn load(tls_dir: &Path, client_ca: Option<&Path>, alpn: &[&[u8]]) -> Result<Self> {
+        let provider = Arc::new(ring::default_provider());
+        let ca_path = client_ca
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| tls_dir.join("ca.pem"));
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut read(&ca_path)?.as_slice()) {
+            let cert = cert.with_context(|| format!("parsing {}", ca_path.display()))?;
+            roots
+                .add(cert)
+                .with_context(|| format!("adding client CA {}", ca_path.display()))?;
+        }
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+                .context("building client certificate verifier")?;
+
+        let cert = Arc::new(ReloadingCert::load(tls_dir.to_path_buf())?);
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_cert_resolver(cert.clone());
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            cert,
+        })
+    }
+
+    /// Periodically reload the server certificate if its files changed
+    pub fn spawn_reload(&self) {
+        let cert = self.cert.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(RELOAD_INTERVAL);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(err) = cert.reload_if_changed() {
+                    tracing::error!("Failed to reload the server certificate: {:#}", err);
+                }
+            }
+        });
+    }
+
+    /// Accept connections on `listeners` and yield them once the TLS handshake completed.
+    ///
+    /// Handshakes run concurrently so a slow client cannot hold up other connections.
+    pub fn incoming(
+        &self,
+        listeners: Vec<TcpListener>,
+    ) -> ReceiverStream<io::Result<TlsStream<TcpStream>>> {
+        let (tx, rx) = mpsc::channel(16);
+        for listener in listeners {
+            let acceptor = self.acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, peer) = tokio::select! {
+                        // the server stopped taking connections
+                        _ = tx.closed() => return,
+                        accepted = listener.accept() => match accepted {
+                            Ok(accepted) => accepted,
+                            Err(err) => {
+                                tracing::warn!("Failed to accept connection: {}", err);
+                                tokio::time::sleep(Duration::from_millis(100)).await;
+                                continue;
+                            }
+                        },
+                    };
+                    let acceptor = acceptor.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+                        {
+                            Ok(Ok(tls)) => {
+                                let _ = tx.send(Ok(tls)).await;
+                            }
+                            Ok(Err(err)) => {
+                                tracing::debug!("TLS handshake with {} failed: {}", peer, err)
+                            }
+                            Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                        }
+                    });
+                }
+            });
+        }
+        ReceiverStream::new(rx)
+    }
+}
+
+/// Server certificate handed to new connections, swapped when the files change
+#[derive(Debug)]
+struct ReloadingCert {
+    tls_dir: PathBuf,
+    current: RwLock<(Arc<CertifiedKey>, Option<SystemTime>)>,
+}
+
+impl ReloadingCert {
+    fn load(tls_dir: PathBuf) -> Result<Self> {
+        let modified = last_modified(&tls_dir);
+        let key = load_certified_key(&tls_dir)?;
+        Ok(Self {
+            tls_dir,
+            current: RwLock::new((Arc::new(key), modified)),
+        })
+    }
+
+    fn reload_if_changed(&self) -> Result<()> {
+        let modified = last_modified(&self.tls_dir);
+        if modified == self.current.read().unwrap_or_else(|e| e.into_inner()).1 {
+            return Ok(());
+        }
+        // keep serving the old certificate if the new files are incomplete or invalid
+        let key = load_certified_key(&self.tls_dir)?;
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = (Arc::new(key), modified);
+        tracing::info!(
+            "Reloaded the server certificate from {}",
+            self.tls_dir.display()
+        );
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(
+            self.current
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .0
+                .clone(),
+        )
+    }
+}
+
+fn load_certified_key(tls_dir: &Path) -> Result<CertifiedKey> {
+    let cert_path = tls_dir.join("server.pem");
+    let key_path = tls_dir.join("server.key");
+    let certs = rustls_pemfile::certs(&mut read(&cert_path)?.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("parsing {}", cert_path.display()))?;
+    let key = rustls_pemfile::private_key(&mut read(&key_path)?.as_slice())
+        .with_context(|| format!("parsing {}", key_path.display()))?
+        .with_context(|| format!("no private key in {}", key_path.display()))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .with_context(|| format!("unsupported key in {}", key_path.display()))?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/// Latest modification time of the server certificate and key, following symlinks
+fn last_modified(tls_dir: &Path) -> Option<SystemTime> {
+    ["server.pem", "server.key"]
+        .iter()
+        .filter_map(|file| std::fs::metadata(tls_dir.join(file)).ok()?.modified().ok())
+        .max()
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("reading {}", path.display()))
+}
@@ -1,24 +1,455 @@
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
 use cdk_common::Error;
-use trezor_client::{TrezorMessage, TrezorResponse};
+use serde::Deserialize;
+use trezor_client::transport::AvailableDeviceTransport;
+use trezor_client::transport::udp::UdpTransport;
+use trezor_client::transport::webusb::WebUsbTransport;
+use trezor_client::{AvailableDevice, Trezor, TrezorMessage, TrezorResponse, protos};
+
+use crate::device::{DeviceSession, SessionState};
+use crate::error::TrezorSignatoryError;
+use crate::passphrase::{PassphraseAnswer, PassphraseProvider};
+use crate::pin::PinProvider;
+use crate::progress::{CallOrigin, ProgressEvents};
+
+/// Host-side answers to interaction requests raised by the device
+#[derive(Clone)]
+pub struct Interaction {
+    pub pin: Arc<dyn PinProvider>,
+    pub passphrase: Arc<dyn PassphraseProvider>,
+    /// Told about confirmation screens so clients can show progress
+    pub progress: ProgressEvents,
+    /// Session of the device the interaction belongs to, set by `TrezorDevice::connect`
+    pub session: Option<Arc<DeviceSession>>,
+    /// Cancellation of the call being served, set per call by `TrezorDevice`
+    pub cancel: CallCancel,
+    /// Device the interaction belongs to, as named in progress events
+    pub device: String,
+    /// Signatory call being served, set per call by `TrezorDevice`
+    pub call: Option<CallOrigin>,
+}
+
+impl Interaction {
+    fn set_state(&self, state: SessionState) {
+        if let Some(session) = &self.session {
+            session.set(state);
+        }
+    }
+
+    /// The device ended the call's workflow without finishing it and is idle again, still
+    /// locked if it stopped while asking for PIN or passphrase
+    pub fn set_aborted(&self) {
+        if let Some(session) = &self.session {
+            if session.get() != SessionState::Locked {
+                session.set(SessionState::Ready);
+            }
+        }
+    }
+}
+
+tokio::task_local! {
+    /// Interaction of the signatory the device calls of the current task are made for
+    static CALLER: Interaction;
+}
+
+/// Run `fut` with its device calls answering interaction requests through `interaction`, so a
+/// device shared by several signatories prompts and reports progress to the one calling
+pub async fn interaction_scope<F: Future>(interaction: Interaction, fut: F) -> F::Output {
+    CALLER.scope(interaction, fut).await
+}
+
+/// Interaction of the signatory calling from the current task, if it set one
+pub fn caller_interaction() -> Option<Interaction> {
+    CALLER.try_with(Interaction::clone).ok()
+}
+
+/// Cancellation of one device call, requested once nobody waits for its answer anymore, e.g.
+/// because the mint's deadline passed
+#[derive(Debug, Clone, Default)]
+pub struct CallCancel {
+    requested: Arc<AtomicBool>,
+    /// Set when an interaction request was left unanswered because of the cancellation
+    prompt_left: Arc<AtomicBool>,
+}
+
+impl CallCancel {
+    pub fn is_cancelled(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Guard requesting cancellation when dropped, held by the future waiting for the call
+    pub fn guard(&self) -> CancelGuard {
+        CancelGuard(self.requested.clone())
+    }
 
-/// Unwrap Trezor call responses and handle interaction requests
+    /// Whether the device is still waiting for an answer to an interaction request, which
+    /// should then be answered with `Cancel`
+    pub fn take_prompt_left(&self) -> bool {
+        self.prompt_left.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// See [`CallCancel::guard`]
+pub struct CancelGuard(Arc<AtomicBool>);
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Unwrap Trezor call responses and handle interaction requests.
+///
+/// The session state follows the device: it is only ready again once the device gave its
+/// final answer, not when a prompt was acknowledged. Once the call is cancelled, further
+/// interaction requests are left unanswered and the call fails, see
+/// [`CallCancel::take_prompt_left`].
 pub fn handle_trezor_call<T, R: TrezorMessage>(
     resp: Result<TrezorResponse<T, R>, trezor_client::Error>,
+    interaction: &Interaction,
 ) -> Result<T, Error> {
     match resp {
-        Err(err) => Err(Error::Custom(format!("Trezor call error: {:?}", err))),
-        Ok(TrezorResponse::Ok(res)) => Ok(res),
+        Err(err) => Err(TrezorSignatoryError::from_client(err).into()),
+        Ok(TrezorResponse::Ok(res)) => {
+            interaction.set_state(SessionState::Ready);
+            Ok(res)
+        }
         Ok(TrezorResponse::Failure(err)) => {
-            Err(Error::Custom(format!("Trezor failure response: {:?}", err)))
+            interaction.set_aborted();
+            Err(TrezorSignatoryError::from_failure(err).into())
+        }
+        Ok(
+            TrezorResponse::ButtonRequest(_)
+            | TrezorResponse::PinMatrixRequest(_)
+            | TrezorResponse::PassphraseRequest(_),
+        ) if interaction.cancel.is_cancelled() => {
+            interaction.cancel.prompt_left.store(true, Ordering::SeqCst);
+            Err(TrezorSignatoryError::Cancelled.into())
+        }
+        Ok(TrezorResponse::ButtonRequest(req)) => {
+            let code = format!("{:?}", req.request().code());
+            interaction.progress.awaiting_confirmation(
+                &interaction.device,
+                interaction.call.as_ref(),
+                &code,
+            );
+            interaction.set_state(SessionState::AwaitingConfirmation);
+            // ack blocks until the user answers on the device
+            let resp = req.ack();
+            interaction.progress.confirmation_answered(
+                &interaction.device,
+                interaction.call.as_ref(),
+                &code,
+            );
+            handle_trezor_call(resp, interaction)
+        }
+        Ok(TrezorResponse::PinMatrixRequest(req)) => {
+            interaction.set_state(SessionState::Locked);
+            let pin = interaction.pin.get_pin(req.request_type())?;
+            let resp = req.ack_pin(pin);
+            handle_trezor_call(resp, interaction)
         }
-        Ok(TrezorResponse::ButtonRequest(req)) => handle_trezor_call(req.ack()),
-        Ok(TrezorResponse::PinMatrixRequest(_)) => Err(Error::Custom(
-            "Pin matrix request not supported".to_string(),
-        )),
         Ok(TrezorResponse::PassphraseRequest(req)) => {
-            // empty passphrase
-            let pass = String::new();
-            handle_trezor_call(req.ack_passphrase(pass.to_owned()))
+            interaction.set_state(SessionState::Locked);
+            let resp = match interaction.passphrase.get_passphrase()? {
+                PassphraseAnswer::Host(pass) => req.ack_passphrase(pass),
+                PassphraseAnswer::OnDevice => req.ack(true),
+            };
+            handle_trezor_call(resp, interaction)
         }
     }
 }
+
+/// How often a call is retried after a transport failure, such as a USB glitch.
+///
+/// Only failures of the link are retried. Cancellations and firmware failures are answers
+/// from the device and are returned right away.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts per call including the first one
+    pub max_attempts: u32,
+    /// Pause before the first retry, doubled for each further retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Pause before retry number `retry`, counting from 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+/// Address the trezor-emulator listens on by default
+pub const EMULATOR_ADDR: &str = "127.0.0.1:21324";
+
+/// How devices are looked up.
+///
+/// trezor-client talks to devices directly and has no Trezor Bridge or legacy HID transport,
+/// so only WebUSB and UDP are available.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DeviceTransport {
+    /// WebUSB, falling back to the emulator on [`EMULATOR_ADDR`] if no device is plugged in
+    #[default]
+    Auto,
+    /// Physical devices over WebUSB
+    Usb,
+    /// The physical device behind a USB device node, e.g. `/dev/bus/usb/<bus>/<address>`, a
+    /// udev symlink like `/dev/trezor` or the node mapped into a container. Once opened, the
+    /// device is found again by its serial, as its node changes on replug.
+    UsbPath(PathBuf),
+    /// UDP, as used by the emulator, at `host:port`
+    Udp(String),
+    /// Answers served from a recording of an earlier session, see [`crate::record::ReplayDevice`]
+    Replay(PathBuf),
+    /// In-process mock signing with a public test seed, see [`crate::mock::MockDevice`]
+    #[cfg(feature = "mock-device")]
+    Mock,
+}
+
+impl FromStr for DeviceTransport {
+    type Err = String;
+
+    /// Parse `auto`, `usb`, `usb:<device node>`, `udp` (the default emulator address),
+    /// `udp:<host>:<port>` or `replay:<file>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            "usb" | "webusb" => Ok(Self::Usb),
+            "udp" => Ok(Self::Udp(EMULATOR_ADDR.to_string())),
+            #[cfg(feature = "mock-device")]
+            "mock" => Ok(Self::Mock),
+            "bridge" | "hid" => Err(format!(
+                "the {} transport is not supported, use usb or udp",
+                value
+            )),
+            _ => {
+                if let Some(path) = value.strip_prefix("replay:").filter(|p| !p.is_empty()) {
+                    return Ok(Self::Replay(PathBuf::from(path)));
+                }
+                if let Some(path) = value.strip_prefix("usb:").filter(|p| !p.is_empty()) {
+                    return Ok(Self::UsbPath(PathBuf::from(path)));
+                }
+                match value.strip_prefix("udp:") {
+                    Some(addr) if addr.contains(':') => Ok(Self::Udp(addr.to_string())),
+                    _ => Err(format!(
+                        "invalid transport {:?}, expected auto, usb, usb:<device node>, udp, udp:<host>:<port> or replay:<file>",
+                        value
+                    )),
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceTransport {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl DeviceTransport {
+    /// Whether devices may be found over USB, and so come and go with hotplug events
+    pub fn is_usb(&self) -> bool {
+        matches!(self, Self::Auto | Self::Usb | Self::UsbPath(_))
+    }
+
+    fn find_devices(&self) -> Result<Vec<AvailableDevice>, Error> {
+        match self {
+            Self::Auto => {
+                for transport in [Self::Usb, Self::Udp(EMULATOR_ADDR.to_string())] {
+                    match transport.find_devices() {
+                        Ok(devices) if !devices.is_empty() => return Ok(devices),
+                        Ok(_) => {}
+                        Err(err) => tracing::debug!("No devices on {}: {}", transport, err),
+                    }
+                }
+                Ok(Vec::new())
+            }
+            Self::Usb => WebUsbTransport::find_devices(false)
+                .map_err(|e| TrezorSignatoryError::from_client(e).into()),
+            Self::UsbPath(path) => {
+                let (bus, address) =
+                    usb_bus_address(path).map_err(TrezorSignatoryError::Transport)?;
+                let devices = WebUsbTransport::find_devices(false)
+                    .map_err(TrezorSignatoryError::from_client)?;
+                Ok(devices
+                    .into_iter()
+                    .filter(|device| match &device.transport {
+                        AvailableDeviceTransport::WebUsb(usb) => {
+                            usb.bus == bus && usb.address == address
+                        }
+                        _ => false,
+                    })
+                    .collect())
+            }
+            Self::Udp(addr) => UdpTransport::find_devices(false, Some(addr))
+                .map_err(|e| TrezorSignatoryError::from_client(e).into()),
+            // replay and the mock are opened by `backend::open_backend` without a lookup
+            Self::Replay(_) => Ok(Vec::new()),
+            #[cfg(feature = "mock-device")]
+            Self::Mock => Ok(Vec::new()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Usb => write!(f, "usb"),
+            Self::UsbPath(path) => write!(f, "usb:{}", path.display()),
+            Self::Udp(addr) => write!(f, "udp:{}", addr),
+            Self::Replay(path) => write!(f, "replay:{}", path.display()),
+            #[cfg(feature = "mock-device")]
+            Self::Mock => write!(f, "mock"),
+        }
+    }
+}
+
+/// Major number of USB device nodes on Linux
+const USB_DEVICE_MAJOR: u64 = 189;
+
+/// Bus and address of the USB device behind the node at `path`, read from the device number
+/// of the node rather than its name, so symlinks and nodes renamed in a container work
+fn usb_bus_address(path: &Path) -> Result<(u8, u8), String> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let metadata = std::fs::metadata(path)
+        .map_err(|e| format!("USB device node {}: {}", path.display(), e))?;
+    let rdev = metadata.rdev();
+    // the encoding of glibc's major() and minor()
+    let major = ((rdev >> 8) & 0xfff) | ((rdev >> 32) & 0xffff_f000);
+    let minor = (rdev & 0xff) | ((rdev >> 12) & 0xffff_ff00);
+    if !metadata.file_type().is_char_device() || major != USB_DEVICE_MAJOR {
+        return Err(format!("{} is not a USB device node", path.display()));
+    }
+    // the kernel numbers the nodes of bus b, address a as (b - 1) * 128 + a - 1
+    let bus = u8::try_from(minor / 128 + 1)
+        .map_err(|_| format!("{} has an invalid device number", path.display()))?;
+    Ok((bus, (minor % 128 + 1) as u8))
+}
+
+/// Criteria used to pick one device when several Trezors are connected
+#[derive(Debug, Clone, Default)]
+pub struct DeviceSelector {
+    /// Transport the device is looked up on
+    pub transport: DeviceTransport,
+    /// Device serial (`device_id` in the device features)
+    pub serial: Option<String>,
+    /// Device label set by the owner
+    pub label: Option<String>,
+}
+
+impl DeviceSelector {
+    fn is_any(&self) -> bool {
+        self.serial.is_none() && self.label.is_none()
+    }
+
+    fn matches(&self, features: &protos::Features) -> bool {
+        let serial_ok = self
+            .serial
+            .as_ref()
+            .is_none_or(|serial| serial.eq_ignore_ascii_case(features.device_id()));
+        let label_ok = self
+            .label
+            .as_ref()
+            .is_none_or(|label| label == features.label());
+        serial_ok && label_ok
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.serial, &self.label) {
+            (Some(serial), Some(label)) => write!(f, "serial {} and label {:?}", serial, label),
+            (Some(serial), None) => write!(f, "serial {}", serial),
+            (None, Some(label)) => write!(f, "label {:?}", label),
+            (None, None) => write!(f, "any device"),
+        }
+    }
+}
+
+/// Connect to the device matching `selector` and initialize it, resuming `session_id` if the
+/// device still knows it
+pub fn open_device(selector: &DeviceSelector, session_id: Option<&[u8]>) -> Result<Trezor, Error> {
+    // a device node only names a device until it is replugged, with a serial the device is
+    // found among all USB devices wherever it is now
+    let transport = match (&selector.transport, &selector.serial) {
+        (DeviceTransport::UsbPath(_), Some(_)) => &DeviceTransport::Usb,
+        (transport, _) => transport,
+    };
+    let devices = transport.find_devices()?;
+    if devices.is_empty() {
+        return Err(TrezorSignatoryError::Transport(format!(
+            "No Trezor device found on {} transport",
+            transport
+        ))
+        .into());
+    }
+    if selector.is_any() && devices.len() > 1 {
+        return Err(TrezorSignatoryError::Transport(format!(
+            "Found {} Trezor devices, pick one by serial or label",
+            devices.len()
+        ))
+        .into());
+    }
+
+    let mut found = Vec::new();
+    for device in devices {
+        let mut trezor = match device.connect() {
+            Ok(trezor) => trezor,
+            Err(err) => {
+                tracing::warn!("Skipping device that failed to connect: {:?}", err);
+                continue;
+            }
+        };
+        // other devices do not know the session and start a new one
+        if let Err(err) = trezor.init_device(session_id.map(<[u8]>::to_vec)) {
+            tracing::warn!("Skipping device that failed to initialize: {:?}", err);
+            continue;
+        }
+        let Some(features) = trezor.features() else {
+            continue;
+        };
+
+        tracing::info!(
+            "Found Trezor serial={} label={:?}",
+            features.device_id(),
+            features.label()
+        );
+        if selector.matches(features) {
+            return Ok(trezor);
+        }
+        found.push(format!("{} ({:?})", features.device_id(), features.label()));
+    }
+
+    Err(TrezorSignatoryError::Transport(format!(
+        "No connected Trezor matches {}; found: [{}]",
+        selector,
+        found.join(", ")
+    ))
+    .into())
+}
@@ -0,0 +1,49 @@
+use tonic::{Request, Status};
+
+/// Metadata key carrying the cdk-signatory protocol version the client speaks
+pub const VERSION_HEADER: &str = "x-cdk-signatory-version";
+
+/// Version of the cdk-signatory gRPC protocol served, that of the `cdk-signatory` crate the
+/// signatory is built against, as resolved in Cargo.lock by the build script
+pub const PROTOCOL_VERSION: &str = env!("CDK_SIGNATORY_VERSION");
+
+/// Reject clients announcing an incompatible protocol version.
+///
+/// Versions are compatible when the major version matches and, while it is 0, the minor
+/// version as well. The check is opt-in on the client side: the cdk gRPC client does not send
+/// [`VERSION_HEADER`], so only mints that add it, e.g. with an interceptor, are checked and
+/// calls without it are accepted.
+pub fn check(req: &Request<()>) -> Result<(), Status> {
+    let Some(value) = req.metadata().get(VERSION_HEADER) else {
+        return Ok(());
+    };
+    let client = value
+        .to_str()
+        .map_err(|_| Status::invalid_argument(format!("{} is not valid text", VERSION_HEADER)))?;
+    if is_compatible(client, PROTOCOL_VERSION) {
+        return Ok(());
+    }
+    tracing::warn!(
+        "Rejected client speaking cdk-signatory protocol {}, serving {}",
+        client,
+        PROTOCOL_VERSION
+    );
+    Err(Status::failed_precondition(format!(
+        "cdk-signatory protocol {} is not supported, this signatory speaks {}",
+        client, PROTOCOL_VERSION
+    )))
+}
+
+fn is_compatible(client: &str, server: &str) -> bool {
+    let parse = |version: &str| -> Option<(u64, u64)> {
+        let mut parts = version.trim().trim_start_matches('v').split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().unwrap_or("0").parse().ok()?;
+        Some((major, minor))
+    };
+    match (parse(client), parse(server)) {
+        (Some((0, client_minor)), Some((0, server_minor))) => client_minor == server_minor,
+        (Some((client_major, _)), Some((server_major, _))) => client_major == server_major,
+        _ => false,
+    }
+}
@@ -0,0 +1,163 @@
+//! End-to-end check of the signatory behind a real CDK mint.
+//!
+//! Starts the signatory binary on the in-process mock device, builds a mint with the fake
+//! Lightning backend that signs through it over gRPC and runs mint, swap and melt with a
+//! wallet, so mapping regressions between CDK and the device protocol show up without
+//! hardware. Run with `cargo test --features mock-device`.
+
+use std::collections::{HashMap, HashSet};
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bip39::Mnemonic;
+use cdk::Amount;
+use cdk::amount::SplitTarget;
+use cdk::mint::{MintBuilder, MintMeltLimits};
+use cdk::nuts::{CurrencyUnit, MeltQuoteState, PaymentMethod};
+use cdk::types::FeeReserve;
+use cdk::wallet::Wallet;
+use cdk_fake_wallet::{FakeWallet, create_fake_invoice};
+use cdk_signatory::SignatoryRpcClient;
+use cdk_signatory::signatory::Signatory;
+
+/// How long the signatory may take to open the mock and start serving
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Signatory process, killed when the test ends
+struct SignatoryProcess {
+    child: Child,
+    addr: SocketAddr,
+}
+
+impl SignatoryProcess {
+    fn spawn() -> Self {
+        let addr = free_addr();
+        let child = Command::new(env!("CARGO_BIN_EXE_cdk-signatory-trezor"))
+            .args(["--transport", "mock", "--listen-addr", "127.0.0.1"])
+            .args(["--listen-port", &addr.port().to_string()])
+            .args(["--keyset-refresh-interval", "0"])
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start the signatory");
+        Self { child, addr }
+    }
+
+    /// Connect once the signatory serves its keysets
+    async fn connect(&self) -> SignatoryRpcClient {
+        let started = Instant::now();
+        loop {
+            let last_err =
+                match SignatoryRpcClient::new(format!("http://{}", self.addr), None).await {
+                    Ok(client) => match client.keysets().await {
+                        Ok(_) => return client,
+                        Err(err) => err.to_string(),
+                    },
+                    Err(err) => err.to_string(),
+                };
+            if started.elapsed() > STARTUP_TIMEOUT {
+                panic!("signatory did not come up: {}", last_err);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+}
+
+impl Drop for SignatoryProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port")
+}
+
+/// Serve a mint signing through `signatory` and return its URL
+async fn start_mint(signatory: SignatoryRpcClient) -> String {
+    let fake_wallet = FakeWallet::new(
+        FeeReserve {
+            min_fee_reserve: 1.into(),
+            percent_fee_reserve: 0.02,
+        },
+        HashMap::new(),
+        HashSet::new(),
+        0,
+        CurrencyUnit::Sat,
+    );
+
+    let localstore = Arc::new(cdk_sqlite::mint::memory::empty().await.unwrap());
+    let mut builder = MintBuilder::new(localstore);
+    builder
+        .add_payment_processor(
+            CurrencyUnit::Sat,
+            PaymentMethod::Bolt11,
+            MintMeltLimits::new(1, 100_000),
+            Arc::new(fake_wallet),
+        )
+        .await
+        .unwrap();
+    let mint = Arc::new(
+        builder
+            .build_with_signatory(Arc::new(signatory))
+            .await
+            .unwrap(),
+    );
+    mint.start().await.unwrap();
+
+    let router = cdk_axum::create_mint_router(mint, false).await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    format!("http://{}", addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mint_swap_melt_through_mock_device() {
+    let signatory = SignatoryProcess::spawn();
+    let client = signatory.connect().await;
+    let mint_url = start_mint(client).await;
+
+    let wallet = Wallet::new(
+        &mint_url,
+        CurrencyUnit::Sat,
+        Arc::new(cdk_sqlite::wallet::memory::empty().await.unwrap()),
+        Mnemonic::generate(12).unwrap().to_seed_normalized(""),
+        None,
+    )
+    .unwrap();
+
+    // mint: blind_sign on the device
+    let quote = wallet.mint_quote(Amount::from(100), None).await.unwrap();
+    wallet
+        .wait_for_payment(&quote, Duration::from_secs(10))
+        .await
+        .unwrap();
+    let proofs = wallet
+        .mint(&quote.id, SplitTarget::default(), None)
+        .await
+        .unwrap();
+    assert_eq!(proofs.len(), 3, "100 sats split into 64 + 32 + 4");
+    assert_eq!(wallet.total_balance().await.unwrap(), Amount::from(100));
+
+    // swap: verify_proofs and blind_sign
+    wallet
+        .swap(None, SplitTarget::None, proofs, None, false)
+        .await
+        .unwrap();
+    assert_eq!(wallet.total_balance().await.unwrap(), Amount::from(100));
+
+    // melt: verify_proofs, then change signed for the unused fee reserve
+    let invoice = create_fake_invoice(20_000, "end-to-end".to_string());
+    let melt_quote = wallet.melt_quote(invoice.to_string(), None).await.unwrap();
+    let melted = wallet.melt(&melt_quote.id).await.unwrap();
+    assert_eq!(melted.state, MeltQuoteState::Paid);
+    assert_eq!(
+        wallet.total_balance().await.unwrap(),
+        Amount::from(100) - melted.amount - melted.fee_paid
+    );
+}